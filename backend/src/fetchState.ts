@@ -6,9 +6,10 @@ import { Program } from '@coral-xyz/anchor';
 import type { Idl } from '@coral-xyz/anchor';
 
 const SLAB_HEADER_LEN = 32;
-const NODE_SIZE = 88;
+const NODE_SIZE = 96; // size_of::<AnyNode>() on-chain (88-byte free node padded to 16-byte alignment)
 const LEAF_NODE_TAG = 2;
-// LeafNode: tag(4) + fee_tier(1) + reserved(11) = 16, key(16), owner(32), quantity(8), timestamp(8)
+// LeafNode: tag(4) + fee_tier(1) + client_order_id(8) + reduce_only(1) + reserved(2) = 16, key(16), owner(32), quantity(8), timestamp(8), expires_at(8), padding(8)
+// key = price << 64 | time bits, for both bids and asks
 const LEAF_KEY_OFFSET = 16;
 const LEAF_QUANTITY_OFFSET = 64;
 
@@ -98,6 +99,7 @@ export type ApiMarket = {
   authority: string;
   lastOraclePrice: number;
   lastOracleTs: number;
+  markPrice: number;
   bid: string;
   asks: string;
   imBps: number;
@@ -110,6 +112,8 @@ export type ApiMarket = {
 export type ApiUserCollateral = {
   owner: string;
   collateralAmount: string;
+  reservedMargin: string;
+  openPositions: string[]; // markets margined by this collateral
   lastUpdated: number;
 };
 
@@ -163,6 +167,7 @@ export async function fetchMarkets(
           authority: decoded.authority?.toBase58() ?? '',
           lastOraclePrice: decoded.lastOraclePrice != null ? Number(decoded.lastOraclePrice) : 0,
           lastOracleTs: decoded.lastOracleTs != null ? Number(decoded.lastOracleTs) : 0,
+          markPrice: decoded.markPrice != null ? Number(decoded.markPrice) : 0,
           bid: decoded.bid?.toBase58() ?? '',
           asks: decoded.asks?.toBase58() ?? '',
           imBps: decoded.imBps ?? 0,
@@ -197,6 +202,8 @@ export async function fetchUser(
       collateral = {
         owner: decoded.owner?.toBase58() ?? '',
         collateralAmount: decoded.collateralAmount != null ? decoded.collateralAmount.toString() : '0',
+        reservedMargin: decoded.reservedMargin != null ? decoded.reservedMargin.toString() : '0',
+        openPositions: (decoded.openPositions ?? []).map((m: PublicKey) => m.toBase58()),
         lastUpdated: decoded.lastUpdated != null ? Number(decoded.lastUpdated) : 0,
       };
     } catch {}
@@ -234,9 +241,13 @@ export async function fetchUser(
 
 export async function getRequestQueueCount(
   connection: Connection,
-  programId: PublicKey
+  programId: PublicKey,
+  symbol: string
 ): Promise<number> {
-  const [pda] = PublicKey.findProgramAddressSync([Buffer.from('request_queue')], programId);
+  const [pda] = PublicKey.findProgramAddressSync(
+    [Buffer.from('request_queue'), Buffer.from(symbol)],
+    programId
+  );
   const info = await connection.getAccountInfo(pda);
   if (!info?.data || info.data.length < REQUEST_QUEUE_COUNT_OFFSET + 2) return 0;
   return info.data.readUInt16LE(REQUEST_QUEUE_COUNT_OFFSET);
@@ -244,9 +255,13 @@ export async function getRequestQueueCount(
 
 export async function getEventQueueCount(
   connection: Connection,
-  programId: PublicKey
+  programId: PublicKey,
+  symbol: string
 ): Promise<number> {
-  const [pda] = PublicKey.findProgramAddressSync([Buffer.from('event_queue')], programId);
+  const [pda] = PublicKey.findProgramAddressSync(
+    [Buffer.from('event_queue'), Buffer.from(symbol)],
+    programId
+  );
   const info = await connection.getAccountInfo(pda);
   if (!info?.data || info.data.length < EVENT_QUEUE_COUNT_OFFSET + 2) return 0;
   return info.data.readUInt16LE(EVENT_QUEUE_COUNT_OFFSET);
@@ -148,11 +148,14 @@ function stateKey(s: IndexerState): string {
 
 async function tick(): Promise<boolean> {
   try {
-    const [markets, requestQueueCount, eventQueueCount] = await Promise.all([
-      fetchMarkets(connection, program),
-      getRequestQueueCount(connection, PROGRAM_ID),
-      getEventQueueCount(connection, PROGRAM_ID),
+    const markets = await fetchMarkets(connection, program);
+    // queues are per market; report the totals across all markets
+    const [requestCounts, eventCounts] = await Promise.all([
+      Promise.all(markets.map((m) => getRequestQueueCount(connection, PROGRAM_ID, m.symbol))),
+      Promise.all(markets.map((m) => getEventQueueCount(connection, PROGRAM_ID, m.symbol))),
     ]);
+    const requestQueueCount = requestCounts.reduce((a, b) => a + b, 0);
+    const eventQueueCount = eventCounts.reduce((a, b) => a + b, 0);
     const orderBooks: Record<string, ApiOrderBook> = {};
     await Promise.all(
       markets.map(async (m) => {
@@ -1,8 +1,8 @@
 /**
- * Event-queue cranker: when event_queue.count > 0, peeks head user and calls position_manager(user) per market until one consumes the event.
+ * Event-queue cranker: for each market whose event_queue.count > 0, peeks the head user and calls position_manager(user) for that market.
  * Run: RPC_URL=... CRANKER_AUTHORITY_KEYPAIR=... [MARKET_SYMBOLS=SOL-PERP,BTC-PERP] node dist/event-cranker.js
  */
-import { SystemProgram, Transaction } from '@solana/web3.js';
+import { PublicKey, SystemProgram, Transaction } from '@solana/web3.js';
 import { TOKEN_PROGRAM_ID } from '@solana/spl-token';
 import {
   connection,
@@ -13,6 +13,8 @@ import {
   marketPda,
   positionPdaFromSymbol,
   userCollateralPda,
+  userStatsPda,
+  globalConfigPda,
   getAllMarkets,
   idl,
 } from './shared.js';
@@ -45,51 +47,37 @@ async function main() {
 
   for (;;) {
     try {
-      const count = await getEventQueueCount();
-      if (count === 0) {
-        await sleep(POLL_MS);
-        continue;
-      }
-      const eqInfo = await connection.getAccountInfo(eventQueuePda);
-      if (!eqInfo?.data) {
-        await sleep(POLL_MS);
-        continue;
-      }
-      const userAtHead = peekEventQueueHeadUser(eqInfo.data);
-      if (!userAtHead) {
-        await sleep(POLL_MS);
-        continue;
-      }
-      const userColletral = userCollateralPda(userAtHead);
-      let consumed = false;
       for (const { symbol } of markets) {
-        const market = marketPda(symbol);
-        const userPosition = positionPdaFromSymbol(symbol, userAtHead);
+        const count = await getEventQueueCount(symbol);
+        if (count === 0) continue;
+        const eventQueue = eventQueuePda(symbol);
+        const eqInfo = await connection.getAccountInfo(eventQueue);
+        if (!eqInfo?.data) continue;
+        const userAtHead = peekEventQueueHeadUser(eqInfo.data);
+        if (!userAtHead) continue;
         try {
+          // referred users' taker fees credit their referrer, whose stats must be passed
+          const stats = await (programWithWallet.account as any).userStats.fetch(userStatsPda(userAtHead));
+          const referrerStats = stats.referrer.equals(PublicKey.default) ? null : userStatsPda(stats.referrer);
           await programWithWallet.methods
             .positionManager(userAtHead)
             .accounts({
-              market,
-              userPosition,
-              eventQueue: eventQueuePda,
-              userColletral,
+              market: marketPda(symbol),
+              userPosition: positionPdaFromSymbol(symbol, userAtHead),
+              eventQueue,
+              userColletral: userCollateralPda(userAtHead),
+              globalConfig: globalConfigPda,
+              userStats: userStatsPda(userAtHead),
+              referrerStats,
               systemProgram: SystemProgram.programId,
               tokenProgram: TOKEN_PROGRAM_ID,
             } as any)
             .rpc();
           console.log(`Position manager applied for user ${userAtHead.toBase58().slice(0, 8)}... market ${symbol}`);
-          consumed = true;
-          break;
         } catch (e: any) {
-          if (e.message?.includes('EventNotForUser') || e.logs?.some((l: string) => l.includes('EventNotForUser'))) {
-            continue;
-          }
           console.error(`position_manager ${symbol}:`, e.message || e);
         }
       }
-      if (!consumed) {
-        await sleep(500);
-      }
     } catch (e: any) {
       console.error('Event crank loop error:', e.message || e);
     }
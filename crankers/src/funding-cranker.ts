@@ -0,0 +1,59 @@
+/**
+ * Funding cranker: for each market whose funding interval has elapsed, calls the
+ * permissionless update_funding, which settles every whole interval since the last update.
+ * Run: RPC_URL=... CRANKER_AUTHORITY_KEYPAIR=... [MARKET_SYMBOLS=SOL-PERP,BTC-PERP] node dist/funding-cranker.js
+ */
+import { Transaction } from '@solana/web3.js';
+import { connection, getAuthorityKeypair, marketPda, getAllMarkets, idl } from './shared.js';
+import { AnchorProvider, Program } from '@coral-xyz/anchor';
+
+const POLL_MS = Number(process.env.FUNDING_POLL_MS) || 60000;
+const MARKET_SYMBOLS_ENV = process.env.MARKET_SYMBOLS;
+
+async function main() {
+  const authority = getAuthorityKeypair();
+  const provider = new AnchorProvider(
+    connection,
+    { publicKey: authority.publicKey, signTransaction: async (tx: Transaction) => { tx.partialSign(authority); return tx; } } as any,
+    { commitment: 'confirmed' }
+  );
+  const programWithWallet = new Program(idl, provider);
+
+  let markets: { symbol: string }[];
+  if (MARKET_SYMBOLS_ENV) {
+    markets = MARKET_SYMBOLS_ENV.split(',').map((s) => ({ symbol: s.trim() }));
+  } else {
+    const list = await getAllMarkets();
+    markets = list.map((m) => ({ symbol: m.symbol }));
+  }
+
+  console.log('Funding cranker started. Markets:', markets.map((m) => m.symbol).join(', ') || '(none)');
+
+  for (;;) {
+    const now = Math.floor(Date.now() / 1000);
+    for (const { symbol } of markets) {
+      try {
+        const market = await (programWithWallet.account as any).marketState.fetch(marketPda(symbol));
+        const interval = Number(market.fundingIntervalSecs);
+        if (interval === 0 || now - Number(market.lastFundingTs) < interval) continue;
+        const sig = await programWithWallet.methods
+          .updateFunding()
+          .accounts({
+            market: marketPda(symbol),
+            oracle: market.oraclePubkey,
+          } as any)
+          .rpc();
+        console.log(`update_funding ${symbol}: ${sig}`);
+      } catch (e: any) {
+        console.error(`update_funding ${symbol}:`, e.message || e);
+      }
+    }
+    await sleep(POLL_MS);
+  }
+}
+
+function sleep(ms: number): Promise<void> {
+  return new Promise((r) => setTimeout(r, ms));
+}
+
+main();
@@ -1,9 +1,11 @@
 /**
- * Liquidator: fetches all positions with base_position != 0, computes health (collateral + unrealized_pnl - maintenance_margin).
- * If health < 0, calls liquidate instruction.
+ * Liquidator: fetches all positions with base_position != 0, computes the owner's cross-margin health
+ * (collateral + unrealized_pnl - maintenance_margin over every open position).
+ * If health < 0, calls liquidate instruction with the owner's other positions as remaining accounts.
+ * Isolated positions are judged on their own margin alone.
  * Run: RPC_URL=... LIQUIDATOR_KEYPAIR=... node dist/liquidator.js
  */
-import { Connection, PublicKey, SystemProgram, Transaction } from '@solana/web3.js';
+import { AccountMeta, Connection, PublicKey, SystemProgram, Transaction } from '@solana/web3.js';
 import { TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID, getAssociatedTokenAddressSync } from '@solana/spl-token';
 import {
   connection,
@@ -25,23 +27,27 @@ import { AnchorProvider, Program } from '@coral-xyz/anchor';
 const POLL_MS = Number(process.env.LIQUIDATOR_POLL_MS) || 5000;
 const POSITION_DISCRIMINATOR = Buffer.from([170, 188, 143, 228, 122, 64, 247, 208]);
 
-/** Mirror RiskEngine::account_health_single: collateral + unrealized_pnl - maintenance_margin */
-function accountHealthSingle(
-  collateral: bigint,
-  basePosition: number,
-  entryPrice: number,
-  markPrice: number,
-  mmBps: number
-): bigint {
+interface PositionRisk {
+  basePosition: number;
+  entryPrice: number;
+  markPrice: number;
+  mmBps: number;
+}
+
+/** One position's unrealized_pnl - maintenance_margin. */
+function positionHealth({ basePosition, entryPrice, markPrice, mmBps }: PositionRisk): bigint {
   const qty = BigInt(basePosition);
-  const entry = BigInt(entryPrice);
   const mark = BigInt(markPrice);
-  if (qty === BigInt(0)) return collateral;
-  const diff = mark - entry;
-  const unrealizedPnl = diff * qty;
+  if (qty === BigInt(0)) return BigInt(0);
+  const unrealizedPnl = (mark - BigInt(entryPrice)) * qty;
   const notional = (qty < BigInt(0) ? -qty : qty) * mark;
   const maintenanceMargin = (notional * BigInt(mmBps)) / BigInt(10_000);
-  return collateral + unrealizedPnl - maintenanceMargin;
+  return unrealizedPnl - maintenanceMargin;
+}
+
+/** Mirror RiskEngine::account_health_cross, without the unsettled funding the program also counts. */
+function accountHealthCross(collateral: bigint, positions: PositionRisk[]): bigint {
+  return positions.reduce((health, position) => health + positionHealth(position), collateral);
 }
 
 async function main() {
@@ -128,16 +134,43 @@ async function main() {
         const market = coder.accounts.decode('marketState', marketAcc.data);
         const markPrice = Number(market.lastOraclePrice ?? 0);
         if (markPrice <= 0) continue;
-        const mmBps = Number(market.mmBps ?? 500);
-        const entryPrice = Number(pos.entryPrice ?? 0);
-
-        const health = accountHealthSingle(
-          collateral,
-          basePosition,
-          entryPrice,
-          markPrice,
-          mmBps
-        );
+
+        // every other open position shares this collateral; the program wants them in index order.
+        // An isolated position stands alone on its own margin.
+        const risks: PositionRisk[] = [
+          { basePosition, entryPrice: Number(pos.entryPrice ?? 0), markPrice, mmBps: Number(market.mmBps ?? 500) },
+        ];
+        const remainingAccounts: AccountMeta[] = [];
+        const margin = pos.isIsolated ? BigInt(pos.isolatedMargin?.toString() ?? '0') : collateral;
+        const crossMarkets = pos.isIsolated ? [] : (userColl.openPositions as PublicKey[]);
+        let complete = true;
+        for (const otherPk of crossMarkets) {
+          if (otherPk.equals(marketPk)) continue;
+          const other = marketByPk.get(otherPk.toBase58());
+          const otherMarketAcc = await connection.getAccountInfo(otherPk);
+          const otherPositionPk = other && positionPdaFromSymbol(other.symbol, owner);
+          const otherPositionAcc = otherPositionPk && (await connection.getAccountInfo(otherPositionPk));
+          if (!otherPositionPk || !otherMarketAcc?.data || !otherPositionAcc?.data) {
+            complete = false;
+            break;
+          }
+          const otherMarket = coder.accounts.decode('marketState', otherMarketAcc.data);
+          const otherPosition = coder.accounts.decode('position', otherPositionAcc.data);
+          risks.push({
+            basePosition: Number(otherPosition.basePosition ?? 0),
+            entryPrice: Number(otherPosition.entryPrice ?? 0),
+            markPrice: Number(otherMarket.lastOraclePrice ?? 0),
+            mmBps: Number(otherMarket.mmBps ?? 500),
+          });
+          remainingAccounts.push(
+            { pubkey: otherPk, isSigner: false, isWritable: false },
+            { pubkey: otherMarket.oraclePubkey as PublicKey, isSigner: false, isWritable: false },
+            { pubkey: otherPositionPk, isSigner: false, isWritable: false }
+          );
+        }
+        if (!complete) continue;
+
+        const health = accountHealthCross(margin, risks);
         if (health >= BigInt(0)) continue;
 
         const liquidateePositionPk = positionPdaFromSymbol(symbol, owner);
@@ -154,9 +187,10 @@ async function main() {
               liquidator: liquidatorKp.publicKey,
               liquidatorTokenAccount: liquidatorAta,
               market: marketPdaKey,
+              oracle: market.oraclePubkey as PublicKey,
               bids,
               ask: asks,
-              eventQueue: eventQueuePda,
+              eventQueue: eventQueuePda(symbol),
               liquidateePosition: liquidateePositionPk,
               liquidateeUserCollateral: liquidateeCollateralPda,
               liquidateeTokenAccount,
@@ -168,6 +202,7 @@ async function main() {
               associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
               tokenProgram: TOKEN_PROGRAM_ID,
             } as any)
+            .remainingAccounts(remainingAccounts)
             .rpc();
           console.log(`Liquidated position for ${owner.toBase58().slice(0, 8)}... on ${symbol}`);
         } catch (e: any) {
@@ -1,8 +1,8 @@
 /**
- * Request-queue cranker: when request_queue.count > 0, calls process_place_order for each market.
+ * Request-queue cranker: for each market whose request_queue.count > 0, calls process_place_order.
  * Run: RPC_URL=... CRANKER_AUTHORITY_KEYPAIR=... [MARKET_SYMBOLS=SOL-PERP,BTC-PERP] node dist/request-cranker.js
  */
-import { Keypair, SystemProgram, Transaction } from '@solana/web3.js';
+import { Keypair, PublicKey, SystemProgram, Transaction } from '@solana/web3.js';
 import { TOKEN_PROGRAM_ID } from '@solana/spl-token';
 import {
   connection,
@@ -15,6 +15,9 @@ import {
   bidsPda,
   asksPda,
   getAllMarkets,
+  pendingPlaceUsers,
+  positionPdaFromSymbol,
+  reduceOnlyMakers,
   idl,
 } from './shared.js';
 import { AnchorProvider, Program } from '@coral-xyz/anchor';
@@ -46,38 +49,45 @@ async function main() {
 
   for (;;) {
     try {
-      const count = await getRequestQueueCount();
-      if (count === 0) {
-        await sleep(POLL_MS);
-        continue;
-      }
       for (const { symbol } of markets) {
+        const count = await getRequestQueueCount(symbol);
+        if (count === 0) continue;
         const market = marketPda(symbol);
+        const { oraclePubkey } = await (programWithWallet.account as any).marketState.fetch(market);
         const bids = bidsPda(symbol);
         const asks = asksPda(symbol);
+        const users = new Map<string, PublicKey>();
+        for (const user of [...(await pendingPlaceUsers(symbol)), ...(await reduceOnlyMakers(symbol))]) {
+          users.set(user.toBase58(), user);
+        }
+        const positions = Array.from(users.values()).map((user) => ({
+          pubkey: positionPdaFromSymbol(symbol, user),
+          isWritable: true,
+          isSigner: false,
+        }));
         try {
           await programWithWallet.methods
             .processPlaceOrder()
             .accounts({
               authority: authority.publicKey,
               market,
+              oracle: oraclePubkey,
               bids,
               asks,
-              requestQueue: requestQueuePda,
-              eventQueue: eventQueuePda,
+              requestQueue: requestQueuePda(symbol),
+              eventQueue: eventQueuePda(symbol),
               systemProgram: SystemProgram.programId,
               tokenProgram: TOKEN_PROGRAM_ID,
             } as any)
+            .remainingAccounts(positions)
             .rpc();
           console.log(`Processed request queue for market ${symbol}`);
         } catch (e: any) {
           if (e.message?.includes('QueueEmpty') || e.logs?.some((l: string) => l.includes('QueueEmpty'))) {
-            break;
+            continue;
           }
           console.error(`process_place_order ${symbol}:`, e.message || e);
         }
-        const remaining = await getRequestQueueCount();
-        if (remaining === 0) break;
       }
     } catch (e: any) {
       console.error('Crank loop error:', e.message || e);
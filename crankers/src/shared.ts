@@ -56,33 +56,42 @@ const QUEUE_HEAD_OFFSET = 8;
 const QUEUE_COUNT_OFFSET = 8 + 2 + 2; // 12
 const EVENT_QUEUE_SLOT_OFFSET = 8 + 2 + 2 + 2 + 2 + 8; // 24 (after sequence u64)
 const EVENT_SLOT_SIZE = 128 + 2 + 1 + 5; // data[128] + len(u16) + is_occupied(u8) + _pad[5] = 136
+const REQUEST_QUEUE_SLOT_OFFSET = 8 + 2 + 2 + 2 + 2 + 8; // 24 (after sequence u64)
+const REQUEST_SLOT_SIZE = EVENT_SLOT_SIZE;
+const MAX_REQUESTS_PER_CRANK = 10; // MAX_TO_PROCESS on-chain
+// RequestType (Borsh): tag(1, 0 = Place) + Order { user(32) + ... }
+const PLACE_REQUEST_TAG = 0;
 // MatchedOrder (Borsh): is_maker(1) + order_id(16) + user(32) + ...
 const MATCHED_ORDER_USER_OFFSET = 1 + 16; // 17
 const MATCHED_ORDER_USER_LEN = 32;
 
-export const requestQueuePda = PublicKey.findProgramAddressSync(
-  [Buffer.from('request_queue')],
-  PROGRAM_ID
-)[0];
+export function requestQueuePda(symbol: string): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from('request_queue'), Buffer.from(symbol)],
+    PROGRAM_ID
+  )[0];
+}
 
-export const eventQueuePda = PublicKey.findProgramAddressSync(
-  [Buffer.from('event_queue')],
-  PROGRAM_ID
-)[0];
+export function eventQueuePda(symbol: string): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from('event_queue'), Buffer.from(symbol)],
+    PROGRAM_ID
+  )[0];
+}
 
 export const globalConfigPda = PublicKey.findProgramAddressSync(
   [Buffer.from('global_config')],
   PROGRAM_ID
 )[0];
 
-export async function getRequestQueueCount(): Promise<number> {
-  const info = await connection.getAccountInfo(requestQueuePda);
+export async function getRequestQueueCount(symbol: string): Promise<number> {
+  const info = await connection.getAccountInfo(requestQueuePda(symbol));
   if (!info?.data || info.data.length < QUEUE_COUNT_OFFSET + 2) return 0;
   return info.data.readUInt16LE(QUEUE_COUNT_OFFSET);
 }
 
-export async function getEventQueueCount(): Promise<number> {
-  const info = await connection.getAccountInfo(eventQueuePda);
+export async function getEventQueueCount(symbol: string): Promise<number> {
+  const info = await connection.getAccountInfo(eventQueuePda(symbol));
   if (!info?.data || info.data.length < QUEUE_COUNT_OFFSET + 2) return 0;
   return info.data.readUInt16LE(QUEUE_COUNT_OFFSET);
 }
@@ -104,6 +113,54 @@ export function peekEventQueueHeadUser(data: Buffer): PublicKey | null {
   return new PublicKey(userBytes);
 }
 
+/**
+ * Users behind the Place requests the next process_place_order call will handle.
+ * Their position accounts are passed as remaining accounts so reduce-only orders can be capped.
+ */
+export async function pendingPlaceUsers(symbol: string): Promise<PublicKey[]> {
+  const info = await connection.getAccountInfo(requestQueuePda(symbol));
+  if (!info?.data || info.data.length < QUEUE_COUNT_OFFSET + 2) return [];
+  const data = info.data;
+  const head = data.readUInt16LE(QUEUE_HEAD_OFFSET);
+  const count = data.readUInt16LE(QUEUE_COUNT_OFFSET);
+  const capacity = data.readUInt16LE(QUEUE_COUNT_OFFSET + 2);
+  const users = new Map<string, PublicKey>();
+  for (let i = 0; i < Math.min(count, MAX_REQUESTS_PER_CRANK); i++) {
+    const slotOffset = REQUEST_QUEUE_SLOT_OFFSET + ((head + i) % capacity) * REQUEST_SLOT_SIZE;
+    if (data[slotOffset + 128 + 2] !== 1 || data[slotOffset] !== PLACE_REQUEST_TAG) continue;
+    const user = new PublicKey(data.subarray(slotOffset + 1, slotOffset + 33));
+    users.set(user.toBase58(), user);
+  }
+  return Array.from(users.values());
+}
+
+const SLAB_HEADER_LEN = 32;
+const SLAB_NODE_SIZE = 96;
+const LEAF_NODE_TAG = 2;
+const LEAF_REDUCE_ONLY_OFFSET = 13; // tag(4) + fee_tier(1) + client_order_id(8)
+const LEAF_OWNER_OFFSET = 32; // after key(16)
+
+/**
+ * Owners of reduce-only orders resting on either side of the book. They are capped at their
+ * position whenever they trade, so their position accounts are passed to process_place_order too.
+ */
+export async function reduceOnlyMakers(symbol: string): Promise<PublicKey[]> {
+  const infos = await connection.getMultipleAccountsInfo([bidsPda(symbol), asksPda(symbol)]);
+  const users = new Map<string, PublicKey>();
+  for (const info of infos) {
+    if (!info?.data) continue;
+    const slabStart = 8; // account discriminator
+    const nodeCount = Math.floor((info.data.length - slabStart - SLAB_HEADER_LEN) / SLAB_NODE_SIZE);
+    for (let i = 0; i < nodeCount; i++) {
+      const nodeBase = slabStart + SLAB_HEADER_LEN + i * SLAB_NODE_SIZE;
+      if (info.data.readUInt32LE(nodeBase) !== LEAF_NODE_TAG || info.data[nodeBase + LEAF_REDUCE_ONLY_OFFSET] !== 1) continue;
+      const owner = new PublicKey(info.data.subarray(nodeBase + LEAF_OWNER_OFFSET, nodeBase + LEAF_OWNER_OFFSET + 32));
+      users.set(owner.toBase58(), owner);
+    }
+  }
+  return Array.from(users.values());
+}
+
 export function marketPda(symbol: string): PublicKey {
   return PublicKey.findProgramAddressSync(
     [Buffer.from('market'), Buffer.from(symbol)],
@@ -111,6 +168,13 @@ export function marketPda(symbol: string): PublicKey {
   )[0];
 }
 
+export function triggerBookPda(symbol: string): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from('trigger_book'), Buffer.from(symbol)],
+    PROGRAM_ID
+  )[0];
+}
+
 export function bidsPda(symbol: string): PublicKey {
   return PublicKey.findProgramAddressSync(
     [Buffer.from('bids'), Buffer.from(symbol)],
@@ -139,6 +203,13 @@ export function userCollateralPda(userPk: PublicKey): PublicKey {
   )[0];
 }
 
+export function userStatsPda(userPk: PublicKey): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from('user_stats'), userPk.toBuffer()],
+    PROGRAM_ID
+  )[0];
+}
+
 const MARKET_DISCRIMINATOR = Buffer.from([0, 125, 123, 215, 95, 96, 164, 194]);
 
 export interface MarketInfo {
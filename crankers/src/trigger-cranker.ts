@@ -0,0 +1,103 @@
+/**
+ * Trigger cranker: for each market with pending trigger orders, calls trigger_orders so
+ * stops and take-profits the mark price has crossed are queued (and expired ones dropped).
+ * Every PRUNE_POLL_MS it also calls prune_expired to sweep expired resting orders off both books.
+ * Run: RPC_URL=... CRANKER_AUTHORITY_KEYPAIR=... [MARKET_SYMBOLS=SOL-PERP,BTC-PERP] node dist/trigger-cranker.js
+ */
+import { Transaction } from '@solana/web3.js';
+import {
+  connection,
+  requestQueuePda,
+  eventQueuePda,
+  getAuthorityKeypair,
+  marketPda,
+  bidsPda,
+  asksPda,
+  triggerBookPda,
+  getAllMarkets,
+  idl,
+} from './shared.js';
+import { AnchorProvider, Program } from '@coral-xyz/anchor';
+
+const POLL_MS = Number(process.env.CRANK_POLL_MS) || 2000;
+const PRUNE_POLL_MS = Number(process.env.PRUNE_POLL_MS) || 30000;
+const MARKET_SYMBOLS_ENV = process.env.MARKET_SYMBOLS;
+
+async function main() {
+  const authority = getAuthorityKeypair();
+  const provider = new AnchorProvider(
+    connection,
+    { publicKey: authority.publicKey, signTransaction: async (tx: Transaction) => { tx.partialSign(authority); return tx; } } as any,
+    { commitment: 'confirmed' }
+  );
+  const programWithWallet = new Program(idl, provider);
+
+  let markets: { symbol: string }[];
+  if (MARKET_SYMBOLS_ENV) {
+    markets = MARKET_SYMBOLS_ENV.split(',').map((s) => ({ symbol: s.trim() }));
+  } else {
+    const list = await getAllMarkets();
+    markets = list.map((m) => ({ symbol: m.symbol }));
+  }
+
+  console.log('Trigger cranker started. Markets:', markets.map((m) => m.symbol).join(', ') || '(none)');
+
+  let lastPrune = 0;
+  for (;;) {
+    try {
+      if (Date.now() - lastPrune >= PRUNE_POLL_MS) {
+        lastPrune = Date.now();
+        for (const { symbol } of markets) {
+          try {
+            await programWithWallet.methods
+              .pruneExpired()
+              .accounts({
+                market: marketPda(symbol),
+                bids: bidsPda(symbol),
+                asks: asksPda(symbol),
+                eventQueue: eventQueuePda(symbol),
+              } as any)
+              .rpc();
+          } catch (e: any) {
+            console.error(`prune_expired ${symbol}:`, e.message || e);
+          }
+        }
+      }
+      for (const { symbol } of markets) {
+        const triggerBook = triggerBookPda(symbol);
+        let pending = 0;
+        try {
+          const book = await (programWithWallet.account as any).triggerBook.fetch(triggerBook);
+          pending = book.orders.length;
+        } catch {
+          continue; // market has no trigger book yet
+        }
+        if (pending === 0) continue;
+        try {
+          const market = await (programWithWallet.account as any).marketState.fetch(marketPda(symbol));
+          await programWithWallet.methods
+            .triggerOrders()
+            .accounts({
+              market: marketPda(symbol),
+              oracle: market.oraclePubkey,
+              triggerBook,
+              requestQueue: requestQueuePda(symbol),
+              eventQueue: eventQueuePda(symbol),
+            } as any)
+            .rpc();
+        } catch (e: any) {
+          console.error(`trigger_orders ${symbol}:`, e.message || e);
+        }
+      }
+    } catch (e: any) {
+      console.error('Crank loop error:', e.message || e);
+    }
+    await sleep(POLL_MS);
+  }
+}
+
+function sleep(ms: number): Promise<void> {
+  return new Promise((r) => setTimeout(r, ms));
+}
+
+main();
@@ -10,6 +10,7 @@ import { getConnection, getProgram } from '../lib/solana/program';
 import {
   getMarketPda,
   getUserCollateralPda,
+  getUserStatsPda,
   getPositionPda,
   getRequestQueuePda,
   getGlobalConfigPda,
@@ -96,6 +97,7 @@ export type PlaceOrderParams = {
   initialMargin?: number;
   leverage?: number;
   orderId?: number;
+  reduceOnly?: boolean;
 };
 
 export function usePlaceOrder(): {
@@ -127,13 +129,30 @@ export function usePlaceOrder(): {
         initialMargin = 10,
         leverage = 10,
         orderId = Date.now() % 0xffffffff,
+        reduceOnly = false,
       } = params;
 
       const [marketPda] = getMarketPda(marketSymbol);
       const [userCollateralPda] = getUserCollateralPda(user);
+      const [userStatsPda] = getUserStatsPda(user);
       const [positionPda] = getPositionPda(marketSymbol, user);
-      const [requestQueuePda] = getRequestQueuePda();
+      const [requestQueuePda] = getRequestQueuePda(marketSymbol);
       const [globalConfigPda] = getGlobalConfigPda();
+      // margin is checked against a fresh read of the market's oracle account
+      const market = await (program.account as any).marketState.fetch(marketPda);
+      // positions in other markets share the collateral; the program checks them all
+      const collateral = await (program.account as any).userCollateral.fetchNullable(userCollateralPda);
+      const remainingAccounts = [];
+      for (const otherMarket of (collateral?.openPositions ?? []) as PublicKey[]) {
+        if (otherMarket.equals(marketPda)) continue;
+        const { symbol, oraclePubkey } = await (program.account as any).marketState.fetch(otherMarket);
+        const [otherPosition] = getPositionPda(symbol, user);
+        remainingAccounts.push(
+          { pubkey: otherMarket, isSigner: false, isWritable: false },
+          { pubkey: oraclePubkey, isSigner: false, isWritable: false },
+          { pubkey: otherPosition, isSigner: false, isWritable: false }
+        );
+      }
 
       const order = {
         user: Array.from(user.toBytes()),
@@ -145,6 +164,11 @@ export function usePlaceOrder(): {
         initialMargin: new BN(initialMargin),
         leverage,
         market: marketPda,
+        reduceOnly,
+        selfTrade: { cancelTaker: {} },
+        clientOrderId: new BN(0),
+        expiresAt: new BN(0),
+        feeTier: 0, // stamped on-chain from the user's 30-day volume
       };
 
       const tx = await program.methods
@@ -153,13 +177,16 @@ export function usePlaceOrder(): {
           user,
           globalConfig: globalConfigPda,
           market: marketPda,
+          oracle: market.oraclePubkey,
           userColletral: userCollateralPda,
+          userStats: userStatsPda,
           positionPerMarket: positionPda,
           requestQueue: requestQueuePda,
           systemProgram: new PublicKey('11111111111111111111111111111111'),
           associatedTokenProgram: new PublicKey('ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL'),
           tokenProgram: new PublicKey('TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA'),
         } as any)
+        .remainingAccounts(remainingAccounts)
         .transaction();
 
       const signed = await signTransaction(tx);
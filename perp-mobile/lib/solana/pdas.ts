@@ -12,16 +12,16 @@ export function getGlobalConfigPda(): [PublicKey, number] {
   );
 }
 
-export function getRequestQueuePda(): [PublicKey, number] {
+export function getRequestQueuePda(marketSymbol: string): [PublicKey, number] {
   return PublicKey.findProgramAddressSync(
-    [Buffer.from('request_queue')],
+    [Buffer.from('request_queue'), Buffer.from(marketSymbol)],
     PROGRAM_ID
   );
 }
 
-export function getEventQueuePda(): [PublicKey, number] {
+export function getEventQueuePda(marketSymbol: string): [PublicKey, number] {
   return PublicKey.findProgramAddressSync(
-    [Buffer.from('event_queue')],
+    [Buffer.from('event_queue'), Buffer.from(marketSymbol)],
     PROGRAM_ID
   );
 }
@@ -54,6 +54,13 @@ export function getUserCollateralPda(user: PublicKey): [PublicKey, number] {
   );
 }
 
+export function getUserStatsPda(user: PublicKey): [PublicKey, number] {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from('user_stats'), user.toBuffer()],
+    PROGRAM_ID
+  );
+}
+
 export function getPositionPda(marketSymbol: string, user: PublicKey): [PublicKey, number] {
   return PublicKey.findProgramAddressSync(
     [Buffer.from('position'), Buffer.from(marketSymbol), user.toBuffer()],
@@ -4,7 +4,7 @@ use anchor_lang::prelude::*;
 pub const SEED: &str = "anchor";
 
 pub const SLAB_HEADER_LEN: usize = 32; // Fixed header size
-pub const NODE_SIZE: usize = 88; // 88 bytes per node (8 + 80)
+pub const NODE_SIZE: usize = 96; // size_of::<AnyNode>(): 88-byte free node padded to 16-byte alignment
 
 // Node type tags
 pub const UNINITIALIZED: u32 = 0;
@@ -20,3 +20,28 @@ pub const BID_SLAB_CAPACITY: usize = 100;
 pub const ASK_SLAB_CAPACITY: usize = 100;
 
 pub const MAX_TO_PROCESS:u16 = 10;
+
+pub const MAX_OPEN_ORDERS: usize = 32; // open orders per user that can hold a margin reservation
+pub const MAX_OPEN_POSITIONS: usize = 8; // markets a user can hold positions in at once; bounds the cross-margin accounts
+pub const MAX_CANCEL_PER_CALL: usize = 32; // leaves removed by one cancel_all_orders call
+pub const MAX_PRUNE_PER_CALL: usize = 32; // expired leaves removed by one prune_expired call
+
+pub const PRICE_DECIMALS: i32 = 6; // prices are quote atoms (USDC, 6 decimals) per base unit
+pub const ORACLE_MAX_STALENESS_SECS: i64 = 60; // oldest oracle publish time accepted
+pub const ORACLE_MAX_CONF_BPS: u16 = 200; // widest confidence interval accepted, relative to the price
+pub const MARK_CLAMP_BPS: u64 = 100; // furthest the mark price may sit from the oracle
+pub const FILL_EMA_ALPHA_BPS: u64 = 1_000; // weight of each placement's fill price in the fill EMA
+
+// bounds on the per-market funding parameters
+pub const MIN_FUNDING_INTERVAL_SECS: u32 = 60;
+pub const MAX_FUNDING_PERIOD_SECS: u32 = 7 * 24 * 3600;
+pub const MAX_INTEREST_RATE_BPS: u16 = 100; // per funding period
+pub const MAX_PREMIUM_DAMPENER_BPS: u16 = 100;
+pub const MAX_FUNDING_RATE_BPS: i64 = 1_000; // clamp per funding interval
+
+pub const MAX_FEE_TIERS: usize = 8;
+pub const VOLUME_WINDOW_DAYS: usize = 30; // trailing window fee tiers are keyed on
+pub const SECONDS_PER_DAY: i64 = 86_400;
+
+pub const MAX_TRIGGER_ORDERS: usize = 64; // pending trigger orders per market
+pub const MAX_TRIGGERS_PER_USER: usize = 8;
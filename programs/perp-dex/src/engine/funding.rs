@@ -1,20 +1,31 @@
 
 use anchor_lang::prelude::*;
 
-use crate::{MarketState, PerpError};
+use crate::{FundingParams, MarketState, PerpError};
 
-pub const INTEREST_RATE_BPS: i128 = 333;
 pub const FUNDING_SCALE: i128 = 1_000_000_000; // 1e9
-pub const STANDARD_FUNDING_PERIOD: i64 = 8 * 3600;
 
 
-pub fn compute_funding_rate (
+/// Premium and interest parts of one interval's funding rate, already scaled from
+/// the funding period to the interval, in `FUNDING_SCALE` units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FundingComponents {
+    pub premium: i128,
+    pub interest: i128,
+}
+
+impl FundingComponents {
+    pub fn rate(&self) -> Result<i128> {
+        Ok(self.premium.checked_add(self.interest).ok_or(PerpError::MathOverflow)?)
+    }
+}
+
+/// Mark over oracle as a fraction of the oracle, in `FUNDING_SCALE` units.
+pub fn premium_index(
     mark_price : u128,
-    oracal_price: u128,
-    funding_interval : i64
+    oracal_price: u128
 )->Result<i128>{
     require!(oracal_price>0,PerpError::InvalidOraclePrice);
-    require!(funding_interval>0,PerpError::MathOverflow);
 
     let mark_i = mark_price as i128;
     let oracle_i = oracal_price as i128;
@@ -22,29 +33,65 @@ pub fn compute_funding_rate (
         .checked_sub(oracle_i)
         .ok_or(PerpError::MathOverflow)?;
 
-    let premium = price_diff
+    Ok(price_diff
         .checked_mul(FUNDING_SCALE)
         .and_then(|v|v.checked_div(oracle_i))
-        .ok_or(PerpError::MathOverflow)?;
+        .ok_or(PerpError::MathOverflow)?)
+}
+
+/// `premium` is a premium index over `funding_period_secs`, normally the window's TWAP.
+/// The dampener drops the part of the premium within `premium_dampener_bps` of zero, and
+/// both parts are scaled from the funding period down to the funding interval.
+pub fn compute_funding_components(
+    premium : i128,
+    params: &FundingParams
+)->Result<FundingComponents>{
+    require!(params.funding_interval_secs>0 && params.funding_period_secs>0,PerpError::InvalidMarketConfig);
 
-    let intrest = (INTEREST_RATE_BPS)
+    let bps = |value: u16| (value as i128)
         .checked_mul(FUNDING_SCALE)
         .and_then(|v|v.checked_div(10000))
-        .ok_or(PerpError::MathOverflow)?;
+        .ok_or(PerpError::MathOverflow);
+
+    let dampener = bps(params.premium_dampener_bps)?;
+    let dampened_premium = premium - premium.clamp(-dampener, dampener);
+
+    let intrest = bps(params.interest_rate_bps)?;
         //why intrest => if mark==oracle
         //prem =0 and fund = 0,
         //this cause ,funding system inactive
 
-    let funding_rate = premium
-        .checked_add(intrest)
-        .ok_or(PerpError::MathOverflow)?;
+    let time_scale = |rate: i128| {
+        rate.checked_mul(params.funding_interval_secs as i128)
+            .and_then(|v|v.checked_div(params.funding_period_secs as i128))
+            .ok_or(PerpError::MathOverflow)
+    };
+    Ok(FundingComponents {
+        premium: time_scale(dampened_premium)?,
+        interest: time_scale(intrest)?,
+    })
+}
 
-    let time_scaled_rate = funding_rate
-        .checked_mul(funding_interval as i128)
-        .and_then(|v|v.checked_div(STANDARD_FUNDING_PERIOD as i128))
-        .ok_or(PerpError::MathOverflow)?;
-    Ok(time_scaled_rate)
+/// Funding rate for a premium held at `mark_price - oracal_price` over the whole interval.
+pub fn compute_funding_rate (
+    mark_price : u128,
+    oracal_price: u128,
+    params: &FundingParams
+)->Result<i128>{
+    compute_funding_components(premium_index(mark_price, oracal_price)?, params)?.rate()
+}
 
+/// Funding owed by `base_position` for a move of `delta_funding` in `cum_funding`, in
+/// quote units, rounded toward zero. Positive means the position pays. The product is
+/// taken in i128 so no position size or funding delta that fits the stored types overflows.
+pub fn funding_payment(
+    delta_funding: i64,
+    base_position: i64
+)->Result<i128>{
+    Ok((delta_funding as i128)
+        .checked_mul(base_position as i128)
+        .and_then(|v| v.checked_div(FUNDING_SCALE))
+        .ok_or(PerpError::MathOverflow)?)
 }
 
 pub fn clamp_funding_rate(  
@@ -59,14 +106,31 @@ pub fn clamp_funding_rate(
     Ok(funding_rate.clamp(-max_cap, max_cap))
 }
 
-pub fn update_funding(
-    market: &mut Account<MarketState>,
-    mark_price : u128,
-    oracal_price: u128,
+/// Result of one `accrue_funding` call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FundingUpdate {
+    pub premium_twap: i128,
+    pub components: FundingComponents,
+    pub rate: i128,       // per interval, after the clamp
+    pub intervals: i64,   // intervals settled by this call
+    pub cum_funding: i64,
+    pub last_funding_ts: i64,
+}
+
+/// Settle every whole interval since `last_funding_ts` at the premium TWAP of the
+/// window since the last update. Missed intervals are charged at that rate, each
+/// clamped on its own, and `last_funding_ts` advances by whole intervals so the
+/// schedule does not drift with crank latency.
+///
+/// The caller refreshes the oracle first, which also takes the closing premium sample
+/// and rejects a stale or wide-confidence price.
+pub fn accrue_funding(
+    market: &mut MarketState,
     current_ts : i64
-)->Result<()>{
+)->Result<FundingUpdate>{
     require!(current_ts >= market.last_funding_ts, PerpError::InvalidTimestamp);
-    let interval = market.funding_interval_secs as i64;
+    let params = market.funding_params();
+    let interval = params.funding_interval_secs as i64;
     require!(interval > 0, PerpError::InvalidMarketConfig);
 
     let elapsed = current_ts
@@ -74,23 +138,214 @@ pub fn update_funding(
         .ok_or(PerpError::MathOverflow)?;
 
     require!(elapsed >= interval, PerpError::FundingNotDue);
+    let intervals = elapsed / interval;
 
-    let funding_rate = compute_funding_rate(
-        mark_price,
-        oracal_price,
-        interval
-    )?;
+    let premium_twap = market.premium_twap(current_ts)?;
+    let components = compute_funding_components(premium_twap, &params)?;
 
     let capped_rate = clamp_funding_rate(
-        funding_rate,
-        market.max_funding_rate as i128
+        components.rate()?,
+        params.max_funding_rate as i128
     )?;
 
+    let delta = capped_rate
+        .checked_mul(intervals as i128)
+        .and_then(|v| i64::try_from(v).ok())
+        .ok_or(PerpError::MathOverflow)?;
     market.cum_funding = market.cum_funding
-        .checked_add(
-            capped_rate as i64
-        )
+        .checked_add(delta)
         .ok_or(PerpError::MathOverflow)?;
-    market.last_funding_ts = current_ts;
-    Ok(())
-}
\ No newline at end of file
+    market.last_funding_ts = market.last_funding_ts
+        .checked_add(intervals * interval)
+        .ok_or(PerpError::MathOverflow)?;
+    market.reset_premium_window(current_ts);
+
+    Ok(FundingUpdate {
+        premium_twap,
+        components,
+        rate: capped_rate,
+        intervals,
+        cum_funding: market.cum_funding,
+        last_funding_ts: market.last_funding_ts,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MAX_FUNDING_PERIOD_SECS, MAX_FUNDING_RATE_BPS, MAX_INTEREST_RATE_BPS, MAX_PREMIUM_DAMPENER_BPS,
+        MIN_FUNDING_INTERVAL_SECS,
+    };
+
+    fn market(interval: u32, max_funding_rate: i64) -> MarketState {
+        MarketState {
+            last_oracle_price: 100_000_000,
+            mark_price: 100_000_000,
+            oracle_band_bps: 0,
+            max_funding_rate,
+            funding_interval_secs: interval,
+            interest_rate_bps: 3,
+            min_order_notional: 0,
+            ..MarketState::test_default()
+        }
+    }
+
+    /// Market whose funding window opened at `start` with `premium` held since.
+    fn holding(market: &mut MarketState, start: i64, premium: i128) {
+        market.last_funding_ts = start;
+        market.premium_window_start = start;
+        market.premium_sample_ts = start;
+        market.last_premium = premium as i64;
+    }
+
+    /// Moves the mark by quoting a book mid; the oracle stays at 100.
+    fn sample_at(market: &mut MarketState, now: i64, book_mid: u64) {
+        market.book_mid_price = book_mid;
+        market.sample_premium(now).unwrap();
+    }
+
+    /// Second-by-second integral of a step function: each premium holds from its
+    /// sample until the next one.
+    fn reference_twap(initial: i128, samples: &[(i64, i128)], start: i64, end: i64) -> i128 {
+        let mut total = 0i128;
+        for t in start..end {
+            let premium = samples.iter().rev().find(|(ts, _)| *ts <= t).map_or(initial, |(_, p)| *p);
+            total += premium;
+        }
+        total / (end - start) as i128
+    }
+
+    #[test]
+    fn test_components_scale_to_interval() {
+        // mark 1% over the oracle for a full funding period
+        let mut params = market(8 * 3600, 1_000).funding_params();
+        let premium = premium_index(101_000_000, 100_000_000).unwrap();
+        assert_eq!(premium, FUNDING_SCALE / 100);
+        let components = compute_funding_components(premium, &params).unwrap();
+        assert_eq!(components.premium, FUNDING_SCALE / 100);
+        assert_eq!(components.interest, 3 * FUNDING_SCALE / 10_000);
+
+        params.funding_interval_secs = 3600;
+        let hourly = compute_funding_components(premium, &params).unwrap();
+        assert_eq!(hourly.premium, components.premium / 8);
+        assert_eq!(hourly.rate().unwrap(), compute_funding_rate(101_000_000, 100_000_000, &params).unwrap());
+    }
+
+    #[test]
+    fn test_dampener_drops_premium_near_zero() {
+        let mut params = market(8 * 3600, 1_000).funding_params();
+        params.premium_dampener_bps = 5;
+        let bp = FUNDING_SCALE / 10_000;
+
+        let inside = compute_funding_components(4 * bp, &params).unwrap();
+        assert_eq!((inside.premium, inside.interest), (0, 3 * bp));
+        assert_eq!(compute_funding_components(-5 * bp, &params).unwrap().premium, 0);
+        assert_eq!(compute_funding_components(12 * bp, &params).unwrap().premium, 7 * bp);
+        assert_eq!(compute_funding_components(-12 * bp, &params).unwrap().premium, -7 * bp);
+    }
+
+    #[test]
+    fn test_funding_params_bounds() {
+        let valid = market(3600, 100).funding_params();
+        assert!(valid.validate().is_ok());
+
+        let invalid = [
+            FundingParams { funding_interval_secs: MIN_FUNDING_INTERVAL_SECS - 1, ..valid },
+            FundingParams { funding_interval_secs: valid.funding_period_secs + 1, ..valid },
+            FundingParams { funding_period_secs: MAX_FUNDING_PERIOD_SECS + 1, funding_interval_secs: 3600, ..valid },
+            FundingParams { interest_rate_bps: MAX_INTEREST_RATE_BPS + 1, ..valid },
+            FundingParams { premium_dampener_bps: MAX_PREMIUM_DAMPENER_BPS + 1, ..valid },
+            FundingParams { max_funding_rate: 0, ..valid },
+            FundingParams { max_funding_rate: MAX_FUNDING_RATE_BPS + 1, ..valid },
+        ];
+        for params in invalid {
+            let mut market = market(3600, 100);
+            assert_eq!(market.set_funding_params(params).unwrap_err(), error!(PerpError::InvalidFundingParams));
+            assert_eq!(market.funding_params(), valid);
+        }
+    }
+
+    #[test]
+    fn test_accrue_rejects_before_interval() {
+        let mut market = market(3600, 1_000);
+        holding(&mut market, 1_000, 0);
+        assert_eq!(accrue_funding(&mut market, 1_000 + 3599).unwrap_err(), error!(PerpError::FundingNotDue));
+        assert_eq!(accrue_funding(&mut market, 999).unwrap_err(), error!(PerpError::InvalidTimestamp));
+    }
+
+    #[test]
+    fn test_accrue_catches_up_missed_intervals() {
+        let mut market = market(3600, 1_000);
+        let premium = premium_index(101_000_000, 100_000_000).unwrap();
+        holding(&mut market, 1_000, premium);
+        let rate = compute_funding_rate(101_000_000, 100_000_000, &market.funding_params()).unwrap();
+
+        // three and a half intervals late: three are charged, the half carries over
+        let update = accrue_funding(&mut market, 1_000 + 3 * 3600 + 1800).unwrap();
+        assert_eq!((update.intervals, update.rate, update.premium_twap), (3, rate, premium));
+        assert_eq!(market.cum_funding as i128, 3 * rate);
+        assert_eq!(market.last_funding_ts, 1_000 + 3 * 3600);
+        assert_eq!((market.premium_window_start, market.premium_accumulator), (1_000 + 3 * 3600 + 1800, 0));
+
+        let update = accrue_funding(&mut market, 1_000 + 4 * 3600).unwrap();
+        assert_eq!(update.intervals, 1);
+        assert_eq!(market.cum_funding as i128, 4 * rate);
+    }
+
+    #[test]
+    fn test_accrue_clamps_each_interval() {
+        let mut market = market(3600, 1); // 1 bp per interval
+        let cap = FUNDING_SCALE / 10_000;
+        holding(&mut market, 0, premium_index(150_000_000, 100_000_000).unwrap());
+        let update = accrue_funding(&mut market, 2 * 3600).unwrap();
+        assert_eq!(update.rate, cap);
+        assert_eq!(market.cum_funding as i128, 2 * cap);
+
+        market.last_premium = premium_index(50_000_000, 100_000_000).unwrap() as i64;
+        let update = accrue_funding(&mut market, 3 * 3600).unwrap();
+        assert_eq!(update.rate, -cap);
+        assert_eq!(market.cum_funding as i128, cap);
+    }
+
+    #[test]
+    fn test_premium_accumulator_matches_reference() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = |bound: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % bound
+        };
+
+        for _ in 0..50 {
+            let mut market = market(3600, 1_000);
+            let start = 1_000 + next(1_000) as i64;
+            let initial = next(2 * FUNDING_SCALE as u64 / 100) as i128 - FUNDING_SCALE / 100;
+            holding(&mut market, start, initial);
+
+            let mut samples = Vec::new();
+            let mut now = start;
+            for _ in 0..next(20) {
+                now += next(600) as i64; // several samples may share a timestamp
+                sample_at(&mut market, now, 98_000_000 + next(4_000_000));
+                samples.push((now, market.last_premium as i128));
+            }
+            let end = now + 1 + next(600) as i64;
+            assert_eq!(market.premium_twap(end).unwrap(), reference_twap(initial, &samples, start, end));
+        }
+    }
+
+    #[test]
+    fn test_spike_before_crank_barely_moves_twap() {
+        let mut market = market(3600, 1_000);
+        holding(&mut market, 0, 0);
+        sample_at(&mut market, 0, 100_000_000);
+        // mark pushed to the clamp one second before the crank
+        sample_at(&mut market, 3599, 150_000_000);
+        let spike = market.last_premium as i128;
+        assert_eq!(spike, premium_index(101_000_000, 100_000_000).unwrap());
+        assert_eq!(market.premium_twap(3600).unwrap(), spike / 3600);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{FILL_EMA_ALPHA_BPS, MARK_CLAMP_BPS};
+
+/// Mid of the best bid and ask, or `None` while either side of the book is empty.
+pub fn book_mid(best_bid: Option<u64>, best_ask: Option<u64>) -> Option<u64> {
+    let (bid, ask) = (best_bid?, best_ask?);
+    Some(((bid as u128 + ask as u128) / 2) as u64)
+}
+
+/// Fold a fill price into the EMA; the first fill seeds it.
+pub fn update_fill_ema(ema: u64, fill_price: u64) -> u64 {
+    if ema == 0 {
+        return fill_price;
+    }
+    let weighted = ema as u128 * (10_000 - FILL_EMA_ALPHA_BPS) as u128 + fill_price as u128 * FILL_EMA_ALPHA_BPS as u128;
+    (weighted / 10_000) as u64
+}
+
+/// Median of the oracle, the book mid and the fill EMA (the mean when only two are
+/// known), clamped to `MARK_CLAMP_BPS` around the oracle so that neither the book nor
+/// a burst of fills can move it far from the oracle.
+pub fn compute_mark_price(oracle: u64, book_mid: Option<u64>, fill_ema: Option<u64>) -> u64 {
+    let mut prices = vec![oracle];
+    prices.extend(book_mid);
+    prices.extend(fill_ema);
+    prices.sort_unstable();
+    let median = match prices.len() {
+        2 => ((prices[0] as u128 + prices[1] as u128) / 2) as u64,
+        n => prices[n / 2],
+    };
+
+    let width = (oracle as u128 * MARK_CLAMP_BPS as u128 / 10_000) as u64;
+    median.clamp(oracle.saturating_sub(width), oracle.saturating_add(width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_book_mid_needs_both_sides() {
+        assert_eq!(book_mid(Some(99), Some(102)), Some(100));
+        assert_eq!(book_mid(Some(99), None), None);
+        assert_eq!(book_mid(None, Some(102)), None);
+        assert_eq!(book_mid(Some(u64::MAX), Some(u64::MAX)), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_fill_ema_seeds_then_moves_by_alpha() {
+        assert_eq!(update_fill_ema(0, 1_000), 1_000);
+        assert_eq!(update_fill_ema(1_000, 2_000), 1_000 + 1_000 * FILL_EMA_ALPHA_BPS / 10_000);
+    }
+
+    #[test]
+    fn test_mark_is_median_within_clamp() {
+        let oracle = 100_000;
+        assert_eq!(compute_mark_price(oracle, None, None), oracle);
+        assert_eq!(compute_mark_price(oracle, Some(100_400), None), 100_200);
+        assert_eq!(compute_mark_price(oracle, Some(100_300), Some(99_900)), 100_000);
+        assert_eq!(compute_mark_price(oracle, Some(100_300), Some(100_500)), 100_300);
+    }
+
+    #[test]
+    fn test_mark_cannot_be_pushed_past_the_clamp() {
+        let oracle = 100_000;
+        let width = oracle * MARK_CLAMP_BPS / 10_000;
+        // a thin book and fills far above the oracle only move the mark to the clamp
+        assert_eq!(compute_mark_price(oracle, Some(150_000), Some(140_000)), oracle + width);
+        assert_eq!(compute_mark_price(oracle, Some(1), Some(2)), oracle - width);
+    }
+}
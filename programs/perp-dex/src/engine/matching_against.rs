@@ -1,15 +1,96 @@
 use anchor_lang::prelude::*;
-use crate::{EventQueue, INNER_NODE, LEAF_NODE, MatchedOrder, MatchingType, Order, OrderType, PerpError, Side, Slab};
+use crate::{CancelByClientId, CancelOrder, EventKind, EventQueue, INNER_NODE, LEAF_NODE, LeafNode, MatchedOrder, MatchingType, Order, OrderType, PerpError, Position, PriceBand, SelfTradeMode, Side, Slab};
+
+/// A resting order of the taker's own owner that was kept from trading.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfTrade {
+    pub maker_order_id: u128,
+    pub prevented_qty: u64,
+}
+
+pub struct MatchResult {
+    pub remaining_qty: u64,            // neither filled nor removed by self-trade prevention
+    pub filled_qty: u64,
+    pub filled_notional: u128,         // sum of fill price * fill qty
+    pub taker_fills: Vec<MatchedOrder>, // only collected for liquidation matches
+    pub self_trades: Vec<SelfTrade>,
+    pub taker_cancelled: bool,         // CancelTaker stopped the match; the remainder must not rest
+    pub expired_makers: Vec<LeafNode>, // removed without trading, as they were when found
+    pub reduce_only_pulled: Vec<LeafNode>, // reduce-only makers with nothing left to reduce, as found
+    pub outside_band: Vec<LeafNode>, // makers pulled for a price better than the band, as found
+}
+
+/// How much reduce-only quantity each owner can still trade, read from their positions
+/// net of fills the position crank has not applied yet. Reduce-only orders of an owner
+/// who is not listed have nothing to reduce.
+#[derive(Clone, Debug, Default)]
+pub struct ReduceOnlyCaps {
+    caps: Vec<ReduceOnlyCap>,
+}
+
+#[derive(Clone, Debug)]
+struct ReduceOnlyCap {
+    owner: [u8; 32],
+    side: Side,    // the side that shrinks the position
+    available: u64,
+    matched: u64,  // traded so far, to be added to the position's pending qty
+}
+
+impl ReduceOnlyCaps {
+    pub fn from_positions<'a>(positions: impl IntoIterator<Item = &'a Position>) -> Self {
+        let caps = positions
+            .into_iter()
+            .map(|position| {
+                let side = if position.base_position > 0 { Side::Sell } else { Side::Buy };
+                ReduceOnlyCap {
+                    owner: position.owner.to_bytes(),
+                    side,
+                    available: position.reducible_qty(side),
+                    matched: 0,
+                }
+            })
+            .collect();
+        Self { caps }
+    }
+
+    /// Quantity `owner` can still trade on `side` without growing or flipping the position.
+    pub fn available(&self, owner: &[u8; 32], side: Side) -> u64 {
+        self.caps
+            .iter()
+            .find(|cap| cap.owner == *owner && cap.side == side)
+            .map_or(0, |cap| cap.available)
+    }
+
+    /// Count `qty` traded by a reduce-only order of `owner` on `side`.
+    pub fn consume(&mut self, owner: &[u8; 32], side: Side, qty: u64) {
+        if let Some(cap) = self.caps.iter_mut().find(|cap| cap.owner == *owner && cap.side == side) {
+            cap.available = cap.available.saturating_sub(qty);
+            cap.matched += qty;
+        }
+    }
+
+    /// Reduce-only quantity of `owner` traded since the caps were read.
+    pub fn matched(&self, owner: &[u8; 32]) -> u64 {
+        self.caps.iter().filter(|cap| cap.owner == *owner).map(|cap| cap.matched).sum()
+    }
+}
 
 /// Core matching logic: takes a mutable queue and timestamp for testability.
 /// Use `match_against_book` from on-chain code to pass `AccountLoader` and `Clock`.
+/// The loop stops at the first maker priced past the band edge the taker trades towards; a
+/// market order's worst price is that edge. Makers priced past the other edge, which the
+/// taker would cross, are pulled rather than filled or left crossing the book.
+/// Reduce-only fills, of the taker or a maker, are counted against `caps`; a reduce-only
+/// maker whose owner has nothing left to reduce is pulled from the book instead.
 pub fn match_against_book_core(
     book: &mut Slab,
     order: &Order,
     event_queue: &mut EventQueue,
+    caps: &mut ReduceOnlyCaps,
     match_type: MatchingType,
+    band: PriceBand,
     now_secs: i64,
-) -> Result<(u64, Vec<MatchedOrder>)> {
+) -> Result<MatchResult> {
     msg!(
         "MATCH: START side={:?} qty={} order_id={} limit_price={}",
         order.side,
@@ -26,7 +107,18 @@ pub fn match_against_book_core(
     );
 
     let mut remaining_qty = order.qty;
+    let mut filled_qty = 0u64;
+    let mut filled_notional = 0u128;
     let mut taker_fills: Vec<MatchedOrder> = Vec::new();
+    let mut self_trades: Vec<SelfTrade> = Vec::new();
+    let mut taker_cancelled = false;
+    let mut expired_makers: Vec<LeafNode> = Vec::new();
+    let mut reduce_only_pulled: Vec<LeafNode> = Vec::new();
+    let mut outside_band: Vec<LeafNode> = Vec::new();
+    let maker_side = match order.side {
+        Side::Buy => Side::Sell,
+        Side::Sell => Side::Buy,
+    };
 
     while remaining_qty > 0 {
         msg!(
@@ -67,6 +159,15 @@ pub fn match_against_book_core(
         let best_price = best_leaf.price();
         let available_qty = best_leaf.quantity;
 
+        if best_leaf.is_expired(now_secs) {
+            let maker = *best_leaf;
+            msg!("MATCH LOOP: maker key={} expired at {}, removing", maker.key, maker.expires_at);
+            book.remove_leaf(idx)?;
+            event_queue.push(&maker_out(&maker, maker_side, maker.quantity, now_secs))?;
+            expired_makers.push(maker);
+            continue;
+        }
+
         msg!(
             "MATCH LOOP: best_leaf idx={} price={} avail_qty={} key={}",
             idx,
@@ -75,33 +176,27 @@ pub fn match_against_book_core(
             best_leaf.key
         );
 
+        if band.is_adverse(order.side, best_price) {
+            msg!("MATCH LOOP: best={} outside band [{}, {}], breaking", best_price, band.min, band.max);
+            break;
+        }
+
         let price_ok = match order.order_type {
             OrderType::Market => {
-                msg!("MATCH LOOP: Market order => price always ok");
+                msg!("MATCH LOOP: Market order => price bounded by the band only");
                 true
             }
-            OrderType::Limit => match order.side {
-                Side::Buy => {
-                    let ok = order.limit_price >= best_price;
-                    msg!(
-                        "MATCH LOOP: Limit BUY price check => limit={} best={} ok={}",
-                        order.limit_price,
-                        best_price,
-                        ok
-                    );
-                    ok
-                }
-                Side::Sell => {
-                    let ok = order.limit_price <= best_price;
-                    msg!(
-                        "MATCH LOOP: Limit SELL price check => limit={} best={} ok={}",
-                        order.limit_price,
-                        best_price,
-                        ok
-                    );
+            _ => {
+                let ok = crosses(order.side, order.limit_price, best_price);
+                msg!(
+                    "MATCH LOOP: Limit {:?} price check => limit={} best={} ok={}",
+                    order.side,
+                    order.limit_price,
+                    best_price,
                     ok
-                }
-            },
+                );
+                ok
+            }
         };
 
         if !price_ok {
@@ -109,7 +204,62 @@ pub fn match_against_book_core(
             break;
         }
 
-        let fill_qty = remaining_qty.min(available_qty);
+        if !band.contains(best_price) {
+            let maker = *best_leaf;
+            msg!("MATCH LOOP: maker key={} at {} past band [{}, {}], removing", maker.key, best_price, band.min, band.max);
+            book.remove_leaf(idx)?;
+            event_queue.push(&maker_out(&maker, maker_side, maker.quantity, now_secs))?;
+            outside_band.push(maker);
+            continue;
+        }
+
+        if best_leaf.owner == order.user {
+            let maker = *best_leaf;
+            msg!("MATCH STP: {:?} against own order key={}", order.self_trade, maker.key);
+            match order.self_trade {
+                SelfTradeMode::CancelTaker => {
+                    self_trades.push(SelfTrade { maker_order_id: maker.key, prevented_qty: remaining_qty });
+                    taker_cancelled = true;
+                    break;
+                }
+                SelfTradeMode::CancelMaker => {
+                    book.remove_leaf(idx)?;
+                    event_queue.push(&maker_out(&maker, maker_side, maker.quantity, now_secs))?;
+                    self_trades.push(SelfTrade { maker_order_id: maker.key, prevented_qty: maker.quantity });
+                }
+                SelfTradeMode::DecrementBoth => {
+                    let qty = remaining_qty.min(available_qty);
+                    if qty == available_qty {
+                        book.remove_leaf(idx)?;
+                    } else {
+                        book.nodes[idx as usize].as_leaf_mut().quantity -= qty;
+                    }
+                    event_queue.push(&maker_out(&maker, maker_side, qty, now_secs))?;
+                    event_queue.push(&taker_out(order, qty, now_secs))?;
+                    remaining_qty -= qty;
+                    self_trades.push(SelfTrade { maker_order_id: maker.key, prevented_qty: qty });
+                }
+            }
+            continue;
+        }
+
+        let mut fill_qty = remaining_qty.min(available_qty);
+        if best_leaf.is_reduce_only() {
+            let cap = caps.available(&best_leaf.owner, maker_side);
+            if cap == 0 {
+                let maker = *best_leaf;
+                msg!("MATCH LOOP: reduce-only maker key={} has nothing to reduce, removing", maker.key);
+                book.remove_leaf(idx)?;
+                event_queue.push(&maker_out(&maker, maker_side, maker.quantity, now_secs))?;
+                reduce_only_pulled.push(maker);
+                continue;
+            }
+            fill_qty = fill_qty.min(cap);
+            caps.consume(&best_leaf.owner, maker_side, fill_qty);
+        }
+        if order.reduce_only {
+            caps.consume(&order.user, order.side, fill_qty);
+        }
         let fill_price = best_price;
 
         msg!(
@@ -126,11 +276,13 @@ pub fn match_against_book_core(
             user: best_leaf.owner,
             fill_price,
             fill_qty,
-            side: match order.side {
-                Side::Buy => Side::Sell,
-                Side::Sell => Side::Buy,
-            },
+            side: maker_side,
             timestamp: now_secs,
+            kind: EventKind::Fill,
+            client_order_id: best_leaf.client_order_id(),
+            reduce_only: best_leaf.is_reduce_only(),
+            liquidation: matches!(match_type, MatchingType::Liquidation),
+            maker_fee_tier: best_leaf.fee_tier,
         };
 
         let taker_event = MatchedOrder {
@@ -141,6 +293,11 @@ pub fn match_against_book_core(
             fill_qty,
             side: order.side,
             timestamp: now_secs,
+            kind: EventKind::Fill,
+            client_order_id: order.client_order_id,
+            reduce_only: order.reduce_only,
+            liquidation: false,
+            maker_fee_tier: 0,
         };
 
         match match_type {
@@ -157,6 +314,8 @@ pub fn match_against_book_core(
         }
 
         remaining_qty -= fill_qty;
+        filled_qty += fill_qty;
+        filled_notional += fill_price as u128 * fill_qty as u128;
         msg!(
             "MATCH LOOP: after fill => remaining_qty={}, available_qty_before={}",
             remaining_qty,
@@ -189,7 +348,53 @@ pub fn match_against_book_core(
         taker_fills.len()
     );
 
-    Ok((remaining_qty, taker_fills))
+    Ok(MatchResult {
+        remaining_qty,
+        filled_qty,
+        filled_notional,
+        taker_fills,
+        self_trades,
+        taker_cancelled,
+        expired_makers,
+        reduce_only_pulled,
+        outside_band,
+    })
+}
+
+/// `Out` event for `qty` of a resting order that leaves the book without trading.
+pub(crate) fn maker_out(leaf: &LeafNode, side: Side, qty: u64, now_secs: i64) -> MatchedOrder {
+    MatchedOrder {
+        is_maker: true,
+        order_id: leaf.key,
+        user: leaf.owner,
+        fill_price: leaf.price(),
+        fill_qty: qty,
+        side,
+        timestamp: now_secs,
+        kind: EventKind::Out,
+        client_order_id: leaf.client_order_id(),
+        reduce_only: leaf.is_reduce_only(),
+        liquidation: false,
+        maker_fee_tier: leaf.fee_tier,
+    }
+}
+
+/// `Out` event for `qty` of a taker that will never trade.
+pub(crate) fn taker_out(order: &Order, qty: u64, now_secs: i64) -> MatchedOrder {
+    MatchedOrder {
+        is_maker: false,
+        order_id: order.order_id,
+        user: order.user,
+        fill_price: order.limit_price,
+        fill_qty: qty,
+        side: order.side,
+        timestamp: now_secs,
+        kind: EventKind::Out,
+        client_order_id: order.client_order_id,
+        reduce_only: order.reduce_only,
+        liquidation: false,
+        maker_fee_tier: 0,
+    }
 }
 
 /// On-chain entrypoint: loads event queue and uses Clock for timestamp.
@@ -197,9 +402,1117 @@ pub fn match_against_book<'info>(
     book: &mut Slab,
     order: &Order,
     event_queue: &mut AccountLoader<'info, EventQueue>,
+    caps: &mut ReduceOnlyCaps,
     match_type: MatchingType,
-) -> Result<(u64, Vec<MatchedOrder>)> {
+    band: PriceBand,
+) -> Result<MatchResult> {
     let eq = &mut event_queue.load_mut()?;
     let now = Clock::get()?.unix_timestamp;
-    match_against_book_core(book, order, eq, match_type, now)
+    match_against_book_core(book, order, eq, caps, match_type, band, now)
+}
+
+/// Whether a taker on `side` with `limit_price` trades against a maker at `maker_price`.
+#[inline]
+pub fn crosses(side: Side, limit_price: u64, maker_price: u64) -> bool {
+    match side {
+        Side::Buy => limit_price >= maker_price,
+        Side::Sell => limit_price <= maker_price,
+    }
+}
+
+/// Remove the expired leaves at the top of `book` (the makers on `book_side`) so that the
+/// best price it reports is live. Each removal pushes an `Out` event.
+pub fn remove_expired_top(
+    book: &mut Slab,
+    book_side: Side,
+    event_queue: &mut EventQueue,
+    now_secs: i64,
+) -> Result<Vec<LeafNode>> {
+    let mut expired = Vec::new();
+    loop {
+        let idx = match book_side {
+            Side::Buy => book.find_max(),
+            Side::Sell => book.find_min(),
+        };
+        let Some(idx) = idx else { break };
+        let leaf = *book.nodes[idx as usize].as_leaf();
+        if !leaf.is_expired(now_secs) {
+            break;
+        }
+        book.remove_leaf(idx)?;
+        event_queue.push(&maker_out(&leaf, book_side, leaf.quantity, now_secs))?;
+        expired.push(leaf);
+    }
+    Ok(expired)
+}
+
+/// Best resting price in `book`, which holds the makers on `book_side`.
+pub fn best_resting_price(book: &Slab, book_side: Side) -> Option<u64> {
+    let idx = match book_side {
+        Side::Buy => book.find_max(),
+        Side::Sell => book.find_min(),
+    }?;
+    Some(book.nodes[idx as usize].as_leaf().price())
+}
+
+/// Quantity `order` could take from `book` (the opposite side) at its price, counted up to `up_to`.
+/// Leaves expired at `now_secs` or priced past the band edge the taker would cross are skipped,
+/// as the match loop removes them instead of filling, and counting stops past the other edge,
+/// where the match loop stops. The taker's own leaves never
+/// fill it: they are skipped under `SelfTradeMode::CancelMaker`, and end the count otherwise, as
+/// the taker is then cancelled or shrunk by the own leaf's qty. Reduce-only
+/// makers count only up to what `caps` lets their owners trade.
+pub fn fillable_qty(book: &Slab, order: &Order, up_to: u64, caps: &ReduceOnlyCaps, band: PriceBand, now_secs: i64) -> u64 {
+    let mut caps = caps.clone();
+    let maker_side = match order.side {
+        Side::Buy => Side::Sell,
+        Side::Sell => Side::Buy,
+    };
+    let mut leaves = book.leaves_in_order();
+    if order.side == Side::Sell {
+        leaves.reverse(); // bids: best (highest key) first
+    }
+    let mut total = 0u64;
+    for idx in leaves {
+        let leaf = book.nodes[idx as usize].as_leaf();
+        if band.is_adverse(order.side, leaf.price())
+            || (order.order_type != OrderType::Market && !crosses(order.side, order.limit_price, leaf.price()))
+        {
+            break;
+        }
+        if leaf.is_expired(now_secs) || !band.contains(leaf.price()) {
+            continue;
+        }
+        if leaf.owner == order.user {
+            if order.self_trade == SelfTradeMode::CancelMaker {
+                continue;
+            }
+            break;
+        }
+        let mut qty = leaf.quantity;
+        if leaf.is_reduce_only() {
+            qty = qty.min(caps.available(&leaf.owner, maker_side));
+            caps.consume(&leaf.owner, maker_side, qty);
+        }
+        total = total.saturating_add(qty);
+        if total >= up_to {
+            break;
+        }
+    }
+    total
+}
+
+/// Resolve a cancel by client id into a cancel of the owner's leaf carrying that id.
+pub fn resolve_client_order_id(book: &Slab, cancel: &CancelByClientId) -> Result<CancelOrder> {
+    let idx = book
+        .find_by_client_order_id(&cancel.user.to_bytes(), cancel.client_order_id)
+        .ok_or(PerpError::OrderNotFound)?;
+    Ok(CancelOrder {
+        order_id: book.nodes[idx as usize].as_leaf().key,
+        user: cancel.user,
+        side: cancel.side,
+    })
+}
+
+/// Remove `cancel.order_id` from `book` if it belongs to `cancel.user`, and push an `Out`
+/// event so the owner's reserved margin is released. Returns the removed leaf.
+pub fn cancel_against_book_core(
+    book: &mut Slab,
+    cancel: &CancelOrder,
+    event_queue: &mut EventQueue,
+    now_secs: i64,
+) -> Result<LeafNode> {
+    let idx = book
+        .find_by_key(cancel.order_id)
+        .ok_or(PerpError::OrderNotFound)?;
+    let leaf = *book.nodes[idx as usize].as_leaf();
+    require!(leaf.owner == cancel.user.to_bytes(), PerpError::Unauthorized);
+
+    let removed = book.remove_leaf(idx)?;
+    event_queue.push(&maker_out(&removed, cancel.side, removed.quantity, now_secs))?;
+    Ok(removed)
+}
+
+/// Remove up to `limit` of `owner`'s leaves priced within `[min_price, max_price]`, in key order.
+/// Returns the removed leaves and whether matching leaves were left behind for another call.
+pub fn cancel_all_in_book_core(
+    book: &mut Slab,
+    owner: &[u8; 32],
+    min_price: u64,
+    max_price: u64,
+    limit: usize,
+) -> Result<(Vec<LeafNode>, bool)> {
+    let mut matching: Vec<u32> = book
+        .leaves_in_order()
+        .into_iter()
+        .filter(|&i| {
+            let leaf = book.nodes[i as usize].as_leaf();
+            leaf.owner == *owner && (min_price..=max_price).contains(&leaf.price())
+        })
+        .collect();
+
+    let has_more = matching.len() > limit;
+    matching.truncate(limit);
+
+    // removing a leaf only frees it and its parent, so the other collected indices stay valid
+    let mut removed = Vec::with_capacity(matching.len());
+    for idx in matching {
+        removed.push(book.remove_leaf(idx)?);
+    }
+    Ok((removed, has_more))
+}
+
+/// Remove up to `limit` leaves of `book` (the makers on `book_side`) that have expired at
+/// `now_secs`, pushing an `Out` event for each so the owner's reserved margin is released.
+/// Also returns whether expired leaves were left behind.
+pub fn prune_expired_in_book_core(
+    book: &mut Slab,
+    book_side: Side,
+    event_queue: &mut EventQueue,
+    now_secs: i64,
+    limit: usize,
+) -> Result<(Vec<LeafNode>, bool)> {
+    let mut expired: Vec<u32> = book
+        .leaves_in_order()
+        .into_iter()
+        .filter(|&i| book.nodes[i as usize].as_leaf().is_expired(now_secs))
+        .collect();
+
+    let has_more = expired.len() > limit;
+    expired.truncate(limit);
+
+    let mut removed = Vec::with_capacity(expired.len());
+    for idx in expired {
+        let leaf = book.remove_leaf(idx)?;
+        event_queue.push(&maker_out(&leaf, book_side, leaf.quantity, now_secs))?;
+        removed.push(leaf);
+    }
+    Ok((removed, has_more))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{make_order_id, MatchParams, MatchingEngine, OrderStatus, PlaceOutcome, PlaceResult};
+    use anchor_lang::prelude::Pubkey;
+
+    const CAPACITY: usize = 128;
+
+    struct Book {
+        bids: Vec<u128>,
+        asks: Vec<u128>,
+    }
+
+    impl Book {
+        fn new() -> Self {
+            let words = Slab::compute_allocation_size(CAPACITY) / 16;
+            let mut book = Self {
+                bids: vec![0u128; words],
+                asks: vec![0u128; words],
+            };
+            Slab::initialize(bytemuck::cast_slice_mut(&mut book.bids), CAPACITY).unwrap();
+            Slab::initialize(bytemuck::cast_slice_mut(&mut book.asks), CAPACITY).unwrap();
+            book
+        }
+
+        fn side(&mut self, side: Side) -> &mut Slab {
+            let buf = match side {
+                Side::Buy => &mut self.bids,
+                Side::Sell => &mut self.asks,
+            };
+            Slab::from_bytes_mut(bytemuck::cast_slice_mut(buf)).unwrap()
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Resting {
+        key: u128,
+        price: u64,
+        seq: u64,
+        qty: u64,
+    }
+
+    /// Reference book: plain vectors scanned for the best price, oldest first.
+    #[derive(Default)]
+    struct Reference {
+        bids: Vec<Resting>,
+        asks: Vec<Resting>,
+    }
+
+    impl Reference {
+        fn best(&self, side: Side) -> Option<usize> {
+            let orders = match side {
+                Side::Buy => &self.bids,
+                Side::Sell => &self.asks,
+            };
+            (0..orders.len()).min_by_key(|&i| {
+                let o = &orders[i];
+                let price_rank = match side {
+                    Side::Buy => u64::MAX - o.price,
+                    Side::Sell => o.price,
+                };
+                (price_rank, o.seq)
+            })
+        }
+
+        /// Returns the (maker key, qty, price) fills and the unfilled remainder.
+        fn execute(&mut self, order: &Order, seq: u64) -> (Vec<(u128, u64, u64)>, u64) {
+            let maker_side = opposite(order.side);
+            let mut remaining = order.qty;
+            let mut fills = Vec::new();
+            while remaining > 0 {
+                let Some(i) = self.best(maker_side) else { break };
+                let makers = match maker_side {
+                    Side::Buy => &mut self.bids,
+                    Side::Sell => &mut self.asks,
+                };
+                let maker = &mut makers[i];
+                let crosses = match (order.order_type, order.side) {
+                    (OrderType::Market, _) => true,
+                    (_, Side::Buy) => order.limit_price >= maker.price,
+                    (_, Side::Sell) => order.limit_price <= maker.price,
+                };
+                if !crosses {
+                    break;
+                }
+                let qty = remaining.min(maker.qty);
+                fills.push((maker.key, qty, maker.price));
+                maker.qty -= qty;
+                remaining -= qty;
+                if maker.qty == 0 {
+                    makers.remove(i);
+                }
+            }
+            if remaining > 0 && order.order_type == OrderType::Limit {
+                let resting = Resting {
+                    key: order.order_id,
+                    price: order.limit_price,
+                    seq,
+                    qty: remaining,
+                };
+                match order.side {
+                    Side::Buy => self.bids.push(resting),
+                    Side::Sell => self.asks.push(resting),
+                }
+            }
+            (fills, remaining)
+        }
+
+        fn sorted_keys(&self, side: Side) -> Vec<(u128, u64)> {
+            let orders = match side {
+                Side::Buy => &self.bids,
+                Side::Sell => &self.asks,
+            };
+            let mut keys: Vec<(u128, u64)> = orders.iter().map(|o| (o.key, o.qty)).collect();
+            keys.sort_unstable();
+            keys
+        }
+    }
+
+    fn opposite(side: Side) -> Side {
+        match side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+
+    fn next(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    fn new_event_queue() -> Box<EventQueue> {
+        let mut eq: Box<EventQueue> = Box::new(bytemuck::Zeroable::zeroed());
+        eq.init();
+        eq
+    }
+
+    fn order(order_type: OrderType, side: Side, price: u64, qty: u64, seq: u64) -> Order {
+        Order {
+            user: [seq as u8; 32],
+            order_id: make_order_id(order_type, side, price, seq),
+            side,
+            qty,
+            order_type,
+            limit_price: price,
+            initial_margin: 0,
+            leverage: 1,
+            market: Pubkey::default(),
+            reduce_only: false,
+            self_trade: SelfTradeMode::CancelTaker,
+            client_order_id: seq,
+            expires_at: 0,
+            fee_tier: 0,
+        }
+    }
+
+    /// Runs `MatchingEngine::place_order_core` and returns the maker fills and the unfilled remainder.
+    fn place(book: &mut Book, eq: &mut EventQueue, order: &Order) -> (Vec<(u128, u64, u64)>, u64) {
+        let (fills, result) = place_with_result(book, eq, order);
+        (fills, order.qty - result.filled_qty)
+    }
+
+    fn place_with_result(book: &mut Book, eq: &mut EventQueue, order: &Order) -> (Vec<(u128, u64, u64)>, PlaceResult) {
+        let (fills, result, _) = place_with_outs(book, eq, order);
+        (fills, result)
+    }
+
+    /// (maker order id, qty, price) per fill.
+    type Fills = Vec<(u128, u64, u64)>;
+
+    /// Also returns the (order id, qty) of every maker `Out` event.
+    fn place_with_outs(book: &mut Book, eq: &mut EventQueue, order: &Order) -> (Fills, PlaceResult, Vec<(u128, u64)>) {
+        place_with_caps(book, eq, order, &mut ReduceOnlyCaps::default())
+    }
+
+    fn place_with_caps(
+        book: &mut Book,
+        eq: &mut EventQueue,
+        order: &Order,
+        caps: &mut ReduceOnlyCaps,
+    ) -> (Fills, PlaceResult, Vec<(u128, u64)>) {
+        let bids = Slab::from_bytes_mut(bytemuck::cast_slice_mut(&mut book.bids)).unwrap();
+        let asks = Slab::from_bytes_mut(bytemuck::cast_slice_mut(&mut book.asks)).unwrap();
+        let params = MatchParams { tick_size: 1, band: PriceBand::NONE, now_secs: 0 };
+        let result = MatchingEngine::place_order_core(bids, asks, order, eq, caps, params).unwrap();
+
+        let mut maker_fills = Vec::new();
+        let mut maker_outs = Vec::new();
+        let mut taker_out_qty = 0;
+        while eq.count > 0 {
+            let taker = eq.pop().unwrap();
+            if taker.kind == EventKind::Out {
+                if taker.is_maker {
+                    maker_outs.push((taker.order_id, taker.fill_qty));
+                } else {
+                    assert_eq!(taker.order_id, order.order_id);
+                    taker_out_qty += taker.fill_qty;
+                }
+                continue;
+            }
+            let maker = eq.pop().unwrap();
+            assert!(!taker.is_maker && maker.is_maker);
+            assert_eq!((taker.order_id, taker.client_order_id), (order.order_id, order.client_order_id));
+            assert_eq!((taker.fill_qty, taker.fill_price), (maker.fill_qty, maker.fill_price));
+            maker_fills.push((maker.order_id, maker.fill_qty, maker.fill_price));
+        }
+        assert_eq!(taker_out_qty, result.cancelled_qty);
+        assert_eq!(result.filled_qty + result.rested_qty + result.cancelled_qty, order.qty);
+        (maker_fills, result, maker_outs)
+    }
+
+    fn slab_keys(slab: &Slab) -> Vec<(u128, u64)> {
+        slab.leaves_in_order()
+            .iter()
+            .map(|&i| {
+                let leaf = slab.nodes[i as usize].as_leaf();
+                (leaf.key, leaf.quantity)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_best_bid_fills_before_older_lower_bid() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        place(&mut book, &mut eq, &order(OrderType::Limit, Side::Buy, 100, 5, 1));
+        let better = order(OrderType::Limit, Side::Buy, 101, 5, 2);
+        place(&mut book, &mut eq, &better);
+
+        let (fills, remaining) = place(&mut book, &mut eq, &order(OrderType::Limit, Side::Sell, 100, 5, 3));
+        assert_eq!(fills, vec![(better.order_id, 5, 101)]);
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn test_older_order_fills_first_at_same_price() {
+        for maker_side in [Side::Buy, Side::Sell] {
+            let mut book = Book::new();
+            let mut eq = new_event_queue();
+            let first = order(OrderType::Limit, maker_side, 100, 3, 1);
+            let second = order(OrderType::Limit, maker_side, 100, 3, 2);
+            place(&mut book, &mut eq, &first);
+            place(&mut book, &mut eq, &second);
+
+            let taker = order(OrderType::Market, opposite(maker_side), 0, 4, 3);
+            let (fills, _) = place(&mut book, &mut eq, &taker);
+            assert_eq!(fills, vec![(first.order_id, 3, 100), (second.order_id, 1, 100)]);
+        }
+    }
+
+    #[test]
+    fn test_limit_taker_stops_at_its_price() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        place(&mut book, &mut eq, &order(OrderType::Limit, Side::Sell, 100, 2, 1));
+        place(&mut book, &mut eq, &order(OrderType::Limit, Side::Sell, 102, 2, 2));
+
+        let taker = order(OrderType::Limit, Side::Buy, 101, 5, 3);
+        let (fills, remaining) = place(&mut book, &mut eq, &taker);
+        assert_eq!(fills.len(), 1);
+        assert_eq!(remaining, 3);
+
+        let bids = book.side(Side::Buy);
+        let best = bids.find_max().unwrap();
+        assert_eq!(bids.nodes[best as usize].as_leaf().price(), 101);
+    }
+
+    #[test]
+    fn test_randomized_flow_respects_price_time_priority() {
+        let mut rng = 0xD1B5_4A32_D192_ED03u64;
+        for _ in 0..20 {
+            let mut book = Book::new();
+            let mut eq = new_event_queue();
+            let mut reference = Reference::default();
+
+            for seq in 1..=300u64 {
+                let side = if next(&mut rng) % 2 == 0 { Side::Buy } else { Side::Sell };
+                let order_type = if next(&mut rng) % 8 == 0 { OrderType::Market } else { OrderType::Limit };
+                let price = 95 + next(&mut rng) % 11;
+                let qty = 1 + next(&mut rng) % 10;
+                let o = order(order_type, side, price, qty, seq);
+
+                // keep the books below capacity so inserts never fail
+                if order_type == OrderType::Limit && reference.bids.len() + reference.asks.len() >= CAPACITY / 4 {
+                    continue;
+                }
+
+                let (fills, remaining) = place(&mut book, &mut eq, &o);
+                let (expected_fills, expected_remaining) = reference.execute(&o, seq);
+                assert_eq!(fills, expected_fills);
+                assert_eq!(remaining, expected_remaining);
+
+                for s in [Side::Buy, Side::Sell] {
+                    let slab = book.side(s);
+                    assert_eq!(slab_keys(slab), reference.sorted_keys(s));
+                    let best = match s {
+                        Side::Buy => slab.find_max(),
+                        Side::Sell => slab.find_min(),
+                    }
+                    .map(|i| *slab.nodes[i as usize].as_leaf());
+                    let expected = reference.best(s).map(|i| match s {
+                        Side::Buy => reference.bids[i].clone(),
+                        Side::Sell => reference.asks[i].clone(),
+                    });
+                    assert_eq!(best.map(|l| (l.key, l.price())), expected.map(|r| (r.key, r.price)));
+                }
+
+                // the book never rests crossed
+                let best_bid = book.side(Side::Buy).find_max();
+                let best_ask = book.side(Side::Sell).find_min();
+                if let (Some(b), Some(a)) = (best_bid, best_ask) {
+                    let bid_px = book.side(Side::Buy).nodes[b as usize].as_leaf().price();
+                    let ask_px = book.side(Side::Sell).nodes[a as usize].as_leaf().price();
+                    assert!(bid_px < ask_px);
+                }
+            }
+        }
+    }
+
+    fn cancel_of(order: &Order) -> CancelOrder {
+        CancelOrder {
+            order_id: order.order_id,
+            user: Pubkey::new_from_array(order.user),
+            side: order.side,
+        }
+    }
+
+    #[test]
+    fn test_cancel_removes_owned_order_and_emits_out_event() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        let orders: Vec<Order> = (1..=6)
+            .map(|seq| order(OrderType::Limit, Side::Buy, 95 + seq % 3, 4, seq))
+            .collect();
+        for o in &orders {
+            place(&mut book, &mut eq, o);
+        }
+
+        let target = &orders[2];
+        let removed = cancel_against_book_core(book.side(Side::Buy), &cancel_of(target), &mut eq, 7).unwrap();
+        assert_eq!((removed.key, removed.quantity), (target.order_id, 4));
+
+        let out = eq.pop().unwrap();
+        assert_eq!(out.kind, EventKind::Out);
+        assert_eq!((out.order_id, out.user, out.fill_qty), (target.order_id, target.user, 4));
+        assert_eq!(eq.count, 0);
+
+        let bids = book.side(Side::Buy);
+        assert!(bids.find_by_key(target.order_id).is_none());
+        let mut expected: Vec<(u128, u64)> = orders
+            .iter()
+            .filter(|o| o.order_id != target.order_id)
+            .map(|o| (o.order_id, 4))
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(slab_keys(bids), expected);
+    }
+
+    #[test]
+    fn test_cancel_rejects_other_owner() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        let resting = order(OrderType::Limit, Side::Sell, 100, 5, 1);
+        place(&mut book, &mut eq, &resting);
+
+        let mut cancel = cancel_of(&resting);
+        cancel.user = Pubkey::new_unique();
+        let err = cancel_against_book_core(book.side(Side::Sell), &cancel, &mut eq, 0).unwrap_err();
+        assert_eq!(err, error!(PerpError::Unauthorized));
+        assert!(book.side(Side::Sell).find_by_key(resting.order_id).is_some());
+        assert_eq!(eq.count, 0);
+    }
+
+    #[test]
+    fn test_cancel_of_filled_order_is_not_found() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        let resting = order(OrderType::Limit, Side::Sell, 100, 5, 1);
+        place(&mut book, &mut eq, &resting);
+        place(&mut book, &mut eq, &order(OrderType::Market, Side::Buy, 0, 5, 2));
+
+        let err = cancel_against_book_core(book.side(Side::Sell), &cancel_of(&resting), &mut eq, 0).unwrap_err();
+        assert_eq!(err, error!(PerpError::OrderNotFound));
+    }
+
+    #[test]
+    fn test_maker_fill_carries_its_stamped_fee_tier() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        let mut maker = order(OrderType::Limit, Side::Sell, 100, 5, 1);
+        maker.fee_tier = 2;
+        place(&mut book, &mut eq, &maker);
+
+        let mut taker = order(OrderType::Market, Side::Buy, 0, 2, 2);
+        taker.fee_tier = 1;
+        let bids = Slab::from_bytes_mut(bytemuck::cast_slice_mut(&mut book.bids)).unwrap();
+        let asks = Slab::from_bytes_mut(bytemuck::cast_slice_mut(&mut book.asks)).unwrap();
+        let params = MatchParams { tick_size: 1, band: PriceBand::NONE, now_secs: 0 };
+        MatchingEngine::place_order_core(bids, asks, &taker, &mut eq, &mut ReduceOnlyCaps::default(), params).unwrap();
+        let (taker_fill, maker_fill) = (eq.pop().unwrap(), eq.pop().unwrap());
+        assert_eq!((maker_fill.is_maker, maker_fill.maker_fee_tier), (true, 2));
+        assert_eq!((taker_fill.is_maker, taker_fill.maker_fee_tier), (false, 0));
+    }
+
+    #[test]
+    fn test_client_order_id_is_echoed_and_cancellable() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        let mut maker = order(OrderType::Limit, Side::Sell, 100, 5, 1);
+        maker.client_order_id = u64::MAX - 7;
+        place(&mut book, &mut eq, &maker);
+        let mut other = order(OrderType::Limit, Side::Sell, 101, 5, 2);
+        other.client_order_id = maker.client_order_id;
+        place(&mut book, &mut eq, &other);
+
+        let leaf_idx = book.side(Side::Sell).find_by_key(maker.order_id).unwrap();
+        assert_eq!(book.side(Side::Sell).nodes[leaf_idx as usize].as_leaf().client_order_id(), maker.client_order_id);
+
+        // the maker's fill carries the id it rested with
+        let taker = order(OrderType::Market, Side::Buy, 0, 2, 3);
+        let bids = Slab::from_bytes_mut(bytemuck::cast_slice_mut(&mut book.bids)).unwrap();
+        let asks = Slab::from_bytes_mut(bytemuck::cast_slice_mut(&mut book.asks)).unwrap();
+        let params = MatchParams { tick_size: 1, band: PriceBand::NONE, now_secs: 0 };
+        MatchingEngine::place_order_core(bids, asks, &taker, &mut eq, &mut ReduceOnlyCaps::default(), params).unwrap();
+        let (taker_fill, maker_fill) = (eq.pop().unwrap(), eq.pop().unwrap());
+        assert_eq!(taker_fill.client_order_id, taker.client_order_id);
+        assert_eq!(maker_fill.client_order_id, maker.client_order_id);
+
+        // ids are scoped to their owner: the same id of another owner is not touched
+        let by_client_id = CancelByClientId {
+            client_order_id: maker.client_order_id,
+            user: Pubkey::new_from_array(maker.user),
+            side: Side::Sell,
+        };
+        let cancel = resolve_client_order_id(book.side(Side::Sell), &by_client_id).unwrap();
+        assert_eq!(cancel.order_id, maker.order_id);
+        cancel_against_book_core(book.side(Side::Sell), &cancel, &mut eq, 0).unwrap();
+        let out = eq.pop().unwrap();
+        assert_eq!((out.kind, out.fill_qty, out.client_order_id), (EventKind::Out, 3, maker.client_order_id));
+        assert_eq!(slab_keys(book.side(Side::Sell)), vec![(other.order_id, 5)]);
+
+        let err = resolve_client_order_id(book.side(Side::Sell), &by_client_id)
+            .map(|c| c.order_id)
+            .unwrap_err();
+        assert_eq!(err, error!(PerpError::OrderNotFound));
+    }
+
+    #[test]
+    fn test_cancel_all_removes_only_owner_orders_in_range() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        let owner = [0xAA; 32];
+        let mut mine = Vec::new();
+        for seq in 1..=20u64 {
+            let mut o = order(OrderType::Limit, Side::Sell, 100 + seq % 5, 2, seq);
+            if seq % 2 == 0 {
+                o.user = owner;
+                mine.push(o.clone());
+            }
+            place(&mut book, &mut eq, &o);
+        }
+
+        let asks = book.side(Side::Sell);
+        let (removed, has_more) = cancel_all_in_book_core(asks, &owner, 101, 103, usize::MAX).unwrap();
+        assert!(!has_more);
+        let mut expected: Vec<u128> = mine
+            .iter()
+            .filter(|o| (101..=103).contains(&o.limit_price))
+            .map(|o| o.order_id)
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(removed.iter().map(|l| l.key).collect::<Vec<_>>(), expected);
+
+        for i in asks.leaves_in_order() {
+            let leaf = asks.nodes[i as usize].as_leaf();
+            assert!(leaf.owner != owner || !(101..=103).contains(&leaf.price()));
+        }
+        assert_eq!(asks.header.leaf_count as usize, 20 - expected.len());
+    }
+
+    #[test]
+    fn test_cancel_all_is_bounded_and_resumable() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        let owner = [0xBB; 32];
+        for seq in 1..=10u64 {
+            let mut o = order(OrderType::Limit, Side::Buy, 90 + seq, 1, seq);
+            o.user = owner;
+            place(&mut book, &mut eq, &o);
+        }
+
+        let bids = book.side(Side::Buy);
+        let mut total = 0;
+        let mut calls = 0;
+        loop {
+            let (removed, has_more) = cancel_all_in_book_core(bids, &owner, 0, u64::MAX, 4).unwrap();
+            assert!(removed.len() <= 4);
+            total += removed.len();
+            calls += 1;
+            if !has_more {
+                break;
+            }
+        }
+        assert_eq!((total, calls), (10, 3));
+        assert!(bids.find_max().is_none());
+    }
+
+    fn book_with_asks(eq: &mut EventQueue, levels: &[(u64, u64)]) -> Book {
+        let mut book = Book::new();
+        for (i, &(price, qty)) in levels.iter().enumerate() {
+            place(&mut book, eq, &order(OrderType::Limit, Side::Sell, price, qty, 100 + i as u64));
+        }
+        book
+    }
+
+    #[test]
+    fn test_ioc_never_rests() {
+        let mut eq = new_event_queue();
+        let mut book = book_with_asks(&mut eq, &[(100, 3), (102, 3)]);
+
+        let ioc = order(OrderType::ImmediateOrCancel, Side::Buy, 101, 5, 1);
+        let (fills, result) = place_with_result(&mut book, &mut eq, &ioc);
+        assert_eq!(fills.len(), 1);
+        assert_eq!(result.outcome, PlaceOutcome::RemainderCancelled);
+        assert_eq!((result.filled_qty, result.rested_qty, result.cancelled_qty), (3, 0, 2));
+        assert!(book.side(Side::Buy).find_max().is_none());
+    }
+
+    #[test]
+    fn test_filled_notional_sums_every_level() {
+        let mut eq = new_event_queue();
+        let mut book = book_with_asks(&mut eq, &[(100, 3), (102, 3)]);
+
+        let taker = order(OrderType::Market, Side::Buy, 0, 4, 1);
+        let (_, result) = place_with_result(&mut book, &mut eq, &taker);
+        assert_eq!(result.filled_qty, 4);
+        assert_eq!(result.filled_notional, 100 * 3 + 102);
+    }
+
+    #[test]
+    fn test_fok_emits_nothing_without_enough_depth() {
+        let mut eq = new_event_queue();
+        let mut book = book_with_asks(&mut eq, &[(100, 3), (101, 3), (105, 10)]);
+        let before = slab_keys(book.side(Side::Sell));
+
+        let fok = order(OrderType::FillOrKill, Side::Buy, 101, 7, 1);
+        let (fills, result) = place_with_result(&mut book, &mut eq, &fok);
+        assert!(fills.is_empty());
+        assert_eq!(result.outcome, PlaceOutcome::FillOrKillRejected);
+        assert_eq!(result.cancelled_qty, 7);
+        assert_eq!(slab_keys(book.side(Side::Sell)), before);
+
+        let fok = order(OrderType::FillOrKill, Side::Buy, 101, 6, 2);
+        let (fills, result) = place_with_result(&mut book, &mut eq, &fok);
+        assert_eq!(fills.len(), 2);
+        assert_eq!(result.outcome, PlaceOutcome::Filled);
+    }
+
+    #[test]
+    fn test_post_only_rejected_when_crossing() {
+        let mut eq = new_event_queue();
+        let mut book = book_with_asks(&mut eq, &[(100, 3)]);
+
+        let post = order(OrderType::PostOnly, Side::Buy, 100, 2, 1);
+        let (fills, result) = place_with_result(&mut book, &mut eq, &post);
+        assert!(fills.is_empty());
+        assert_eq!(result.outcome, PlaceOutcome::PostOnlyRejected);
+        assert!(book.side(Side::Buy).find_max().is_none());
+
+        let post = order(OrderType::PostOnly, Side::Buy, 99, 2, 2);
+        let (_, result) = place_with_result(&mut book, &mut eq, &post);
+        assert_eq!(result.outcome, PlaceOutcome::Rested);
+        assert_eq!(result.rested_qty, 2);
+    }
+
+    #[test]
+    fn test_post_only_slide_reprices_behind_best() {
+        let mut eq = new_event_queue();
+        let mut book = book_with_asks(&mut eq, &[(100, 3)]);
+
+        let post = order(OrderType::PostOnlySlide, Side::Buy, 103, 2, 1);
+        let (fills, result) = place_with_result(&mut book, &mut eq, &post);
+        assert!(fills.is_empty());
+        assert_eq!(result.outcome, PlaceOutcome::PostOnlyRepriced);
+        assert_eq!(result.limit_price, 99);
+        assert_eq!(result.order_id, make_order_id(OrderType::Limit, Side::Buy, 99, 1));
+
+        let bids = book.side(Side::Buy);
+        let best = bids.find_max().unwrap();
+        let leaf = bids.nodes[best as usize].as_leaf();
+        assert_eq!((leaf.key, leaf.price(), leaf.quantity), (result.order_id, 99, 2));
+    }
+
+    /// Position of `owner` holding `base` lots.
+    fn position(owner: [u8; 32], base: i64) -> Position {
+        Position {
+            owner: Pubkey::new_from_array(owner),
+            market: Pubkey::default(),
+            order_id: 0,
+            side: Side::Buy,
+            price: 0,
+            qty: 0,
+            order_type: OrderType::Limit,
+            status: OrderStatus::Pending,
+            base_position: base,
+            entry_price: 100,
+            realized_pnl: 0,
+            last_cum_funding: 0,
+            is_isolated: false,
+            isolated_margin: 0,
+            reduce_only_pending: 0,
+            initial_margin: 0,
+            leverage: 0,
+            flags: 0,
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_reduce_only_remainder_rests_flagged() {
+        let mut eq = new_event_queue();
+        let mut book = book_with_asks(&mut eq, &[(100, 2)]);
+
+        let mut close = order(OrderType::Limit, Side::Buy, 101, 5, 1);
+        close.reduce_only = true;
+        let mut caps = ReduceOnlyCaps::from_positions([&position(close.user, -5)]);
+        let (fills, result, _) = place_with_caps(&mut book, &mut eq, &close, &mut caps);
+        assert_eq!(fills.len(), 1);
+        assert_eq!(result.outcome, PlaceOutcome::Rested);
+        assert_eq!((result.filled_qty, result.rested_qty), (2, 3));
+        assert_eq!((caps.available(&close.user, Side::Buy), caps.matched(&close.user)), (3, 2));
+
+        let bids = book.side(Side::Buy);
+        let leaf = bids.nodes[bids.find_max().unwrap() as usize].as_leaf();
+        assert_eq!((leaf.key, leaf.quantity), (close.order_id, 3));
+        assert!(leaf.is_reduce_only());
+    }
+
+    #[test]
+    fn test_resting_reduce_only_maker_is_capped_at_its_position() {
+        let mut eq = new_event_queue();
+        let mut book = Book::new();
+        let mut close = order(OrderType::Limit, Side::Sell, 100, 5, 1);
+        close.reduce_only = true;
+        let mut caps = ReduceOnlyCaps::from_positions([&position(close.user, 5)]);
+        place_with_caps(&mut book, &mut eq, &close, &mut caps);
+        let other = order(OrderType::Limit, Side::Sell, 101, 5, 2);
+        place(&mut book, &mut eq, &other);
+
+        // the position shrank to 3 while the order rested
+        let mut caps = ReduceOnlyCaps::from_positions([&position(close.user, 3)]);
+        let mut fok = order(OrderType::FillOrKill, Side::Buy, 101, 9, 3);
+        let (fills, result, _) = place_with_caps(&mut book, &mut eq, &fok, &mut caps);
+        assert_eq!(result.outcome, PlaceOutcome::FillOrKillRejected);
+        assert!(fills.is_empty());
+
+        // it fills 3, then is pulled with the 2 it can no longer reduce
+        fok.qty = 8;
+        let (fills, result, outs) = place_with_caps(&mut book, &mut eq, &fok, &mut caps);
+        assert_eq!(result.outcome, PlaceOutcome::Filled);
+        assert_eq!(fills, vec![(close.order_id, 3, 100), (other.order_id, 5, 101)]);
+        assert_eq!(outs, vec![(close.order_id, 2)]);
+        assert_eq!(result.reduce_only_pulled.len(), 1);
+        assert_eq!(caps.matched(&close.user), 3);
+        assert!(book.side(Side::Sell).find_min().is_none());
+
+        // an owner with no position listed has nothing to reduce
+        let mut eq = new_event_queue();
+        let mut book = Book::new();
+        place_with_caps(&mut book, &mut eq, &close, &mut ReduceOnlyCaps::from_positions([&position(close.user, 5)]));
+        let taker = order(OrderType::Market, Side::Buy, 0, 5, 3);
+        let (fills, result, outs) = place_with_outs(&mut book, &mut eq, &taker);
+        assert!(fills.is_empty());
+        assert_eq!(result.outcome, PlaceOutcome::RemainderCancelled);
+        assert_eq!(outs, vec![(close.order_id, 5)]);
+    }
+
+    /// Asks at 100 (owner A, qty 3), 101 (owner B, qty 3) and a buy from owner A for `qty`.
+    fn self_trade_setup(mode: SelfTradeMode, qty: u64) -> (Book, Box<EventQueue>, Order, Order, Order) {
+        let mut eq = new_event_queue();
+        let mut book = Book::new();
+        let mut own = order(OrderType::Limit, Side::Sell, 100, 3, 1);
+        own.user = [0xA1; 32];
+        let other = order(OrderType::Limit, Side::Sell, 101, 3, 2);
+        place(&mut book, &mut eq, &own);
+        place(&mut book, &mut eq, &other);
+
+        let mut taker = order(OrderType::Limit, Side::Buy, 101, qty, 3);
+        taker.user = own.user;
+        taker.self_trade = mode;
+        (book, eq, own, other, taker)
+    }
+
+    #[test]
+    fn test_self_trade_cancel_taker() {
+        let (mut book, mut eq, own, _, taker) = self_trade_setup(SelfTradeMode::CancelTaker, 4);
+        let (fills, result, maker_outs) = place_with_outs(&mut book, &mut eq, &taker);
+
+        assert!(fills.is_empty());
+        assert!(maker_outs.is_empty());
+        assert_eq!(result.outcome, PlaceOutcome::RemainderCancelled);
+        assert_eq!(result.cancelled_qty, 4);
+        assert_eq!(result.self_trades, vec![SelfTrade { maker_order_id: own.order_id, prevented_qty: 4 }]);
+        // the maker is untouched and the taker did not rest against it
+        assert_eq!(slab_keys(book.side(Side::Sell))[0], (own.order_id, 3));
+        assert!(book.side(Side::Buy).find_max().is_none());
+    }
+
+    #[test]
+    fn test_self_trade_cancel_maker() {
+        let (mut book, mut eq, own, other, taker) = self_trade_setup(SelfTradeMode::CancelMaker, 4);
+        let (fills, result, maker_outs) = place_with_outs(&mut book, &mut eq, &taker);
+
+        assert_eq!(fills, vec![(other.order_id, 3, 101)]);
+        assert_eq!(maker_outs, vec![(own.order_id, 3)]);
+        assert_eq!(result.self_trades, vec![SelfTrade { maker_order_id: own.order_id, prevented_qty: 3 }]);
+        assert_eq!((result.filled_qty, result.rested_qty), (3, 1));
+        assert!(book.side(Side::Sell).find_min().is_none());
+    }
+
+    #[test]
+    fn test_self_trade_decrement_both() {
+        let (mut book, mut eq, own, _, taker) = self_trade_setup(SelfTradeMode::DecrementBoth, 2);
+        let (fills, result, maker_outs) = place_with_outs(&mut book, &mut eq, &taker);
+
+        assert!(fills.is_empty());
+        assert_eq!(maker_outs, vec![(own.order_id, 2)]);
+        assert_eq!(result.self_trades, vec![SelfTrade { maker_order_id: own.order_id, prevented_qty: 2 }]);
+        assert_eq!(result.cancelled_qty, 2);
+        assert_eq!(slab_keys(book.side(Side::Sell))[0], (own.order_id, 1));
+
+        // a larger taker consumes the rest of its own order, then trades with the other owner
+        let (mut book, mut eq, own, other, taker) = self_trade_setup(SelfTradeMode::DecrementBoth, 5);
+        let (fills, result, maker_outs) = place_with_outs(&mut book, &mut eq, &taker);
+        assert_eq!(maker_outs, vec![(own.order_id, 3)]);
+        assert_eq!(fills, vec![(other.order_id, 2, 101)]);
+        assert_eq!((result.filled_qty, result.cancelled_qty, result.outcome), (2, 3, PlaceOutcome::RemainderCancelled));
+        assert_eq!(slab_keys(book.side(Side::Sell)), vec![(other.order_id, 1)]);
+    }
+
+    #[test]
+    fn test_fok_depth_ignores_own_liquidity() {
+        // only 3 of the 6 resting can fill a taker that owns the ask at 100
+        let (mut book, mut eq, own, other, mut taker) = self_trade_setup(SelfTradeMode::CancelMaker, 4);
+        taker.order_type = OrderType::FillOrKill;
+        let (fills, result, maker_outs) = place_with_outs(&mut book, &mut eq, &taker);
+        assert!(fills.is_empty() && maker_outs.is_empty());
+        assert_eq!(result.outcome, PlaceOutcome::FillOrKillRejected);
+
+        taker.qty = 3;
+        let (fills, result, maker_outs) = place_with_outs(&mut book, &mut eq, &taker);
+        assert_eq!(fills, vec![(other.order_id, 3, 101)]);
+        assert_eq!(maker_outs, vec![(own.order_id, 3)]);
+        assert_eq!(result.outcome, PlaceOutcome::Filled);
+
+        // cancel-taker stops at its own order, so nothing behind it counts
+        let (mut book, mut eq, own, other, mut taker) = self_trade_setup(SelfTradeMode::CancelTaker, 3);
+        taker.order_type = OrderType::FillOrKill;
+        let (fills, result) = place_with_result(&mut book, &mut eq, &taker);
+        assert!(fills.is_empty());
+        assert_eq!(result.outcome, PlaceOutcome::FillOrKillRejected);
+        assert_eq!(slab_keys(book.side(Side::Sell)), vec![(own.order_id, 3), (other.order_id, 3)]);
+
+        // decrement-both would shrink the taker by its own order before reaching the other
+        let (mut book, mut eq, own, other, mut taker) = self_trade_setup(SelfTradeMode::DecrementBoth, 3);
+        taker.order_type = OrderType::FillOrKill;
+        let (fills, result, maker_outs) = place_with_outs(&mut book, &mut eq, &taker);
+        assert!(fills.is_empty() && maker_outs.is_empty());
+        assert_eq!(result.outcome, PlaceOutcome::FillOrKillRejected);
+        assert_eq!(slab_keys(book.side(Side::Sell)), vec![(own.order_id, 3), (other.order_id, 3)]);
+    }
+
+    /// Rest `order` with `expires_at` directly, as `place_order_core` would.
+    fn rest_expiring(book: &mut Book, order: &Order, expires_at: i64) {
+        let leaf = LeafNode::new(order.order_id, order.user, order.qty, 0, 0)
+            .with_client_order_id(order.client_order_id)
+            .with_expires_at(expires_at);
+        book.side(order.side).insert_leaf(&leaf).unwrap();
+    }
+
+    fn place_at(book: &mut Book, eq: &mut EventQueue, order: &Order, now: i64) -> PlaceResult {
+        let bids = Slab::from_bytes_mut(bytemuck::cast_slice_mut(&mut book.bids)).unwrap();
+        let asks = Slab::from_bytes_mut(bytemuck::cast_slice_mut(&mut book.asks)).unwrap();
+        let params = MatchParams { tick_size: 1, band: PriceBand::NONE, now_secs: now };
+        MatchingEngine::place_order_core(bids, asks, order, eq, &mut ReduceOnlyCaps::default(), params).unwrap()
+    }
+
+    #[test]
+    fn test_expired_maker_is_removed_instead_of_filled() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        let stale = order(OrderType::Limit, Side::Sell, 100, 5, 1);
+        let live = order(OrderType::Limit, Side::Sell, 101, 5, 2);
+        rest_expiring(&mut book, &stale, 10);
+        rest_expiring(&mut book, &live, 0);
+
+        let result = place_at(&mut book, &mut eq, &order(OrderType::Limit, Side::Buy, 101, 3, 3), 10);
+        assert_eq!(result.filled_qty, 3);
+        assert_eq!(result.expired_makers.len(), 1);
+        assert_eq!(result.expired_makers[0].key, stale.order_id);
+
+        let out = eq.pop().unwrap();
+        assert_eq!((out.kind, out.is_maker, out.order_id, out.fill_qty), (EventKind::Out, true, stale.order_id, 5));
+        let (taker, maker) = (eq.pop().unwrap(), eq.pop().unwrap());
+        assert_eq!((taker.kind, maker.order_id, maker.fill_price), (EventKind::Fill, live.order_id, 101));
+        assert_eq!(slab_keys(book.side(Side::Sell)), vec![(live.order_id, 2)]);
+    }
+
+    #[test]
+    fn test_expired_depth_does_not_count_and_does_not_block_post_only() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        let stale = order(OrderType::Limit, Side::Sell, 100, 5, 1);
+        rest_expiring(&mut book, &stale, 10);
+        rest_expiring(&mut book, &order(OrderType::Limit, Side::Sell, 101, 2, 2), 0);
+
+        let fok = place_at(&mut book, &mut eq, &order(OrderType::FillOrKill, Side::Buy, 101, 4, 3), 10);
+        assert_eq!(fok.outcome, PlaceOutcome::FillOrKillRejected);
+
+        // the post-only bid at the stale ask's price rests once that ask is gone
+        let post = order(OrderType::PostOnly, Side::Buy, 100, 1, 4);
+        let result = place_at(&mut book, &mut eq, &post, 10);
+        assert_eq!(result.outcome, PlaceOutcome::Rested);
+        assert_eq!(result.expired_makers.len(), 1);
+        assert!(book.side(Side::Sell).find_by_key(stale.order_id).is_none());
+    }
+
+    #[test]
+    fn test_expired_taker_never_trades_and_expiry_is_kept_on_rest() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        rest_expiring(&mut book, &order(OrderType::Limit, Side::Sell, 100, 5, 1), 0);
+
+        let mut late = order(OrderType::Limit, Side::Buy, 100, 2, 2);
+        late.expires_at = 10;
+        let result = place_at(&mut book, &mut eq, &late, 10);
+        assert_eq!((result.outcome, result.cancelled_qty), (PlaceOutcome::Expired, 2));
+        let out = eq.pop().unwrap();
+        assert_eq!((out.kind, out.is_maker, out.fill_qty), (EventKind::Out, false, 2));
+
+        let mut resting = order(OrderType::Limit, Side::Buy, 99, 2, 3);
+        resting.expires_at = 20;
+        place_at(&mut book, &mut eq, &resting, 10);
+        let idx = book.side(Side::Buy).find_by_key(resting.order_id).unwrap();
+        assert_eq!(book.side(Side::Buy).nodes[idx as usize].as_leaf().expires_at, 20);
+    }
+
+    #[test]
+    fn test_prune_expired_is_bounded_and_resumable() {
+        let mut book = Book::new();
+        let mut eq = new_event_queue();
+        let mut stale = Vec::new();
+        for seq in 1..=6 {
+            let o = order(OrderType::Limit, Side::Buy, 90 + seq, 1, seq);
+            rest_expiring(&mut book, &o, if seq % 2 == 0 { 5 } else { 50 });
+            if seq % 2 == 0 {
+                stale.push(o.order_id);
+            }
+        }
+
+        let (first, has_more) = prune_expired_in_book_core(book.side(Side::Buy), Side::Buy, &mut eq, 10, 2).unwrap();
+        assert_eq!(first.len(), 2);
+        assert!(has_more);
+        let (rest, has_more) = prune_expired_in_book_core(book.side(Side::Buy), Side::Buy, &mut eq, 10, 2).unwrap();
+        assert_eq!(rest.len(), 1);
+        assert!(!has_more);
+
+        let mut pruned: Vec<u128> = first.iter().chain(rest.iter()).map(|l| l.key).collect();
+        pruned.sort_unstable();
+        stale.sort_unstable();
+        assert_eq!(pruned, stale);
+        assert_eq!(eq.count, 3);
+        while eq.count > 0 {
+            let out = eq.pop().unwrap();
+            assert!(out.kind == EventKind::Out && out.is_maker && stale.contains(&out.order_id));
+        }
+        assert_eq!(book.side(Side::Buy).leaves_in_order().len(), 3);
+    }
+
+    fn place_in_band(book: &mut Book, eq: &mut EventQueue, order: &Order, band: PriceBand) -> PlaceResult {
+        let bids = Slab::from_bytes_mut(bytemuck::cast_slice_mut(&mut book.bids)).unwrap();
+        let asks = Slab::from_bytes_mut(bytemuck::cast_slice_mut(&mut book.asks)).unwrap();
+        let params = MatchParams { tick_size: 1, band, now_secs: 0 };
+        MatchingEngine::place_order_core(bids, asks, order, eq, &mut ReduceOnlyCaps::default(), params).unwrap()
+    }
+
+    #[test]
+    fn test_market_order_stops_at_band_edge() {
+        let mut eq = new_event_queue();
+        let mut book = book_with_asks(&mut eq, &[(100, 2), (101, 2), (103, 2)]);
+        let band = PriceBand { min: 98, max: 102 };
+
+        let result = place_in_band(&mut book, &mut eq, &order(OrderType::Market, Side::Buy, 0, 6, 10), band);
+        assert_eq!((result.outcome, result.filled_qty, result.cancelled_qty), (PlaceOutcome::RemainderCancelled, 4, 2));
+        assert_eq!(best_resting_price(book.side(Side::Sell), Side::Sell), Some(103));
+        assert_eq!(slab_keys(book.side(Side::Sell)).len(), 1);
+    }
+
+    #[test]
+    fn test_limit_order_and_fok_depth_stop_outside_band() {
+        let mut eq = new_event_queue();
+        let mut book = book_with_asks(&mut eq, &[(95, 2), (100, 2), (103, 2)]);
+        let band = PriceBand { min: 98, max: 102 };
+
+        // the ask above the band stops the count; the one below it is never filled
+        let fok = place_in_band(&mut book, &mut eq, &order(OrderType::FillOrKill, Side::Buy, 103, 3, 10), band);
+        assert_eq!(fok.outcome, PlaceOutcome::FillOrKillRejected);
+        assert_eq!(book.side(Side::Sell).leaves_in_order().len(), 3);
+        eq.pop().unwrap();
+
+        // the ask below the band is pulled rather than left crossing the resting remainder
+        let limit = order(OrderType::Limit, Side::Buy, 101, 3, 11);
+        let result = place_in_band(&mut book, &mut eq, &limit, band);
+        assert_eq!((result.outcome, result.filled_qty, result.rested_qty), (PlaceOutcome::Rested, 2, 1));
+        assert_eq!(result.outside_band.iter().map(|leaf| leaf.price()).collect::<Vec<_>>(), vec![95]);
+        let out = eq.pop().unwrap();
+        assert!(out.kind == EventKind::Out && out.is_maker && out.fill_price == 95 && out.fill_qty == 2);
+
+        let best_bid = best_resting_price(book.side(Side::Buy), Side::Buy);
+        let best_ask = best_resting_price(book.side(Side::Sell), Side::Sell);
+        assert_eq!((best_bid, best_ask), (Some(101), Some(103)));
+    }
 }
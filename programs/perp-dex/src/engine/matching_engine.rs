@@ -1,146 +1,405 @@
 use anchor_lang::prelude::*;
 
 use crate::{
+    CancelByClientId,
     CancelOrder,
+    EventQueue,
+    FillOrKillRejected,
     LeafNode,
     MatchingType,
     Order,
+    OrderCancelled,
+    OrderExpired,
+    OrderRemainderCancelled,
     OrderType,
     PerpError,
+    PostOnlyRejected,
+    PostOnlyRepriced,
+    PriceBand,
+    ReduceOnlyCapped,
+    ReduceOnlyCaps,
+    SelfTrade,
+    SelfTradePrevented,
     Side,
     Slab,
-    match_against_book,
+    best_resting_price,
+    cancel_against_book_core,
+    crosses,
+    fillable_qty,
+    match_against_book_core,
+    remove_expired_top,
+    resolve_client_order_id,
+    taker_out,
     DISCRIMINATOR_LEN,
 };
 
 pub struct MatchingEngine;
 
+/// What happened to an order once it reached the book.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaceOutcome {
+    Filled,             // nothing left over
+    Rested,             // remainder rests at its limit price
+    RemainderCancelled, // market / IOC remainder dropped
+    FillOrKillRejected, // book could not fill the whole qty, nothing traded
+    PostOnlyRejected,   // would have crossed, nothing traded
+    PostOnlyRepriced,   // would have crossed, rests one tick behind the opposite best
+    Expired,            // its expiry passed before it reached the book, nothing traded
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlaceResult {
+    pub outcome: PlaceOutcome,
+    pub order_id: u128, // key the remainder rests under; differs from the request after a reprice
+    pub limit_price: u64,
+    pub filled_qty: u64,
+    pub filled_notional: u128,
+    pub rested_qty: u64,
+    pub cancelled_qty: u64,
+    pub self_trades: Vec<SelfTrade>,
+    pub expired_makers: Vec<LeafNode>, // removed from the opposite book on the way
+    pub reduce_only_pulled: Vec<LeafNode>, // reduce-only makers pulled with nothing left to reduce
+    pub outside_band: Vec<LeafNode>, // makers pulled for a price better than the band
+}
+
+/// Market parameters an order is matched under.
+#[derive(Clone, Copy, Debug)]
+pub struct MatchParams {
+    pub tick_size: u64,
+    pub band: PriceBand,
+    pub now_secs: i64,
+}
+
 impl MatchingEngine {
-    /// Match an order against the book and optionally place remainder as a limit order.
+    /// Match an order against the book and handle the remainder according to its order type.
     pub fn process_place_order<'info>(
         ctx: &mut crate::instructions::process_order::ProcessOrder<'info>,
         order: Order,
+        caps: &mut ReduceOnlyCaps,
+        band: PriceBand,
     ) -> Result<()> {
-        let fee: u8 = 2;
         let current_time = Clock::get()?.unix_timestamp;
+        let market_key = ctx.market.key();
+        let tick_size = ctx.market.tick_size as u64;
 
-        let remaining_qty = match order.side {
-            Side::Buy => {
-                let ask_account_info = ctx.asks.to_account_info();
-                let mut ask_data = ask_account_info.try_borrow_mut_data()?;
-                let ask_bytes: &mut [u8] = &mut ask_data[DISCRIMINATOR_LEN..];
-                let ask_slab = Slab::from_bytes_mut(ask_bytes)?;
-                log_match_header("BUY match asks", ask_slab);
-                let (remaining, _) = match_against_book(
-                    ask_slab,
-                    &order,
-                    &mut ctx.event_queue,
-                    MatchingType::Normal,
-                )?;
-                remaining
-            }
-            Side::Sell => {
-                let bid_account_info = ctx.bids.to_account_info();
-                let mut bid_data = bid_account_info.try_borrow_mut_data()?;
-                let bid_bytes: &mut [u8] = &mut bid_data[DISCRIMINATOR_LEN..];
-                let bid_slab = Slab::from_bytes_mut(bid_bytes)?;
-                log_match_header("SELL match bids", bid_slab);
-                let (remaining, _) = match_against_book(
-                    bid_slab,
-                    &order,
-                    &mut ctx.event_queue,
-                    MatchingType::Normal,
-                )?;
-                remaining
-            }
+        let bid_account_info = ctx.bids.to_account_info();
+        let ask_account_info = ctx.asks.to_account_info();
+        let mut bid_data = bid_account_info.try_borrow_mut_data()?;
+        let mut ask_data = ask_account_info.try_borrow_mut_data()?;
+        let bid_slab = Slab::from_bytes_mut(&mut bid_data[DISCRIMINATOR_LEN..])?;
+        let ask_slab = Slab::from_bytes_mut(&mut ask_data[DISCRIMINATOR_LEN..])?;
+        log_match_header("bids", bid_slab);
+        log_match_header("asks", ask_slab);
+
+        let mut event_queue = ctx.event_queue.load_mut()?;
+        let result = Self::place_order_core(
+            bid_slab,
+            ask_slab,
+            &order,
+            &mut event_queue,
+            caps,
+            MatchParams { tick_size, band, now_secs: current_time },
+        )?;
+
+        msg!(
+            "ME: {:?} {:?} order_id={} => {:?} filled={} rested={} cancelled={}",
+            order.order_type,
+            order.side,
+            order.order_id,
+            result.outcome,
+            result.filled_qty,
+            result.rested_qty,
+            result.cancelled_qty
+        );
+
+        let owner = Pubkey::new_from_array(order.user);
+        let maker_side = match order.side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
         };
+        for maker in &result.expired_makers {
+            emit!(OrderExpired::removed(market_key, maker, maker_side));
+        }
+        for maker in &result.outside_band {
+            emit!(OrderCancelled::removed(market_key, maker, maker_side));
+        }
+        for maker in &result.reduce_only_pulled {
+            emit!(ReduceOnlyCapped {
+                market: market_key,
+                order_id: maker.key,
+                owner: Pubkey::new_from_array(maker.owner),
+                requested_qty: maker.quantity,
+                allowed_qty: 0,
+            });
+        }
+        for self_trade in &result.self_trades {
+            emit!(SelfTradePrevented {
+                market: market_key,
+                taker_order_id: order.order_id,
+                maker_order_id: self_trade.maker_order_id,
+                owner,
+                mode: order.self_trade,
+                prevented_qty: self_trade.prevented_qty,
+            });
+        }
+        match result.outcome {
+            PlaceOutcome::Filled | PlaceOutcome::Rested => {}
+            PlaceOutcome::RemainderCancelled => emit!(OrderRemainderCancelled {
+                market: market_key,
+                order_id: order.order_id,
+                owner,
+                order_type: order.order_type,
+                filled_qty: result.filled_qty,
+                cancelled_qty: result.cancelled_qty,
+            }),
+            PlaceOutcome::Expired => emit!(OrderExpired {
+                market: market_key,
+                order_id: order.order_id,
+                owner,
+                side: order.side,
+                price: order.limit_price,
+                removed_qty: order.qty,
+                client_order_id: order.client_order_id,
+                expires_at: order.expires_at,
+            }),
+            PlaceOutcome::FillOrKillRejected => emit!(FillOrKillRejected {
+                market: market_key,
+                order_id: order.order_id,
+                owner,
+                qty: order.qty,
+            }),
+            PlaceOutcome::PostOnlyRejected => emit!(PostOnlyRejected {
+                market: market_key,
+                order_id: order.order_id,
+                owner,
+                limit_price: order.limit_price,
+            }),
+            PlaceOutcome::PostOnlyRepriced => emit!(PostOnlyRepriced {
+                market: market_key,
+                original_order_id: order.order_id,
+                order_id: result.order_id,
+                owner,
+                original_price: order.limit_price,
+                limit_price: result.limit_price,
+            }),
+        }
 
-        if remaining_qty > 0 {
-            match order.order_type {
-                OrderType::Limit => {
-                    match order.side {
-                        Side::Buy => {
-                            let bid_account_info = ctx.bids.to_account_info();
-                            let mut bid_data = bid_account_info.try_borrow_mut_data()?;
-                            let bid_bytes: &mut [u8] = &mut bid_data[DISCRIMINATOR_LEN..];
-                            let bid_slab = Slab::from_bytes_mut(bid_bytes)?;
-                            log_match_header("insert bids limit", bid_slab);
-                            let leaf = LeafNode::new(
-                                order.order_id,
-                                order.user,
-                                remaining_qty,
-                                fee,
-                                current_time,
-                            );
-                            let order_index = bid_slab.insert_leaf(&leaf)?;
-                            msg!(
-                                "ME: Added limit BUY to bids at index={}, qty={}",
-                                order_index,
-                                remaining_qty
-                            );
-                        }
-                        Side::Sell => {
-                            let ask_account_info = ctx.asks.to_account_info();
-                            let mut ask_data = ask_account_info.try_borrow_mut_data()?;
-                            let ask_bytes: &mut [u8] = &mut ask_data[DISCRIMINATOR_LEN..];
-                            let ask_slab = Slab::from_bytes_mut(ask_bytes)?;
-                            log_match_header("insert asks limit", ask_slab);
-                            let leaf = LeafNode::new(
-                                order.order_id,
-                                order.user,
-                                remaining_qty,
-                                fee,
-                                current_time,
-                            );
-                            let order_index = ask_slab.insert_leaf(&leaf)?;
-                            msg!(
-                                "ME: Added limit SELL to asks at index={}, qty={}",
-                                order_index,
-                                remaining_qty
-                            );
+        drop(event_queue);
+        drop(bid_data);
+        drop(ask_data);
+        let fill_price = (result.filled_qty > 0)
+            .then(|| (result.filled_notional / result.filled_qty as u128) as u64);
+        Self::refresh_mark_price(ctx, fill_price, current_time)
+    }
+
+    /// Feed the current best bid/ask, and the average price of the last placement if it
+    /// traded, into the market's mark price and premium samples.
+    fn refresh_mark_price(
+        ctx: &mut crate::instructions::process_order::ProcessOrder<'_>,
+        fill_price: Option<u64>,
+        now: i64,
+    ) -> Result<()> {
+        let (best_bid, best_ask) = {
+            let bid_account_info = ctx.bids.to_account_info();
+            let ask_account_info = ctx.asks.to_account_info();
+            let mut bid_data = bid_account_info.try_borrow_mut_data()?;
+            let mut ask_data = ask_account_info.try_borrow_mut_data()?;
+            (
+                best_resting_price(Slab::from_bytes_mut(&mut bid_data[DISCRIMINATOR_LEN..])?, Side::Buy),
+                best_resting_price(Slab::from_bytes_mut(&mut ask_data[DISCRIMINATOR_LEN..])?, Side::Sell),
+            )
+        };
+        let mark_price = ctx.market.refresh_mark_price(best_bid, best_ask, fill_price, now)?;
+        msg!("ME: mark price={} best_bid={:?} best_ask={:?}", mark_price, best_bid, best_ask);
+        Ok(())
+    }
+
+    /// Core of `process_place_order` over plain slabs, for testability.
+    /// Every quantity that neither fills nor rests is pushed as an `Out` event so the
+    /// owner's reserved margin is released.
+    pub fn place_order_core(
+        bids: &mut Slab,
+        asks: &mut Slab,
+        order: &Order,
+        event_queue: &mut EventQueue,
+        caps: &mut ReduceOnlyCaps,
+        params: MatchParams,
+    ) -> Result<PlaceResult> {
+        let MatchParams { tick_size, band, now_secs } = params;
+        let (maker_book, maker_side, own_book) = match order.side {
+            Side::Buy => (asks, Side::Sell, bids),
+            Side::Sell => (bids, Side::Buy, asks),
+        };
+
+        let mut order = order.clone();
+        let mut outcome = PlaceOutcome::Rested;
+        let mut expired_makers = Vec::new();
+        let rejected = |outcome, order: &Order, event_queue: &mut EventQueue, expired_makers| -> Result<PlaceResult> {
+            push_out(event_queue, order, order.qty, now_secs)?;
+            Ok(PlaceResult {
+                outcome,
+                order_id: order.order_id,
+                limit_price: order.limit_price,
+                filled_qty: 0,
+                filled_notional: 0,
+                rested_qty: 0,
+                cancelled_qty: order.qty,
+                self_trades: Vec::new(),
+                expired_makers,
+                reduce_only_pulled: Vec::new(),
+                outside_band: Vec::new(),
+            })
+        };
+
+        if order.is_expired(now_secs) {
+            return rejected(PlaceOutcome::Expired, &order, event_queue, expired_makers);
+        }
+
+        if order.order_type.is_post_only() {
+            // an expired maker at the top must not make a post-only order cross
+            expired_makers = remove_expired_top(maker_book, maker_side, event_queue, now_secs)?;
+            if let Some(best) = best_resting_price(maker_book, maker_side) {
+                if crosses(order.side, order.limit_price, best) {
+                    let slid = match (order.order_type, order.side) {
+                        (OrderType::PostOnlySlide, Side::Buy) => {
+                            best.checked_sub(tick_size.max(1)).filter(|p| *p > 0)
                         }
-                    }
-                }
-                OrderType::Market => {
-                    msg!(
-                        "ME: Market {:?} partially filled, remaining {} cancelled",
-                        order.side,
-                        remaining_qty
-                    );
+                        (OrderType::PostOnlySlide, Side::Sell) => best.checked_add(tick_size.max(1)),
+                        _ => None,
+                    };
+                    let Some(price) = slid else {
+                        return rejected(PlaceOutcome::PostOnlyRejected, &order, event_queue, expired_makers);
+                    };
+                    order.limit_price = price;
+                    order.order_id = LeafNode::reprice_key(order.order_id, price);
+                    outcome = PlaceOutcome::PostOnlyRepriced;
                 }
             }
         }
 
-        Ok(())
+        if order.order_type == OrderType::FillOrKill
+            && fillable_qty(maker_book, &order, order.qty, caps, band, now_secs) < order.qty
+        {
+            return rejected(PlaceOutcome::FillOrKillRejected, &order, event_queue, expired_makers);
+        }
+
+        let (remaining_qty, filled_qty, filled_notional, self_trades, taker_cancelled, reduce_only_pulled, outside_band) =
+            if order.order_type.is_post_only() {
+                (order.qty, 0, 0, Vec::new(), false, Vec::new(), Vec::new())
+            } else {
+                let matched =
+                    match_against_book_core(maker_book, &order, event_queue, caps, MatchingType::Normal, band, now_secs)?;
+                expired_makers.extend(matched.expired_makers);
+                (
+                    matched.remaining_qty,
+                    matched.filled_qty,
+                    matched.filled_notional,
+                    matched.self_trades,
+                    matched.taker_cancelled,
+                    matched.reduce_only_pulled,
+                    matched.outside_band,
+                )
+            };
+
+        let mut result = PlaceResult {
+            outcome,
+            order_id: order.order_id,
+            limit_price: order.limit_price,
+            filled_qty,
+            filled_notional,
+            rested_qty: 0,
+            // qty dropped by decrement-both self-trade prevention
+            cancelled_qty: order.qty - filled_qty - remaining_qty,
+            self_trades,
+            expired_makers,
+            reduce_only_pulled,
+            outside_band,
+        };
+        if remaining_qty == 0 {
+            result.outcome = if result.cancelled_qty == 0 {
+                PlaceOutcome::Filled
+            } else {
+                PlaceOutcome::RemainderCancelled
+            };
+            return Ok(result);
+        }
+
+        match order.order_type {
+            // a remainder that self-trade prevention cancelled never rests; a reduce-only one
+            // rests flagged, to be capped at the owner's position whenever it trades
+            order_type if order_type.rests() && !taker_cancelled => {
+                let leaf = LeafNode::new(order.order_id, order.user, remaining_qty, order.fee_tier, now_secs)
+                    .with_client_order_id(order.client_order_id)
+                    .with_expires_at(order.expires_at)
+                    .with_reduce_only(order.reduce_only);
+                let order_index = own_book.insert_leaf(&leaf)?;
+                msg!(
+                    "ME: Added {:?} {:?} at index={}, qty={}",
+                    order.order_type,
+                    order.side,
+                    order_index,
+                    remaining_qty
+                );
+                result.rested_qty = remaining_qty;
+            }
+            // FOK only gets here if the depth check passed, so for it this is defensive
+            _ => {
+                push_out(event_queue, &order, remaining_qty, now_secs)?;
+                result.outcome = PlaceOutcome::RemainderCancelled;
+                result.cancelled_qty += remaining_qty;
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn process_cancel_by_client_id<'info>(
+        ctx: &mut crate::instructions::process_order::ProcessOrder<'info>,
+        cancel: CancelByClientId,
+    ) -> Result<()> {
+        let resolved = {
+            let book_info = match cancel.side {
+                Side::Buy => ctx.bids.to_account_info(),
+                Side::Sell => ctx.asks.to_account_info(),
+            };
+            let mut book_data = book_info.try_borrow_mut_data()?;
+            let book = Slab::from_bytes_mut(&mut book_data[DISCRIMINATOR_LEN..])?;
+            resolve_client_order_id(book, &cancel)
+        };
+        match resolved {
+            Ok(cancel_order) => Self::process_cancel_order(ctx, cancel_order),
+            Err(err) if err == error!(PerpError::OrderNotFound) => {
+                msg!("ME: Cancel {:?} rejected for client_order_id={}: {}", cancel.side, cancel.client_order_id, err);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
     }
 
     pub fn process_cancel_order<'info>(
         ctx: &mut crate::instructions::process_order::ProcessOrder<'info>,
         cancel_order: CancelOrder,
     ) -> Result<()> {
-        let removed_leaf = match cancel_order.side {
-            Side::Buy => {
-                let bid_account_info = ctx.bids.to_account_info();
-                let mut bid_data = bid_account_info.try_borrow_mut_data()?;
-                let bid_bytes: &mut [u8] = &mut bid_data[DISCRIMINATOR_LEN..];
-                let bid_slab = Slab::from_bytes_mut(bid_bytes)?;
-                let order_index = bid_slab
-                    .find_by_key(cancel_order.order_id)
-                    .ok_or(PerpError::OrderNotFound)?;
-                msg!("ME: Cancel BUY, removing order at index={}", order_index);
-                bid_slab.remove_leaf(order_index)?
-            }
-            Side::Sell => {
-                let ask_account_info = ctx.asks.to_account_info();
-                let mut ask_data = ask_account_info.try_borrow_mut_data()?;
-                let ask_bytes: &mut [u8] = &mut ask_data[DISCRIMINATOR_LEN..];
-                let ask_slab = Slab::from_bytes_mut(ask_bytes)?;
-                let order_index = ask_slab
-                    .find_by_key(cancel_order.order_id)
-                    .ok_or(PerpError::OrderNotFound)?;
-                msg!("ME: Cancel SELL, removing order at index={}", order_index);
-                ask_slab.remove_leaf(order_index)?
+        let now = Clock::get()?.unix_timestamp;
+        let book_info = match cancel_order.side {
+            Side::Buy => ctx.bids.to_account_info(),
+            Side::Sell => ctx.asks.to_account_info(),
+        };
+        let mut book_data = book_info.try_borrow_mut_data()?;
+        let book = Slab::from_bytes_mut(&mut book_data[DISCRIMINATOR_LEN..])?;
+        let mut event_queue = ctx.event_queue.load_mut()?;
+
+        let removed_leaf = match cancel_against_book_core(book, &cancel_order, &mut event_queue, now) {
+            Ok(leaf) => leaf,
+            // already filled, or not the caller's order: drop the request instead of stalling the crank
+            Err(err)
+                if err == error!(PerpError::OrderNotFound)
+                    || err == error!(PerpError::Unauthorized) =>
+            {
+                msg!("ME: Cancel {:?} rejected for key={}: {}", cancel_order.side, cancel_order.order_id, err);
+                return Ok(());
             }
+            Err(err) => return Err(err),
         };
 
         msg!(
@@ -150,10 +409,27 @@ impl MatchingEngine {
             removed_leaf.price()
         );
 
-        Ok(())
+        emit!(OrderCancelled {
+            market: ctx.market.key(),
+            order_id: removed_leaf.key,
+            owner: cancel_order.user,
+            side: cancel_order.side,
+            price: removed_leaf.price(),
+            removed_qty: removed_leaf.quantity,
+            client_order_id: removed_leaf.client_order_id(),
+        });
+
+        drop(event_queue);
+        drop(book_data);
+        Self::refresh_mark_price(ctx, None, now)
     }
 }
 
+/// Tell the position crank that `qty` of `order` will never trade.
+pub(crate) fn push_out(event_queue: &mut EventQueue, order: &Order, qty: u64, now_secs: i64) -> Result<()> {
+    event_queue.push(&taker_out(order, qty, now_secs))
+}
+
 fn log_match_header(label: &str, slab: &Slab) {
     msg!(
         "ME: {} => leaf_count={} bump_index={} free_head={} root={}",
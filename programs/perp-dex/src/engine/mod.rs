@@ -3,10 +3,14 @@ pub mod matching_engine;
 pub mod position_manager;
 pub mod risk_engine;
 pub mod funding;
+pub mod oracle;
+pub mod mark_price;
 
 pub use matching_against::*;
 pub use matching_engine::*;
 pub use position_manager::*;
 pub use risk_engine::*;
 pub use funding::*;
+pub use oracle::*;
+pub use mark_price::*;
 
@@ -0,0 +1,146 @@
+use anchor_lang::prelude::*;
+use bytemuck::{Pod, Zeroable};
+
+use crate::{MarketState, PerpError, ORACLE_MAX_CONF_BPS, ORACLE_MAX_STALENESS_SECS, PRICE_DECIMALS};
+
+/// First word of every price account, as in Pyth price accounts.
+pub const ORACLE_MAGIC: u32 = 0xa1b2_c3d4;
+pub const ORACLE_STATUS_TRADING: u32 = 1;
+
+/// Pyth-style price account: an integer price and confidence interval scaled by
+/// `10^expo`, and the unix time they were published. Mock oracles for local tests
+/// use the same layout, written by `set_mark_price`.
+#[derive(Clone, Copy, Pod, Zeroable, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct PriceAccount {
+    pub magic: u32,
+    pub status: u32,
+    pub expo: i32,
+    pub _padding: u32,
+    pub price: i64,
+    pub conf: u64,
+    pub publish_time: i64,
+}
+
+impl PriceAccount {
+    pub const LEN: usize = core::mem::size_of::<PriceAccount>(); // = 40
+
+    pub fn load(data: &[u8]) -> Result<Self> {
+        require!(data.len() >= Self::LEN, PerpError::InvalidOracleAccount);
+        let account: PriceAccount = bytemuck::pod_read_unaligned(&data[..Self::LEN]);
+        require!(account.magic == ORACLE_MAGIC, PerpError::InvalidOracleAccount);
+        Ok(account)
+    }
+
+    /// The price in the program's scale, rejected if it is not trading, older than
+    /// `ORACLE_MAX_STALENESS_SECS` or its confidence is wider than `ORACLE_MAX_CONF_BPS`.
+    pub fn to_price(&self, now: i64) -> Result<OraclePrice> {
+        require!(
+            self.status == ORACLE_STATUS_TRADING && self.price > 0,
+            PerpError::InvalidOraclePrice
+        );
+        require!(
+            now.saturating_sub(self.publish_time) <= ORACLE_MAX_STALENESS_SECS,
+            PerpError::StaleOraclePrice
+        );
+
+        let price = rescale(self.price as u64, self.expo)?;
+        let conf = rescale(self.conf, self.expo)?;
+        require!(price > 0, PerpError::InvalidOraclePrice);
+        require!(
+            (conf as u128) * 10_000 <= (price as u128) * ORACLE_MAX_CONF_BPS as u128,
+            PerpError::OracleConfidenceTooWide
+        );
+
+        Ok(OraclePrice { price, conf, publish_time: self.publish_time })
+    }
+}
+
+/// A validated oracle reading with `PRICE_DECIMALS` decimals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OraclePrice {
+    pub price: u64,
+    pub conf: u64,
+    pub publish_time: i64,
+}
+
+/// `value * 10^expo` expressed with `PRICE_DECIMALS` decimals, rounded down.
+pub fn rescale(value: u64, expo: i32) -> Result<u64> {
+    let shift = expo + PRICE_DECIMALS;
+    let value = value as u128;
+    let scaled = if shift >= 0 {
+        10u128
+            .checked_pow(shift as u32)
+            .and_then(|factor| value.checked_mul(factor))
+            .ok_or(PerpError::MathOverflow)?
+    } else {
+        // past 10^38 every u64 rounds down to zero
+        10u128.checked_pow(shift.unsigned_abs()).map_or(0, |factor| value / factor)
+    };
+    Ok(u64::try_from(scaled).map_err(|_| PerpError::MathOverflow)?)
+}
+
+/// Read and validate the price account at `market.oracle_pubkey`.
+pub fn read_oracle(oracle: &AccountInfo, market: &MarketState, now: i64) -> Result<OraclePrice> {
+    require_keys_eq!(oracle.key(), market.oracle_pubkey, PerpError::OracleMismatch);
+    PriceAccount::load(&oracle.try_borrow_data()?)?.to_price(now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(price: i64, conf: u64, expo: i32, publish_time: i64) -> PriceAccount {
+        PriceAccount {
+            magic: ORACLE_MAGIC,
+            status: ORACLE_STATUS_TRADING,
+            expo,
+            _padding: 0,
+            price,
+            conf,
+            publish_time,
+        }
+    }
+
+    #[test]
+    fn test_rescale_to_program_decimals() {
+        assert_eq!(rescale(6_512_345_678_901, -8).unwrap(), 65_123_456_789); // Pyth's usual expo
+        assert_eq!(rescale(100, -2).unwrap(), 1_000_000);
+        assert_eq!(rescale(42, 0).unwrap(), 42_000_000);
+        assert_eq!(rescale(u64::MAX, -60).unwrap(), 0);
+        assert!(rescale(u64::MAX, 2).is_err());
+    }
+
+    #[test]
+    fn test_load_parses_layout_and_checks_magic() {
+        let original = account(10_000, 5, -2, 1_000);
+        let mut data = bytemuck::bytes_of(&original).to_vec();
+        data.extend_from_slice(&[0u8; 8]); // trailing bytes are ignored
+        assert_eq!(PriceAccount::load(&data).unwrap(), original);
+
+        data[0] ^= 1;
+        assert_eq!(PriceAccount::load(&data).unwrap_err(), error!(PerpError::InvalidOracleAccount));
+        assert_eq!(PriceAccount::load(&data[..PriceAccount::LEN - 1]).unwrap_err(), error!(PerpError::InvalidOracleAccount));
+    }
+
+    #[test]
+    fn test_to_price_rejects_stale_wide_and_halted_prices() {
+        let now = 10_000;
+        let fresh = account(10_000, 10, -2, now - ORACLE_MAX_STALENESS_SECS);
+        assert_eq!(
+            fresh.to_price(now).unwrap(),
+            OraclePrice { price: 100_000_000, conf: 100_000, publish_time: now - ORACLE_MAX_STALENESS_SECS }
+        );
+
+        let stale = account(10_000, 10, -2, now - ORACLE_MAX_STALENESS_SECS - 1);
+        assert_eq!(stale.to_price(now).unwrap_err(), error!(PerpError::StaleOraclePrice));
+
+        let wide = account(10_000, 10_000 * ORACLE_MAX_CONF_BPS as u64 / 10_000 + 1, -2, now);
+        assert_eq!(wide.to_price(now).unwrap_err(), error!(PerpError::OracleConfidenceTooWide));
+
+        let mut halted = account(10_000, 10, -2, now);
+        halted.status = 0;
+        assert_eq!(halted.to_price(now).unwrap_err(), error!(PerpError::InvalidOraclePrice));
+        assert_eq!(account(-1, 0, -2, now).to_price(now).unwrap_err(), error!(PerpError::InvalidOraclePrice));
+    }
+}
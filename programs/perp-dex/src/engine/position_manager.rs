@@ -1,7 +1,8 @@
 use anchor_lang::prelude::*;
 
 use crate::{
-    FUNDING_SCALE,
+    funding_payment,
+    FeeRates,
     MatchedOrder,
     PerpError,
     Side,
@@ -12,16 +13,107 @@ use crate::{
 
 pub struct PositionManager;
 
+/// What one fill cost its owner in fees.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeeCharge {
+    pub charged: i128,         // debited from collateral, negative for a maker rebate
+    pub referrer_reward: u64,  // owed to the taker's referrer, kept in the vault until claimed
+}
+
 impl PositionManager {
-    /// Apply a fill event using current chain time.
+    /// Apply a fill event using current chain time, charging fees at `fee_rates`.
     pub fn apply_fill(
         market: &mut MarketState,
         position: &mut Position,
         user_collateral: &mut UserCollateral,
         event: MatchedOrder,
-    ) -> Result<()> {
+        fee_rates: FeeRates,
+    ) -> Result<FeeCharge> {
         let now_secs = Clock::get()?.unix_timestamp;
-        Self::apply_fill_with_time(market, position, user_collateral, event, now_secs)
+        Self::apply_fill_with_time(market, position, user_collateral, event, fee_rates, now_secs)
+    }
+
+    /// Charge the position the funding accrued since it last settled: the payment comes
+    /// out of realized PnL and the collateral margining the position (its isolated margin
+    /// if it has one), and `last_cum_funding` catches up to the market.
+    /// Returns the amount paid, negative when the position received funding.
+    ///
+    /// The only place funding reaches a position: fills, liquidation, withdraw and the
+    /// `settle_funding` crank all go through here.
+    pub fn settle_funding(
+        market: &MarketState,
+        position: &mut Position,
+        user_collateral: &mut UserCollateral,
+    ) -> Result<i128> {
+        let delta_funding = market
+            .cum_funding
+            .checked_sub(position.last_cum_funding)
+            .ok_or(PerpError::MathOverflow)?;
+
+        let funding_payment = funding_payment(delta_funding, position.base_position)?;
+
+        let new_realized_after_funding = (position.realized_pnl as i128)
+            .checked_sub(funding_payment)
+            .ok_or(PerpError::MathOverflow)?;
+
+        position.realized_pnl = i64::try_from(new_realized_after_funding)
+            .map_err(|_| PerpError::MathOverflow)?;
+
+        let margin = if position.is_isolated {
+            &mut position.isolated_margin
+        } else {
+            &mut user_collateral.collateral_amount
+        };
+        *margin = margin
+            .checked_sub(funding_payment) // paying funding if funding_payment > 0, receiving if < 0
+            .ok_or(PerpError::MathOverflow)?;
+
+        position.last_cum_funding = market.cum_funding;
+        Ok(funding_payment)
+    }
+
+    /// Debit the taker fee, rounded up, or credit the maker rebate, rounded down, on the
+    /// fill's notional at the owner's `fee_rates`, and record it in the market's fee
+    /// ledger. A referred taker is let off `referee_discount_bps` of the fee and
+    /// `referrer_share_bps` of it is set aside for the referrer, both rounded down.
+    /// A maker filled by a liquidation earns no rebate, as the liquidated taker pays no fee.
+    pub fn charge_fee(
+        market: &mut MarketState,
+        user_collateral: &mut UserCollateral,
+        event: &MatchedOrder,
+        fee_rates: FeeRates,
+    ) -> Result<FeeCharge> {
+        let notional = event.notional()?;
+        let mut referrer_reward = 0;
+
+        let charged = if event.is_maker {
+            let rebate_bps = if event.liquidation { 0 } else { fee_rates.maker_rebate_bps };
+            let rebate = notional
+                .checked_mul(rebate_bps as u128)
+                .ok_or(PerpError::MathOverflow)?
+                / 10_000;
+            let rebate = u64::try_from(rebate).map_err(|_| PerpError::MathOverflow)?;
+            market.fees.record_maker_rebate(rebate)?;
+            -(rebate as i128)
+        } else {
+            let fee = notional
+                .checked_mul(fee_rates.taker_fee_bps as u128)
+                .ok_or(PerpError::MathOverflow)?
+                .div_ceil(10_000);
+            let fee = u64::try_from(fee).map_err(|_| PerpError::MathOverflow)?;
+            let share_of_fee = |bps: u16| (fee as u128 * bps as u128 / 10_000) as u64;
+            let fee = fee - share_of_fee(fee_rates.referee_discount_bps);
+            referrer_reward = share_of_fee(fee_rates.referrer_share_bps);
+            market.fees.record_taker_fee(fee)?;
+            market.fees.record_referral_reward(referrer_reward)?;
+            fee as i128
+        };
+
+        user_collateral.collateral_amount = user_collateral
+            .collateral_amount
+            .checked_sub(charged)
+            .ok_or(PerpError::MathOverflow)?;
+        Ok(FeeCharge { charged, referrer_reward })
     }
 
     /// Apply a fill event with explicit timestamp. Used for testing.
@@ -30,9 +122,65 @@ impl PositionManager {
         position: &mut Position,
         user_collateral: &mut UserCollateral,
         event: MatchedOrder,
+        fee_rates: FeeRates,
         now_secs: i64,
+    ) -> Result<FeeCharge> {
+        let released = user_collateral.release_margin(position.market, event.order_id, event.fill_qty)?;
+        if event.reduce_only {
+            position.settle_reduce_only(event.fill_qty);
+        }
+        let collateral_before = user_collateral.collateral_amount;
+        let fee = Self::charge_fee(market, user_collateral, &event, fee_rates)?;
+        Self::update_position(market, position, user_collateral, &event, now_secs)?;
+        if position.is_isolated {
+            Self::isolate_fill(position, user_collateral, collateral_before, released)?;
+        }
+        user_collateral.track_position(position.market, position.cross_base_position())?;
+        Ok(fee)
+    }
+
+    /// Move what a fill did to the collateral onto an isolated position instead, along
+    /// with the order margin it released. A closed position hands a positive remainder
+    /// back to the collateral; a deficit stays with the position.
+    fn isolate_fill(
+        position: &mut Position,
+        user_collateral: &mut UserCollateral,
+        collateral_before: i128,
+        released: u128,
     ) -> Result<()> {
-        let pos_qty = position.base_position as i64;
+        let released = i128::try_from(released).map_err(|_| PerpError::MathOverflow)?;
+        let fill_delta = user_collateral
+            .collateral_amount
+            .checked_sub(collateral_before)
+            .ok_or(PerpError::MathOverflow)?;
+        user_collateral.collateral_amount = collateral_before
+            .checked_sub(released)
+            .ok_or(PerpError::MathOverflow)?;
+        position.isolated_margin = position
+            .isolated_margin
+            .checked_add(fill_delta)
+            .and_then(|v| v.checked_add(released))
+            .ok_or(PerpError::MathOverflow)?;
+
+        if position.base_position == 0 && position.isolated_margin > 0 {
+            user_collateral.collateral_amount = user_collateral
+                .collateral_amount
+                .checked_add(position.isolated_margin)
+                .ok_or(PerpError::MathOverflow)?;
+            position.isolated_margin = 0;
+        }
+        Ok(())
+    }
+
+    /// Fold the fill into the position, realizing PnL on the part it closes.
+    fn update_position(
+        market: &MarketState,
+        position: &mut Position,
+        user_collateral: &mut UserCollateral,
+        event: &MatchedOrder,
+        now_secs: i64,
+    ) -> Result<()> {
+        let pos_qty = position.base_position;
 
         let fill_qty = if event.side == Side::Buy {
             event.fill_qty as i64
@@ -52,30 +200,7 @@ impl PositionManager {
             return Ok(());
         }
 
-        let pos_last_cum = position.last_cum_funding;
-        let delta_funding = market
-            .cum_funding
-            .checked_sub(pos_last_cum)
-            .ok_or(PerpError::MathOverflow)?;
-
-        let funding_payment = (delta_funding as i128)
-            .checked_mul(pos_qty as i128)
-            .and_then(|v| v.checked_div(FUNDING_SCALE))
-            .ok_or(PerpError::MathOverflow)?;
-
-        let new_realized_after_funding = (position.realized_pnl as i128)
-            .checked_sub(funding_payment)
-            .ok_or(PerpError::MathOverflow)?;
-
-        position.realized_pnl = i64::try_from(new_realized_after_funding)
-            .map_err(|_| PerpError::MathOverflow)?;
-
-        user_collateral.collateral_amount = user_collateral
-            .collateral_amount
-            .checked_sub(funding_payment) // paying funding if funding_payment > 0, receiving if < 0
-            .ok_or(PerpError::MathOverflow)?;
-
-        position.last_cum_funding = market.cum_funding;
+        Self::settle_funding(market, position, user_collateral)?;
 
         if pos_qty.signum() == fill_qty.signum() {
             let old_abs = pos_qty.abs() as i128;
@@ -228,9 +353,15 @@ impl PositionManager {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{OrderStatus, OrderType, Side};
+    use crate::{
+        EventKind, FeeLedger, FeeTier, GlobalConfig, OrderStatus, OrderType, PositionRisk, ReduceOnlyCaps, RiskEngine, Side,
+        FUNDING_SCALE,
+    };
     use anchor_lang::prelude::Pubkey;
 
+    /// PnL tests see only PnL; fee tests pass their own rates.
+    const NO_FEES: FeeRates = FeeRates { taker_fee_bps: 0, maker_rebate_bps: 0, referrer_share_bps: 0, referee_discount_bps: 0 };
+
     fn user_pubkey() -> Pubkey {
         Pubkey::new_from_array([1u8; 32])
     }
@@ -253,6 +384,9 @@ mod tests {
             entry_price: entry,
             realized_pnl: 0,
             last_cum_funding,
+            is_isolated: false,
+            isolated_margin: 0,
+            reduce_only_pending: 0,
             initial_margin: 0,
             leverage: 0,
             flags: 0,
@@ -262,38 +396,11 @@ mod tests {
     }
 
     fn make_market(cum_funding: i64) -> MarketState {
-        MarketState {
-            symbol: "BTC".to_string(),
-            authority: Pubkey::default(),
-            oracle_pubkey: Pubkey::default(),
-            last_oracle_price: 100_000,
-            last_oracle_ts: 0,
-            bid: Pubkey::default(),
-            asks: Pubkey::default(),
-            im_bps: 500,
-            mm_bps: 250,
-            taker_fee_bps: 5,
-            maker_fee_bps: 2,
-            liquidator_share_bps: 50,
-            liq_penalty_bps: 500,
-            oracle_band_bps: 100,
-            cum_funding,
-            last_funding_ts: 0,
-            max_funding_rate: 0,
-            funding_interval_secs: 3600,
-            tick_size: 1,
-            step_size: 1,
-            min_order_notional: 1000,
-            bump: 0,
-        }
+        MarketState { cum_funding, ..MarketState::test_default() }
     }
 
     fn make_collateral(owner: Pubkey, amount: i128) -> UserCollateral {
-        UserCollateral {
-            owner,
-            collateral_amount: amount,
-            last_updated: 0,
-        }
+        UserCollateral { owner, collateral_amount: amount, ..UserCollateral::test_default() }
     }
 
     fn make_fill_event(side: Side, price: u64, qty: u64, user: [u8; 32]) -> MatchedOrder {
@@ -305,6 +412,11 @@ mod tests {
             fill_qty: qty,
             side,
             timestamp: 1000,
+            kind: EventKind::Fill,
+            client_order_id: 0,
+            reduce_only: false,
+            liquidation: false,
+            maker_fee_tier: 0,
         }
     }
 
@@ -317,13 +429,14 @@ mod tests {
         let mut collateral = make_collateral(user, 10_000);
 
         let ev = make_fill_event(Side::Buy, 100, 10, user.to_bytes());
-        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, 1000).unwrap();
+        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, NO_FEES, 1000).unwrap();
 
         assert_eq!(position.base_position, 10);
         assert_eq!(position.entry_price, 100);
         assert_eq!(position.realized_pnl, 0);
         assert_eq!(position.last_cum_funding, 0);
         assert_eq!(collateral.collateral_amount, 10_000);
+        assert_eq!(collateral.open_positions, vec![market_pk]);
     }
 
     #[test]
@@ -335,7 +448,7 @@ mod tests {
         let mut collateral = make_collateral(user, 10_000);
 
         let ev = make_fill_event(Side::Buy, 120, 5, user.to_bytes());
-        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, 1000).unwrap();
+        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, NO_FEES, 1000).unwrap();
 
         assert_eq!(position.base_position, 15);
         assert_eq!(position.entry_price, 106);
@@ -352,7 +465,7 @@ mod tests {
         let mut collateral = make_collateral(user, 10_000);
 
         let ev = make_fill_event(Side::Sell, 120, 5, user.to_bytes());
-        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, 1000).unwrap();
+        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, NO_FEES, 1000).unwrap();
 
         assert_eq!(position.base_position, 5);
         assert_eq!(position.entry_price, 100);
@@ -367,13 +480,390 @@ mod tests {
         let mut market = make_market(0);
         let mut position = make_position(user, market_pk, 10, 100, 0);
         let mut collateral = make_collateral(user, 10_000);
+        collateral.open_positions.push(market_pk);
 
         let ev = make_fill_event(Side::Sell, 120, 10, user.to_bytes());
-        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, 1000).unwrap();
+        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, NO_FEES, 1000).unwrap();
 
         assert_eq!(position.base_position, 0);
         assert_eq!(position.entry_price, 0);
         assert_eq!(position.realized_pnl, 200);
         assert_eq!(collateral.collateral_amount, 10_200);
+        assert!(collateral.open_positions.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_settle_funding_charges_idle_positions() {
+        let user = user_pubkey();
+        let market = make_market(3 * FUNDING_SCALE as i64);
+
+        // longs pay positive funding, shorts receive it
+        let mut long = make_position(user, market_pubkey(), 10, 100, FUNDING_SCALE as i64);
+        let mut collateral = make_collateral(user, 10_000);
+        assert_eq!(PositionManager::settle_funding(&market, &mut long, &mut collateral).unwrap(), 20);
+        assert_eq!((long.realized_pnl, long.last_cum_funding), (-20, market.cum_funding));
+        assert_eq!(collateral.collateral_amount, 9_980);
+
+        // settling again is a no-op
+        assert_eq!(PositionManager::settle_funding(&market, &mut long, &mut collateral).unwrap(), 0);
+        assert_eq!(collateral.collateral_amount, 9_980);
+
+        let mut short = make_position(user, market_pubkey(), -10, 100, 0);
+        let mut collateral = make_collateral(user, 10_000);
+        assert_eq!(PositionManager::settle_funding(&market, &mut short, &mut collateral).unwrap(), -30);
+        assert_eq!(collateral.collateral_amount, 10_030);
+
+        let mut flat = make_position(user, market_pubkey(), 0, 0, 0);
+        assert_eq!(PositionManager::settle_funding(&market, &mut flat, &mut collateral).unwrap(), 0);
+        assert_eq!(flat.last_cum_funding, market.cum_funding);
+    }
+
+    #[test]
+    fn test_fill_after_settle_does_not_charge_twice() {
+        let user = user_pubkey();
+        let mut market = make_market(2 * FUNDING_SCALE as i64);
+        let mut position = make_position(user, market_pubkey(), 10, 100, 0);
+        let mut collateral = make_collateral(user, 10_000);
+
+        PositionManager::settle_funding(&market, &mut position, &mut collateral).unwrap();
+        let ev = make_fill_event(Side::Buy, 100, 5, user.to_bytes());
+        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, NO_FEES, 1000).unwrap();
+        assert_eq!(collateral.collateral_amount, 9_980);
+        assert_eq!(position.realized_pnl, -20);
+    }
+
+    #[test]
+    fn test_fill_charges_taker_and_rebates_maker() {
+        let user = user_pubkey();
+        let mut market = make_market(0);
+        let rates = FeeRates { taker_fee_bps: 5, maker_rebate_bps: 2, ..NO_FEES };
+
+        // 10 @ 1_001 = 10_010 notional: taker pays 5.005 rounded up, maker gets 2.002 rounded down
+        let mut taker = make_position(user, market_pubkey(), 0, 0, 0);
+        let mut taker_collateral = make_collateral(user, 10_000);
+        let ev = make_fill_event(Side::Buy, 1_001, 10, user.to_bytes());
+        PositionManager::apply_fill_with_time(&mut market, &mut taker, &mut taker_collateral, ev, rates, 1000).unwrap();
+        assert_eq!(taker_collateral.collateral_amount, 10_000 - 6);
+        assert_eq!(taker.realized_pnl, 0);
+
+        let mut maker = make_position(user, market_pubkey(), 0, 0, 0);
+        let mut maker_collateral = make_collateral(user, 10_000);
+        let mut ev = make_fill_event(Side::Sell, 1_001, 10, user.to_bytes());
+        ev.is_maker = true;
+        PositionManager::apply_fill_with_time(&mut market, &mut maker, &mut maker_collateral, ev, rates, 1000).unwrap();
+        assert_eq!(maker_collateral.collateral_amount, 10_000 + 2);
+
+        assert_eq!(market.fees, FeeLedger { taker_fees: 6, maker_rebates: 2, referral_rewards: 0, swept: 0 });
+        assert_eq!(market.fees.sweepable(), 4);
+    }
+
+    #[test]
+    fn test_maker_filled_by_a_liquidation_earns_no_rebate() {
+        let user = user_pubkey();
+        let mut market = make_market(0);
+        let rates = FeeRates { taker_fee_bps: 5, maker_rebate_bps: 2, ..NO_FEES };
+
+        let mut maker = make_position(user, market_pubkey(), 0, 0, 0);
+        let mut maker_collateral = make_collateral(user, 10_000);
+        let mut ev = make_fill_event(Side::Sell, 1_001, 10, user.to_bytes());
+        ev.is_maker = true;
+        ev.liquidation = true;
+        let fee = PositionManager::apply_fill_with_time(&mut market, &mut maker, &mut maker_collateral, ev, rates, 1000).unwrap();
+        assert_eq!(fee, FeeCharge::default());
+        assert_eq!(maker_collateral.collateral_amount, 10_000);
+        assert_eq!(maker.base_position, -10);
+        assert_eq!(market.fees, FeeLedger::default());
+    }
+
+    fn global_config(fee_tiers: Vec<FeeTier>) -> GlobalConfig {
+        GlobalConfig {
+            authority: Pubkey::default(),
+            vault_quote: Pubkey::default(),
+            insurance_fund: Pubkey::default(),
+            fee_pool: Pubkey::default(),
+            trading_paused: false,
+            fee_tiers,
+            referrer_share_bps: 0,
+            referee_discount_bps: 0,
+            bump: 0,
+        }
+    }
+
+    fn tier(min_volume: u64, taker_fee_bps: u16, maker_rebate_bps: u16) -> FeeTier {
+        FeeTier { min_volume, taker_fee_bps, maker_rebate_bps }
+    }
+
+    #[test]
+    fn test_fee_rates_follow_the_volume_tier() {
+        let mut market = make_market(0);
+        market.taker_fee_bps = 5;
+        market.maker_fee_bps = 2;
+
+        // no table: every user pays the market's fees
+        let untiered = global_config(vec![]);
+        assert_eq!(untiered.fee_rates(&market, u64::MAX, false), FeeRates::from_market(&market));
+
+        let config = global_config(vec![tier(0, 10, 0), tier(1_000, 8, 1), tier(50_000, 5, 3)]);
+        assert_eq!(config.fee_tier(999), 0);
+        assert_eq!(config.fee_tier(1_000), 1);
+        assert_eq!(config.fee_tier(u64::MAX), 2);
+        assert_eq!(config.fee_rates(&market, 60_000, false), FeeRates { taker_fee_bps: 5, maker_rebate_bps: 3, ..NO_FEES });
+
+        // a maker is charged at the tier its order was stamped with, whatever its volume now
+        assert_eq!(config.fee_rates_at_tier(&market, 2, false), config.fee_rates(&market, 60_000, false));
+        assert_eq!(config.fee_rates_at_tier(&market, 3, false), config.fee_rates_at_tier(&market, 2, false));
+
+        // the same 100_000 notional fill costs a tier-0 taker 100 and a tier-2 taker 50
+        for (volume, expected) in [(0, 100), (50_000, 50)] {
+            let mut position = make_position(user_pubkey(), market_pubkey(), 0, 0, 0);
+            let mut collateral = make_collateral(user_pubkey(), 10_000);
+            let ev = make_fill_event(Side::Buy, 1_000, 100, user_pubkey().to_bytes());
+            let rates = config.fee_rates(&market, volume, false);
+            PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, rates, 1000).unwrap();
+            assert_eq!(collateral.collateral_amount, 10_000 - expected);
+        }
+    }
+
+    #[test]
+    fn test_referred_taker_fee_is_split() {
+        let mut market = make_market(0);
+        let mut config = global_config(vec![tier(0, 10, 2)]);
+        config.referrer_share_bps = 2_000;
+        config.referee_discount_bps = 1_000;
+        let rates = config.fee_rates(&market, 0, true);
+
+        // 100_000 notional at 10 bps = 100 fee: 10 waived, 20 to the referrer, 70 to the protocol
+        let mut position = make_position(user_pubkey(), market_pubkey(), 0, 0, 0);
+        let mut collateral = make_collateral(user_pubkey(), 10_000);
+        let ev = make_fill_event(Side::Buy, 1_000, 100, user_pubkey().to_bytes());
+        let fee = PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, rates, 1000).unwrap();
+        assert_eq!(fee, FeeCharge { charged: 90, referrer_reward: 20 });
+        assert_eq!(collateral.collateral_amount, 10_000 - 90);
+        assert_eq!(market.fees.sweepable(), 70);
+
+        // makers keep their rebate and earn the referrer nothing
+        let mut ev = make_fill_event(Side::Sell, 1_000, 100, user_pubkey().to_bytes());
+        ev.is_maker = true;
+        let fee = PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, rates, 1000).unwrap();
+        assert_eq!(fee, FeeCharge { charged: -20, referrer_reward: 0 });
+
+        assert!(GlobalConfig::validate_referral_params(6_000, 4_000).is_ok());
+        assert_eq!(
+            GlobalConfig::validate_referral_params(6_000, 4_001).unwrap_err(),
+            error!(PerpError::InvalidReferralParams)
+        );
+    }
+
+    #[test]
+    fn test_referred_fills_never_leave_the_ledger_short() {
+        let mut config = global_config(vec![tier(0, 10, 2)]);
+        config.referrer_share_bps = 2_000;
+        config.referee_discount_bps = 6_000;
+        assert!(GlobalConfig::rebates_covered(&config.fee_tiers, 2_000, 6_000));
+        assert!(!GlobalConfig::rebates_covered(&config.fee_tiers, 2_001, 6_000));
+
+        let mut market = make_market(0);
+        let taker_rates = config.fee_rates(&market, 0, true);
+        let maker_rates = config.fee_rates(&market, 0, false);
+        let mut taker = make_position(user_pubkey(), market_pubkey(), 0, 0, 0);
+        let mut maker = make_position(user_pubkey(), market_pubkey(), 0, 0, 0);
+        let mut collateral = make_collateral(user_pubkey(), 1_000_000_000);
+        for price in [1, 7, 999, 1_003, 123_457] {
+            for qty in 1..=60 {
+                let ev = make_fill_event(Side::Buy, price, qty, user_pubkey().to_bytes());
+                PositionManager::apply_fill_with_time(&mut market, &mut taker, &mut collateral, ev, taker_rates, 1000).unwrap();
+                let mut ev = make_fill_event(Side::Sell, price, qty, user_pubkey().to_bytes());
+                ev.is_maker = true;
+                PositionManager::apply_fill_with_time(&mut market, &mut maker, &mut collateral, ev, maker_rates, 1000).unwrap();
+
+                let fees = market.fees;
+                assert!(fees.taker_fees >= fees.maker_rebates + fees.referral_rewards, "{price} x {qty}: {fees:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_fee_tier_validation() {
+        assert!(GlobalConfig::validate_fee_tiers(&[]).is_ok());
+        assert!(GlobalConfig::validate_fee_tiers(&[tier(0, 10, 2), tier(1_000, 5, 5)]).is_ok());
+        assert!(GlobalConfig::rebates_covered(&[tier(0, 10, 2), tier(1_000, 5, 4)], 0, 2_000));
+        assert!(!GlobalConfig::rebates_covered(&[tier(0, 10, 2), tier(1_000, 5, 4)], 1, 2_000)); // the referrer's share too
+        assert!(!GlobalConfig::rebates_covered(&[tier(0, 10, 2), tier(1_000, 5, 5)], 0, 2_000));
+
+        let invalid = [
+            vec![tier(1, 10, 2)],                   // must cover zero volume
+            vec![tier(0, 10, 2), tier(0, 5, 1)],    // thresholds strictly increase
+            vec![tier(0, 2, 3)],                    // rebate above the fee
+            vec![tier(0, 10, 2), tier(1_000, 6, 6), tier(2_000, 5, 5)], // top rebate above the base fee
+            vec![tier(0, 10_001, 0)],
+            (0..=crate::MAX_FEE_TIERS as u64).map(|i| tier(i, 1, 0)).collect(),
+        ];
+        for tiers in invalid {
+            assert_eq!(GlobalConfig::validate_fee_tiers(&tiers).unwrap_err(), error!(PerpError::InvalidFeeTiers));
+        }
+    }
+
+    #[test]
+    fn test_isolated_fills_settle_against_the_isolated_margin() {
+        let user = user_pubkey();
+        let market_pk = market_pubkey();
+        let mut market = make_market(0);
+        let mut position = make_position(user, market_pk, 0, 0, 0);
+        position.is_isolated = true;
+        let mut collateral = make_collateral(user, 10_000);
+        let rates = FeeRates { taker_fee_bps: 10, ..NO_FEES };
+
+        // the order's reserved margin moves into the position as it fills; the fee comes out of it
+        let ev = make_fill_event(Side::Buy, 100, 10, user.to_bytes());
+        collateral.reserve_margin(market_pk, ev.order_id, 10, 300).unwrap();
+        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, rates, 1000).unwrap();
+        assert_eq!(position.isolated_margin, 300 - 1);
+        assert_eq!(collateral.collateral_amount, 10_000 - 300);
+        assert_eq!(collateral.reserved_margin, 0);
+        assert!(collateral.open_positions.is_empty());
+
+        // funding is paid from the isolated margin too
+        market.cum_funding = 2 * FUNDING_SCALE as i64;
+        PositionManager::settle_funding(&market, &mut position, &mut collateral).unwrap();
+        assert_eq!(position.isolated_margin, 299 - 20);
+        assert_eq!(collateral.collateral_amount, 9_700);
+
+        // closing at a 100 loss hands the rest back to the collateral
+        let ev = make_fill_event(Side::Sell, 90, 10, user.to_bytes());
+        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, NO_FEES, 1000).unwrap();
+        assert_eq!(position.base_position, 0);
+        assert_eq!(position.isolated_margin, 0);
+        assert_eq!(collateral.collateral_amount, 9_700 + 279 - 100);
+    }
+
+    #[test]
+    fn test_isolated_health_ignores_the_rest_of_the_account() {
+        let market = make_market(0);
+        let mut position = make_position(user_pubkey(), market_pubkey(), -10, 90_000, 0);
+        position.is_isolated = true;
+        let risk = PositionRisk::new(&market, &position).unwrap();
+
+        // short 10 from 90_000 at a 100_000 mark: -100_000 PnL, 2.5% of 1_000_000 maintenance
+        assert_eq!(RiskEngine::account_health_isolated(200_000, &risk).unwrap(), 200_000 - 100_000 - 25_000);
+        assert!(RiskEngine::account_health_isolated(124_999, &risk).unwrap() < 0);
+    }
+
+    #[test]
+    fn test_fee_ledger_sweeps_only_the_net() {
+        let mut ledger = FeeLedger { taker_fees: 10, maker_rebates: 4, referral_rewards: 0, swept: 0 };
+        ledger.record_sweep(5).unwrap();
+        assert_eq!(ledger.sweepable(), 1);
+        assert!(ledger.record_sweep(2).is_err());
+
+        // a maker cranked before the taker whose fee funds its rebate puts the ledger behind
+        ledger.record_maker_rebate(3).unwrap();
+        assert_eq!(ledger.sweepable(), 0);
+        ledger.record_taker_fee(5).unwrap();
+        assert_eq!(ledger.sweepable(), 3);
+    }
+
+    /// Deterministic xorshift so the fuzz cases are reproducible.
+    fn xorshift(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    /// Random value spread over every magnitude up to `max`, so small and huge sizes are both hit.
+    fn any_magnitude(seed: &mut u64, max: i64) -> i64 {
+        let bits = xorshift(seed) % 64;
+        let value = (xorshift(seed) >> (64 - bits.max(1))) as i64 % max;
+        if xorshift(seed) % 2 == 0 { value } else { -value }
+    }
+
+    #[test]
+    fn test_fuzz_funding_settlement_agrees_across_paths() {
+        let user = user_pubkey();
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        for _ in 0..2_000 {
+            let base = match any_magnitude(&mut seed, 1 << 40) {
+                0 => 1, // a flat position takes the open branch of apply_fill
+                base => base,
+            };
+            let last_cum = any_magnitude(&mut seed, 1 << 40);
+            let cum = any_magnitude(&mut seed, 1 << 40);
+            let expected = (cum as i128 - last_cum as i128) * base as i128 / FUNDING_SCALE;
+
+            // settle_funding, as called by withdraw, liquidation and the settle crank
+            let market = make_market(cum);
+            let mut settled = make_position(user, market_pubkey(), base, 100, last_cum);
+            let mut settled_collateral = make_collateral(user, 1 << 80);
+            let paid = PositionManager::settle_funding(&market, &mut settled, &mut settled_collateral).unwrap();
+            assert_eq!(paid, expected, "base={base} last_cum={last_cum} cum={cum}");
+
+            // a fill that adds to the position at its entry only moves collateral by the funding
+            let mut market = make_market(cum);
+            let mut filled = make_position(user, market_pubkey(), base, 100, last_cum);
+            let mut filled_collateral = make_collateral(user, 1 << 80);
+            let side = if base > 0 { Side::Buy } else { Side::Sell };
+            let ev = make_fill_event(side, 100, 1, user.to_bytes());
+            PositionManager::apply_fill_with_time(&mut market, &mut filled, &mut filled_collateral, ev, NO_FEES, 1000).unwrap();
+
+            assert_eq!(filled_collateral.collateral_amount, settled_collateral.collateral_amount);
+            assert_eq!(filled.realized_pnl, settled.realized_pnl);
+            assert_eq!(filled.last_cum_funding, settled.last_cum_funding);
+        }
+    }
+
+    #[test]
+    fn test_funding_payment_does_not_overflow_where_i64_did() {
+        // the old i64 liquidation path overflowed on this product
+        let (delta, base) = (i64::MAX / 2, 1_000_000);
+        assert!(delta.checked_mul(base).is_none());
+        assert_eq!(funding_payment(delta, base).unwrap(), delta as i128 * base as i128 / FUNDING_SCALE);
+        assert_eq!(funding_payment(-delta, base).unwrap(), -(delta as i128 * base as i128 / FUNDING_SCALE));
+    }
+
+    #[test]
+    fn test_reducible_qty_only_on_opposite_side() {
+        let user = user_pubkey();
+        let long = make_position(user, market_pubkey(), 7, 100, 0);
+        assert_eq!(long.reducible_qty(Side::Sell), 7);
+        assert_eq!(long.reducible_qty(Side::Buy), 0);
+
+        let short = make_position(user, market_pubkey(), -4, 100, 0);
+        assert_eq!(short.reducible_qty(Side::Buy), 4);
+        assert_eq!(short.reducible_qty(Side::Sell), 0);
+
+        let flat = make_position(user, market_pubkey(), 0, 0, 0);
+        assert_eq!(flat.reducible_qty(Side::Buy), 0);
+        assert_eq!(flat.reducible_qty(Side::Sell), 0);
+    }
+
+    #[test]
+    fn test_queued_reduce_only_orders_cannot_flip_the_position() {
+        let user = user_pubkey();
+        let market_pk = market_pubkey();
+        let mut market = make_market(0);
+        let mut position = make_position(user, market_pk, 10, 100, 0);
+        let mut collateral = make_collateral(user, 1_000_000);
+
+        // a crank matches 10 of reduce-only sells before either fill reaches the position
+        let mut caps = ReduceOnlyCaps::from_positions([&position]);
+        caps.consume(&user.to_bytes(), Side::Sell, 10);
+        assert_eq!(caps.available(&user.to_bytes(), Side::Sell), 0);
+        position.add_reduce_only_pending(caps.matched(&user.to_bytes()));
+        assert_eq!(position.reducible_qty(Side::Sell), 0);
+        assert_eq!(ReduceOnlyCaps::from_positions([&position]).available(&user.to_bytes(), Side::Sell), 0);
+
+        let mut ev = make_fill_event(Side::Sell, 110, 6, user.to_bytes());
+        ev.reduce_only = true;
+        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, NO_FEES, 1000).unwrap();
+        assert_eq!(position.base_position, 4);
+        assert_eq!(position.reduce_only_pending, 4);
+        assert_eq!(position.reducible_qty(Side::Sell), 0);
+
+        let mut ev = make_fill_event(Side::Sell, 110, 4, user.to_bytes());
+        ev.reduce_only = true;
+        PositionManager::apply_fill_with_time(&mut market, &mut position, &mut collateral, ev, NO_FEES, 1000).unwrap();
+        assert_eq!(position.base_position, 0);
+        assert_eq!(position.reduce_only_pending, 0);
+        assert_eq!(ReduceOnlyCaps::from_positions([&position]).available(&user.to_bytes(), Side::Sell), 0);
+    }
+}
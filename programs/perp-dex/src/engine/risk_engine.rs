@@ -1,9 +1,70 @@
 use anchor_lang::prelude::*;
 
-use crate::{PerpError, Ratio};
+use crate::{funding_payment, MarketState, PerpError, Position, Ratio};
+
+/// One position's contribution to cross-margin health, priced at its market's mark.
+#[derive(Clone, Copy, Debug)]
+pub struct PositionRisk {
+    pub qty_signed: i128,
+    pub entry_price: u128,
+    pub mark_price: u128,
+    pub mmr: Ratio,
+    pub pending_funding: i128,  // accrued but not yet settled into collateral; positive = owed
+}
+
+impl PositionRisk {
+    /// Price `position` at the market's current mark, counting funding it has not settled.
+    pub fn new(market: &MarketState, position: &Position) -> Result<Self> {
+        let delta_funding = market
+            .cum_funding
+            .checked_sub(position.last_cum_funding)
+            .ok_or(PerpError::MathOverflow)?;
+        Ok(Self {
+            qty_signed: position.base_position as i128,
+            entry_price: position.entry_price as u128,
+            mark_price: market.get_mark_price()?,
+            mmr: Ratio::from_bps(market.mm_bps),
+            pending_funding: funding_payment(delta_funding, position.base_position)?,
+        })
+    }
+}
+
+/// Where the equity left after a liquidation's penalty goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LiquidationSettlement {
+    pub insurance_draw: u64, // bad debt the insurance fund covers
+    pub payout: u64,         // paid out to the user's token account
+    pub remaining: i128,     // left as the account's (or isolated position's) equity
+}
 
 pub struct RiskEngine;
 impl RiskEngine {
+    /// Settle `equity` after a liquidation. A shortfall is bad debt for the insurance fund only
+    /// when nothing else backs it: the position was isolated, or no cross positions remain.
+    /// While cross positions remain, a shortfall stays on the account as negative collateral
+    /// for their PnL to cover, and a surplus stays as their collateral. Once none remain,
+    /// everything but `reserved_margin` for open orders is paid out.
+    pub fn settle_liquidation(
+        equity: i128,
+        isolated: bool,
+        cross_positions_left: bool,
+        reserved_margin: u128,
+    ) -> Result<LiquidationSettlement> {
+        let mut settlement = LiquidationSettlement { insurance_draw: 0, payout: 0, remaining: equity };
+        if equity < 0 {
+            if isolated || !cross_positions_left {
+                settlement.insurance_draw = u64::try_from(equity.unsigned_abs()).map_err(|_| PerpError::MathOverflow)?;
+                settlement.remaining = 0;
+            }
+        } else if !isolated && !cross_positions_left {
+            let reserved = i128::try_from(reserved_margin).map_err(|_| PerpError::MathOverflow)?;
+            let payout = equity.saturating_sub(reserved).max(0);
+            settlement.payout = u64::try_from(payout).map_err(|_| PerpError::MathOverflow)?;
+            settlement.remaining = equity - payout;
+        }
+        Ok(settlement)
+    }
+
     pub fn notional(qty_signed: i128, price: u128) -> Result<u128> {
         if qty_signed == 0 || price == 0 {
             return Ok(0);
@@ -70,6 +131,27 @@ impl RiskEngine {
     }
 
 
+    /// Health of a cross-margin account: collateral plus, over every position, unrealized
+    /// PnL less unsettled funding and maintenance margin.
+    pub fn account_health_cross(collateral: i128, positions: &[PositionRisk]) -> Result<i128> {
+        positions.iter().try_fold(collateral, |health, position| {
+            let unrealized_pnl =
+                RiskEngine::unrealized_pnl(position.qty_signed, position.entry_price, position.mark_price)?;
+            let maintenance_margin =
+                RiskEngine::maintenance_margin(position.qty_signed, position.mark_price, position.mmr)? as i128;
+            health
+                .checked_add(unrealized_pnl)
+                .and_then(|v| v.checked_sub(position.pending_funding))
+                .and_then(|v| v.checked_sub(maintenance_margin))
+                .ok_or_else(|| error!(PerpError::MathOverflow))
+        })
+    }
+
+    /// Health of an isolated position: only its own margin backs it.
+    pub fn account_health_isolated(isolated_margin: i128, position: &PositionRisk) -> Result<i128> {
+        RiskEngine::account_health_cross(isolated_margin, core::slice::from_ref(position))
+    }
+
     pub fn is_liquidatable_single(
         collateral: i128,
         qty_signed: i128,
@@ -88,3 +170,66 @@ impl RiskEngine {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn risk(qty_signed: i128, entry_price: u128, mark_price: u128, mm_bps: u16) -> PositionRisk {
+        PositionRisk { qty_signed, entry_price, mark_price, mmr: Ratio::from_bps(mm_bps), pending_funding: 0 }
+    }
+
+    #[test]
+    fn test_cross_health_of_one_position_matches_single() {
+        let position = risk(-7, 1_000, 1_100, 500);
+        assert_eq!(
+            RiskEngine::account_health_cross(2_000, &[position]).unwrap(),
+            RiskEngine::account_health_single(2_000, -7, 1_000, 1_100, Ratio::from_bps(500)).unwrap()
+        );
+        assert_eq!(RiskEngine::account_health_cross(2_000, &[]).unwrap(), 2_000);
+    }
+
+    #[test]
+    fn test_cross_health_nets_every_market() {
+        // long 10 up 100 each, 5% MM on 11_000; short 4 up 50 each, 10% MM on 2_200
+        let winner = risk(10, 1_000, 1_100, 500);
+        let mut loser = risk(-4, 500, 550, 1_000);
+        let health = RiskEngine::account_health_cross(100, &[winner, loser]).unwrap();
+        assert_eq!(health, 100 + 1_000 - 550 - 200 - 220);
+
+        // the loser alone is underwater; the winner's profit carries it
+        assert!(RiskEngine::account_health_cross(100, &[loser]).unwrap() < 0);
+
+        loser.pending_funding = 200;
+        assert_eq!(RiskEngine::account_health_cross(100, &[winner, loser]).unwrap(), health - 200);
+    }
+
+    #[test]
+    fn test_cross_shortfall_stays_on_the_account_while_positions_remain() {
+        // two cross markets: the short closes 400 under water on 100 of collateral
+        let winner = risk(10, 1_000, 1_100, 500);
+        let equity = 100 - 400;
+        let settlement = RiskEngine::settle_liquidation(equity, false, true, 0).unwrap();
+        assert_eq!(settlement, LiquidationSettlement { insurance_draw: 0, payout: 0, remaining: -300 });
+        // the long's profit still backs the debt
+        assert_eq!(RiskEngine::account_health_cross(settlement.remaining, &[winner]).unwrap(), -300 + 1_000 - 550);
+
+        // once the last cross position is closed, what is still owed is bad debt
+        let settlement = RiskEngine::settle_liquidation(-50, false, false, 0).unwrap();
+        assert_eq!(settlement, LiquidationSettlement { insurance_draw: 50, payout: 0, remaining: 0 });
+
+        // an isolated position never leaves its shortfall on the account
+        let settlement = RiskEngine::settle_liquidation(-50, true, true, 0).unwrap();
+        assert_eq!(settlement, LiquidationSettlement { insurance_draw: 50, payout: 0, remaining: 0 });
+
+        // a surplus is paid out only with no cross positions left, less reserved margin
+        assert_eq!(
+            RiskEngine::settle_liquidation(500, false, true, 100).unwrap(),
+            LiquidationSettlement { insurance_draw: 0, payout: 0, remaining: 500 }
+        );
+        assert_eq!(
+            RiskEngine::settle_liquidation(500, false, false, 100).unwrap(),
+            LiquidationSettlement { insurance_draw: 0, payout: 400, remaining: 100 }
+        );
+    }
+}
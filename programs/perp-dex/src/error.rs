@@ -57,6 +57,64 @@ pub enum PerpError {
     #[msg("Event at head of queue is for another user")]
     EventNotForUser,
     #[msg("InvalidVaultQuoteMint ")]
-    InvalidVaultQuoteMint 
+    InvalidVaultQuoteMint,
+    #[msg("Order does not belong to this market")]
+    MarketMismatch,
+    #[msg("Order id already exists in the book")]
+    DuplicateOrderId,
+    #[msg("Too many open orders")]
+    TooManyOpenOrders,
+    #[msg("Too many markets with open positions")]
+    TooManyOpenPositions,
+    #[msg("Remaining accounts must be the market and position of every other open position, in order")]
+    MarginAccountsMismatch,
+    #[msg("Position is not in isolated margin mode")]
+    NotIsolated,
+    #[msg("Margin mode can only change while the position is flat")]
+    PositionNotFlat,
+    #[msg("Reduce-only order would not reduce the position")]
+    ReduceOnlyWouldIncrease,
+    #[msg("Reduce-only orders cannot be post-only")]
+    ReduceOnlyPostOnly,
+    #[msg("Trigger book is full")]
+    TriggerBookFull,
+    #[msg("Invalid trigger order")]
+    InvalidTriggerOrder,
+    #[msg("Expiry must be in the future and only set on orders that rest")]
+    InvalidExpiry,
+    #[msg("Order quantity must be greater than zero")]
+    OrderQtyZero,
+    #[msg("Order quantity is not a multiple of the market step size")]
+    QtyNotMultipleOfStep,
+    #[msg("Limit price must be greater than zero")]
+    InvalidLimitPrice,
+    #[msg("Price is not a multiple of the market tick size")]
+    PriceNotMultipleOfTick,
+    #[msg("Limit price is outside the oracle price band")]
+    PriceOutsideOracleBand,
+    #[msg("Oracle account does not match the market")]
+    OracleMismatch,
+    #[msg("Oracle account is not a price account")]
+    InvalidOracleAccount,
+    #[msg("Oracle price is stale")]
+    StaleOraclePrice,
+    #[msg("Oracle confidence interval is too wide")]
+    OracleConfidenceTooWide,
+    #[msg("Funding parameters are out of bounds")]
+    InvalidFundingParams,
+    #[msg("Fee tiers must start at zero volume, increase, and rebate no more than they charge")]
+    InvalidFeeTiers,
+    #[msg("Referral shares must not exceed the taker fee")]
+    InvalidReferralParams,
+    #[msg("Referrer is already set")]
+    ReferrerAlreadySet,
+    #[msg("Referrer must be another user with trading stats")]
+    InvalidReferrer,
+    #[msg("Referrer stats account does not match the user's referrer")]
+    ReferrerMismatch,
+    #[msg("No referral rewards to claim")]
+    NothingToClaim,
+    #[msg("Position account must be writable")]
+    PositionNotWritable,
 }
 
@@ -0,0 +1,113 @@
+use anchor_lang::prelude::*;
+
+use crate::{MarketState, PerpError, Position, PositionManager, PositionRisk, RiskEngine, UserCollateral};
+
+/// Moves collateral into or out of an isolated position.
+#[derive(Accounts)]
+pub struct AdjustIsolatedMargin<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    /// CHECK: price account at `market.oracle_pubkey`, parsed by the oracle adapter
+    #[account(address = market.oracle_pubkey @ PerpError::OracleMismatch)]
+    pub oracle: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"position", market.symbol.as_bytes(), user.key().as_ref()],
+        bump,
+        constraint = user_position.is_isolated @ PerpError::NotIsolated
+    )]
+    pub user_position: Account<'info, Position>,
+    #[account(
+        mut,
+        seeds = [b"user_colletral", user.key().as_ref()],
+        bump
+    )]
+    pub user_colletral: Account<'info, UserCollateral>,
+}
+
+impl<'info> AdjustIsolatedMargin<'info> {
+    /// Allocate `amount` of free collateral to the position. `remaining_accounts` holds the
+    /// market, oracle and position of each of the user's cross positions, which must stay
+    /// healthy without it.
+    pub fn add(&mut self, amount: u64, remaining_accounts: &[AccountInfo]) -> Result<()> {
+        require!(amount > 0, PerpError::InvalidAmount);
+        let amount = amount as i128;
+        let user_colletral = &mut self.user_colletral;
+        require!(user_colletral.free_collateral()? >= amount, PerpError::InsufficientCollateral);
+
+        let now = Clock::get()?.unix_timestamp;
+        let positions = user_colletral.cross_position_risks(self.market.key(), remaining_accounts, now)?;
+        let free_after = user_colletral.free_collateral()?
+            .checked_sub(amount)
+            .ok_or(PerpError::MathOverflow)?;
+        require!(
+            RiskEngine::account_health_cross(free_after, &positions)? >= 0,
+            PerpError::InsufficientCollateral
+        );
+
+        user_colletral.collateral_amount = user_colletral
+            .collateral_amount
+            .checked_sub(amount)
+            .ok_or(PerpError::MathOverflow)?;
+        let position = &mut self.user_position;
+        position.isolated_margin = position
+            .isolated_margin
+            .checked_add(amount)
+            .ok_or(PerpError::MathOverflow)?;
+        self.emit_adjusted(amount)
+    }
+
+    /// Return `amount` of the position's margin to the collateral, keeping the position
+    /// above maintenance on what is left.
+    pub fn remove(&mut self, amount: u64) -> Result<()> {
+        require!(amount > 0, PerpError::InvalidAmount);
+        let amount = amount as i128;
+        let market = &mut self.market;
+        let position = &mut self.user_position;
+
+        // funding owed comes out of the isolated margin before measuring what is spare
+        PositionManager::settle_funding(market, position, &mut self.user_colletral)?;
+        market.refresh_oracle(&self.oracle, Clock::get()?.unix_timestamp)?;
+
+        let margin_after = position
+            .isolated_margin
+            .checked_sub(amount)
+            .ok_or(PerpError::MathOverflow)?;
+        require!(margin_after >= 0, PerpError::InsufficientCollateral);
+        if position.base_position != 0 {
+            let health = RiskEngine::account_health_isolated(margin_after, &PositionRisk::new(market, position)?)?;
+            require!(health > 0, PerpError::WithdrawWouldLiquidate);
+        }
+
+        position.isolated_margin = margin_after;
+        let user_colletral = &mut self.user_colletral;
+        user_colletral.collateral_amount = user_colletral
+            .collateral_amount
+            .checked_add(amount)
+            .ok_or(PerpError::MathOverflow)?;
+        self.emit_adjusted(-amount)
+    }
+
+    fn emit_adjusted(&self, delta: i128) -> Result<()> {
+        emit!(IsolatedMarginAdjusted {
+            market: self.market.key(),
+            owner: self.user.key(),
+            delta,
+            isolated_margin: self.user_position.isolated_margin,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct IsolatedMarginAdjusted {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub delta: i128,          // positive when margin was added
+    pub isolated_margin: i128,
+}
@@ -0,0 +1,42 @@
+use anchor_lang::prelude::*;
+
+use crate::{PerpError, UserStats};
+
+#[derive(Accounts)]
+pub struct BindReferrer<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"user_stats", user.key().as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    /// The referrer must have deposited, which opened their stats.
+    #[account(
+        seeds = [b"user_stats", referrer_stats.owner.as_ref()],
+        bump = referrer_stats.bump,
+        constraint = referrer_stats.owner != user.key() @ PerpError::InvalidReferrer
+    )]
+    pub referrer_stats: Account<'info, UserStats>,
+}
+
+impl<'info> BindReferrer<'info> {
+    /// Record who referred the user. Can only be done once; from then on the user's taker
+    /// fees are discounted and part of them accrues to the referrer.
+    pub fn process(&mut self) -> Result<()> {
+        let referrer = self.referrer_stats.owner;
+        self.user_stats.bind_referrer(referrer)?;
+
+        emit!(ReferrerBound {
+            user: self.user.key(),
+            referrer,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct ReferrerBound {
+    pub user: Pubkey,
+    pub referrer: Pubkey,
+}
@@ -0,0 +1,98 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    BidAsk, MarketState, PerpError, Side, Slab, UserCollateral, cancel_all_in_book_core,
+    DISCRIMINATOR_LEN, MAX_CANCEL_PER_CALL,
+};
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct CancelAllParams {
+    pub side: Option<Side>,       // None cancels on both sides
+    pub min_price: Option<u64>,
+    pub max_price: Option<u64>,
+}
+
+#[derive(Accounts)]
+pub struct CancelAllOrders<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    #[account(mut, seeds = [b"bids", market.symbol.as_bytes()], bump)]
+    pub bids: AccountLoader<'info, BidAsk>,
+    #[account(mut, seeds = [b"asks", market.symbol.as_bytes()], bump)]
+    pub asks: AccountLoader<'info, BidAsk>,
+    #[account(
+        mut,
+        seeds = [b"user_colletral", user.key().as_ref()],
+        bump
+    )]
+    pub user_colletral: Account<'info, UserCollateral>,
+}
+
+impl<'info> CancelAllOrders<'info> {
+    /// Pull the signer's resting orders straight off the book, at most `MAX_CANCEL_PER_CALL`
+    /// per call. Call again while `AllOrdersCancelled.has_more` is set.
+    pub fn process(&mut self, params: CancelAllParams) -> Result<()> {
+        let min_price = params.min_price.unwrap_or(0);
+        let max_price = params.max_price.unwrap_or(u64::MAX);
+        require!(min_price <= max_price, PerpError::InvalidAmount);
+
+        let owner = self.user.key().to_bytes();
+        let market_key = self.market.key();
+        let mut budget = MAX_CANCEL_PER_CALL;
+        let mut has_more = false;
+        let mut cancelled: u16 = 0;
+        let mut removed_qty: u64 = 0;
+        let mut margin_released: u128 = 0;
+
+        for side in [Side::Buy, Side::Sell] {
+            if params.side.is_some_and(|s| s != side) {
+                continue;
+            }
+            let book_info = match side {
+                Side::Buy => self.bids.to_account_info(),
+                Side::Sell => self.asks.to_account_info(),
+            };
+            let mut book_data = book_info.try_borrow_mut_data()?;
+            let book = Slab::from_bytes_mut(&mut book_data[DISCRIMINATOR_LEN..])?;
+
+            let (removed, more) = cancel_all_in_book_core(book, &owner, min_price, max_price, budget)?;
+            has_more |= more;
+            budget -= removed.len();
+
+            for leaf in removed {
+                margin_released = margin_released
+                    .checked_add(self.user_colletral.release_margin(market_key, leaf.key, leaf.quantity)?)
+                    .ok_or(PerpError::MathOverflow)?;
+                removed_qty = removed_qty
+                    .checked_add(leaf.quantity)
+                    .ok_or(PerpError::MathOverflow)?;
+                cancelled += 1;
+            }
+        }
+
+        msg!("CancelAll: removed {} orders, has_more={}", cancelled, has_more);
+        emit!(AllOrdersCancelled {
+            market: market_key,
+            owner: self.user.key(),
+            cancelled,
+            removed_qty,
+            margin_released: u64::try_from(margin_released).map_err(|_| PerpError::MathOverflow)?,
+            has_more,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct AllOrdersCancelled {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub cancelled: u16,
+    pub removed_qty: u64,
+    pub margin_released: u64,
+    pub has_more: bool,     // matching orders remain; call again to continue
+}
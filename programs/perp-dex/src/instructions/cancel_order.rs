@@ -0,0 +1,79 @@
+use anchor_lang::prelude::*;
+
+use crate::{CancelByClientId, CancelOrder, LeafNode, MarketState, PerpError, RequestQueue, RequestType, Side};
+
+#[derive(Accounts)]
+pub struct CancelOrderIns<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    #[account(
+        mut,
+        seeds = [b"request_queue", market.symbol.as_bytes()],
+        bump
+    )]
+    pub request_queue: AccountLoader<'info, RequestQueue>,
+}
+
+impl<'info> CancelOrderIns<'info> {
+    /// Queue a cancel for one of the signer's resting orders.
+    /// Ownership is checked against the leaf when the request is processed.
+    pub fn process(&mut self, order_id: u128, side: Side) -> Result<()> {
+        let request_queue = &mut self.request_queue.load_mut()?;
+        require!(request_queue.count < request_queue.capacity, PerpError::QueueFull);
+
+        request_queue.push(&RequestType::Cancel(CancelOrder {
+            order_id,
+            user: self.user.key(),
+            side,
+        }))?;
+
+        msg!("CancelOrder: queued cancel for order_id={}", order_id);
+        Ok(())
+    }
+
+    /// Queue a cancel for the signer's resting order tagged with `client_order_id`.
+    /// The book is searched when the request is processed.
+    pub fn process_by_client_id(&mut self, client_order_id: u64, side: Side) -> Result<()> {
+        let request_queue = &mut self.request_queue.load_mut()?;
+        require!(request_queue.count < request_queue.capacity, PerpError::QueueFull);
+
+        request_queue.push(&RequestType::CancelByClientId(CancelByClientId {
+            client_order_id,
+            user: self.user.key(),
+            side,
+        }))?;
+
+        msg!("CancelOrder: queued cancel for client_order_id={}", client_order_id);
+        Ok(())
+    }
+}
+
+#[event]
+pub struct OrderCancelled {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub owner: Pubkey,
+    pub side: Side,
+    pub price: u64,
+    pub removed_qty: u64,
+    pub client_order_id: u64,
+}
+
+impl OrderCancelled {
+    /// Event for a resting order the matching loop pulled, priced outside the oracle band.
+    pub fn removed(market: Pubkey, leaf: &LeafNode, side: Side) -> Self {
+        Self {
+            market,
+            order_id: leaf.key,
+            owner: Pubkey::new_from_array(leaf.owner),
+            side,
+            price: leaf.price(),
+            removed_qty: leaf.quantity,
+            client_order_id: leaf.client_order_id(),
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use anchor_lang::prelude::*;
+
+use crate::{MarketState, TriggerBook, UserCollateral};
+
+#[derive(Accounts)]
+pub struct CancelTriggerOrder<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    #[account(
+        mut,
+        seeds = [b"trigger_book", market.symbol.as_bytes()],
+        bump = trigger_book.bump
+    )]
+    pub trigger_book: Account<'info, TriggerBook>,
+    #[account(
+        mut,
+        seeds = [b"user_colletral", user.key().as_ref()],
+        bump
+    )]
+    pub user_colletral: Account<'info, UserCollateral>,
+}
+
+impl<'info> CancelTriggerOrder<'info> {
+    pub fn process(&mut self, order_id: u128) -> Result<()> {
+        let trigger = self.trigger_book.remove(self.user.key(), order_id)?;
+        self.user_colletral
+            .release_margin(self.market.key(), trigger.order_id, trigger.qty)?;
+
+        emit!(TriggerOrderRemoved {
+            market: self.market.key(),
+            order_id,
+            owner: trigger.owner,
+            expired: false,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct TriggerOrderRemoved {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub owner: Pubkey,
+    pub expired: bool,      // false when cancelled by the owner
+}
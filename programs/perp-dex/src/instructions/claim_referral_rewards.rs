@@ -0,0 +1,68 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+
+use crate::{GlobalConfig, PerpError, UserStats};
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"user_stats", user.key().as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+    #[account(
+        seeds = [b"global_config"],
+        bump = global_config.bump
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+    #[account(
+        mut,
+        address = global_config.vault_quote
+    )]
+    pub vault_quote: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = user_ata.mint == vault_quote.mint @ PerpError::InvalidVaultQuoteMint,
+        constraint = user_ata.owner == user.key() @ PerpError::Unauthorized
+    )]
+    pub user_ata: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> ClaimReferralRewards<'info> {
+    /// Pay every referral reward accrued so far from `vault_quote`, where the fee path left
+    /// them when it charged the referred takers.
+    pub fn process(&mut self) -> Result<()> {
+        let amount = self.user_stats.take_referral_rewards()?;
+
+        let signer_seeds: &[&[u8]] = &[b"global_config", &[self.global_config.bump]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                Transfer {
+                    from: self.vault_quote.to_account_info(),
+                    to: self.user_ata.to_account_info(),
+                    authority: self.global_config.to_account_info(),
+                },
+                &[signer_seeds],
+            ),
+            amount,
+        )?;
+
+        emit!(ReferralRewardsClaimed {
+            referrer: self.user.key(),
+            amount,
+            total_claimed: self.user_stats.claimed_referral_rewards,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct ReferralRewardsClaimed {
+    pub referrer: Pubkey,
+    pub amount: u64,
+    pub total_claimed: u64,
+}
@@ -5,7 +5,7 @@ use anchor_spl::{
     token::{ self, Mint, Token, TokenAccount, Transfer},
 };
 
-use crate::{GlobalConfig, PerpError, UserCollateral, request_queue};
+use crate::{GlobalConfig, PerpError, UserCollateral, UserStats};
 
 #[derive(Accounts)]
 
@@ -42,6 +42,14 @@ pub struct DepositColletral <'info>{
         bump
     )]
     pub user_colletral : Account<'info,UserCollateral>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8+UserStats::INIT_SPACE,
+        seeds = [b"user_stats", user.key().as_ref()],
+        bump
+    )]
+    pub user_stats : Account<'info,UserStats>,
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub token_program: Program<'info, Token>,
@@ -50,7 +58,8 @@ pub struct DepositColletral <'info>{
 impl <'info> DepositColletral <'info>{
     pub fn process(
         &mut self,
-        amount:u64
+        amount:u64,
+        bumps: &DepositColletralBumps,
     )->Result<()>{
         require!(amount>0,crate::PerpError::InvalidAmount);
        
@@ -70,12 +79,20 @@ impl <'info> DepositColletral <'info>{
         // Update user collateral account
         let user_colletral = &mut self.user_colletral;
         user_colletral.owner = self.user.key();
-        let amount_i128 = i128::try_from(amount).map_err(|_|PerpError::MathOverflow)?;
+        let amount_i128 = i128::from(amount);
         user_colletral.collateral_amount = user_colletral.collateral_amount
             .checked_add(amount_i128)
             .ok_or(PerpError::MathOverflow)?;
         user_colletral.last_updated = Clock::get()?.unix_timestamp;
 
+        // first deposit opens the volume stats that fee tiers are keyed on
+        let user_stats = &mut self.user_stats;
+        if user_stats.owner == Pubkey::default() {
+            user_stats.owner = self.user.key();
+            user_stats.last_day = user_colletral.last_updated / crate::SECONDS_PER_DAY;
+            user_stats.bump = bumps.user_stats;
+        }
+
         emit!(DepositEvent{
             user: self.user.key(),
             amount,
@@ -4,7 +4,7 @@ use anchor_spl::{
     associated_token::AssociatedToken,
 };
 
-use crate::{ASK_SLAB_CAPACITY, BID_SLAB_CAPACITY, BidAsk, MarketParams, MarketState, PerpError, Slab};
+use crate::{ASK_SLAB_CAPACITY, BID_SLAB_CAPACITY, BidAsk, EventQueue, FundingParams, MarketParams, MarketState, PerpError, RequestQueue, Slab};
 
 const DISCRIMINATOR_LEN: usize = 8;
 
@@ -41,6 +41,24 @@ pub struct InitializeMarket<'info> {
     )]
     pub asks: AccountLoader<'info, BidAsk>,
 
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + RequestQueue::SIZE,
+        seeds = [b"request_queue", market_symbol.as_slice()],
+        bump
+    )]
+    pub request_queue: AccountLoader<'info, RequestQueue>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + EventQueue::SIZE,
+        seeds = [b"event_queue", market_symbol.as_slice()],
+        bump
+    )]
+    pub event_queue: AccountLoader<'info, EventQueue>,
+
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub token_program: Program<'info, Token>,
@@ -56,6 +74,13 @@ impl<'info> InitializeMarket<'info> {
         let symbol = String::from_utf8(market_symbol)
             .map_err(|_| PerpError::InvalidSymbol)?;
 
+        require!(params.tick_size > 0 && params.step_size > 0, PerpError::InvalidMarketConfig);
+        // rebates are paid out of taker fees
+        require!(
+            params.maker_rebate_bps <= params.taker_fee_bps && params.taker_fee_bps <= 10_000,
+            PerpError::InvalidMarketConfig
+        );
+
         let market = &mut self.market;
 
         require!(
@@ -64,6 +89,9 @@ impl<'info> InitializeMarket<'info> {
             PerpError::NotAuthorized
         );
 
+        // queues are created with the market; re-initialising wipes them along with the slabs
+        let queues_are_new = market.request_queue == Pubkey::default();
+
         let bid_account_info = &mut self.bids.to_account_info();
         let ask_account_info = &mut self.asks.to_account_info();
 
@@ -72,22 +100,39 @@ impl<'info> InitializeMarket<'info> {
         market.oracle_pubkey = params.oracle_pubkey;
         market.last_oracle_price = params.last_oracle_price;
         market.last_oracle_ts = params.last_oracle_ts;
+        // the books are wiped below, so the mark starts again from the oracle alone
+        market.book_mid_price = 0;
+        market.fill_price_ema = 0;
+        market.mark_price = u64::try_from(params.last_oracle_price).unwrap_or(0);
         market.im_bps = params.im_bps;
         market.mm_bps = params.mm_bps;
         market.oracle_band_bps = params.oracle_band_bps;
         market.taker_fee_bps = params.taker_fee_bps;
         market.maker_fee_bps = params.maker_rebate_bps;
+        // `fees` is left alone: unswept fees are still in the vault after a re-initialise
         market.liq_penalty_bps = params.liq_penalty_bps;
         market.liquidator_share_bps = params.liquidator_share_bps;
-        market.max_funding_rate = params.max_funding_rate;
         market.cum_funding = params.cum_funding;
         market.last_funding_ts = params.last_funding_ts;
-        market.funding_interval_secs = params.funding_interval_secs;
+        market.set_funding_params(FundingParams {
+            interest_rate_bps: params.interest_rate_bps,
+            premium_dampener_bps: params.premium_dampener_bps,
+            funding_period_secs: params.funding_period_secs,
+            funding_interval_secs: params.funding_interval_secs,
+            max_funding_rate: params.max_funding_rate,
+        })?;
+        let now = Clock::get()?.unix_timestamp;
+        market.premium_accumulator = 0;
+        market.last_premium = 0;
+        market.premium_sample_ts = now;
+        market.premium_window_start = now;
         market.tick_size = params.tick_size;
         market.step_size = params.step_size;
         market.min_order_notional = params.min_order_notional;
         market.bid = self.bids.key();
         market.asks = self.asks.key();
+        market.request_queue = self.request_queue.key();
+        market.event_queue = self.event_queue.key();
         market.bump = bump.market;
 
         msg!("INIT_MARKET: Starting bid slab initialization");
@@ -109,10 +154,21 @@ impl<'info> InitializeMarket<'info> {
             Slab::initialize(slab_data, ASK_SLAB_CAPACITY)?;
         }
 
+        msg!("INIT_MARKET: Initializing request and event queues");
+        if queues_are_new {
+            self.request_queue.load_init()?.init();
+            self.event_queue.load_init()?.init();
+        } else {
+            self.request_queue.load_mut()?.init();
+            self.event_queue.load_mut()?.init();
+        }
+
         emit!(MarketInitialized {
             market: market.key(),
             symbol: market.symbol.clone(),
             authority: self.authority.key(),
+            request_queue: self.request_queue.key(),
+            event_queue: self.event_queue.key(),
         });
 
         Ok(())
@@ -124,4 +180,6 @@ pub struct MarketInitialized {
     pub market: Pubkey,
     pub symbol: String,
     pub authority: Pubkey,
+    pub request_queue: Pubkey,
+    pub event_queue: Pubkey,
 }
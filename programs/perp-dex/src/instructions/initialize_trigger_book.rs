@@ -0,0 +1,33 @@
+use anchor_lang::prelude::*;
+
+use crate::{MarketState, PerpError, TriggerBook};
+
+#[derive(Accounts)]
+pub struct InitializeTriggerBook<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump,
+        constraint = market.authority == authority.key() @ PerpError::Unauthorized
+    )]
+    pub market: Account<'info, MarketState>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + TriggerBook::INIT_SPACE,
+        seeds = [b"trigger_book", market.symbol.as_bytes()],
+        bump
+    )]
+    pub trigger_book: Account<'info, TriggerBook>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> InitializeTriggerBook<'info> {
+    pub fn process(&mut self, bumps: &InitializeTriggerBookBumps) -> Result<()> {
+        self.trigger_book.market = self.market.key();
+        self.trigger_book.orders = Vec::new();
+        self.trigger_book.bump = bumps.trigger_book;
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@ use anchor_spl::{
     associated_token::AssociatedToken,
 };
 pub const MAX_REQUESTS: usize = 64; 
-use crate::{ EventQueue, GlobalConfig, PerpError, RequestQueue};
+use crate::{GlobalConfig, PerpError};
 
 #[derive(Accounts)]
 pub struct InitializeGlobalConfig<'info> {
@@ -51,25 +51,6 @@ pub struct InitializeGlobalConfig<'info> {
     )]
     pub fee_pool: Account<'info, TokenAccount>,
     
-    #[account(
-        init_if_needed,
-        payer = authority,
-        space = 8 + RequestQueue::SIZE,
-        seeds = [b"request_queue"],
-        bump
-    )]
-    pub request_queue: AccountLoader<'info, RequestQueue>,
-
-
-     #[account(
-        init_if_needed,
-        payer = authority,
-        space = 8+ EventQueue::SIZE,
-        seeds = [b"event_queue"],
-        bump
-    )]
-    pub event_queues: AccountLoader<'info,EventQueue>,
-
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub token_program: Program<'info, Token>,
@@ -80,7 +61,6 @@ impl<'info> InitializeGlobalConfig<'info> {
     pub fn process(
         &mut self,
         is_paused: bool,
-        funding_interval_secs: u32,
         bump: &InitializeGlobalConfigBumps,
     ) -> Result<()> {
 
@@ -94,15 +74,8 @@ impl<'info> InitializeGlobalConfig<'info> {
         global_config.vault_quote = self.vault_quote.key();
         global_config.insurance_fund = self.insurance_fund.key();
         global_config.fee_pool = self.fee_pool.key();
-        global_config.request_queue = self.request_queue.key();
-        global_config.event_queue = self.event_queues.key();
         global_config.trading_paused = is_paused;
-        global_config.funding_interval_secs = funding_interval_secs;
         global_config.bump = bump.global_config;
-        let mut rq = self.request_queue.load_init()?; 
-        rq.init(); 
-        let mut eq = self.event_queues.load_init()?;
-        eq.init();
         msg!("DBG: Exiting InitializeGlobalConfig::process");
 
         Ok(())
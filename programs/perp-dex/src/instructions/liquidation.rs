@@ -4,7 +4,7 @@ use anchor_spl::{
     token::{self, Mint, Token, TokenAccount, Transfer},
 };
 use crate::{
-    BidAsk, EventQueue, FUNDING_SCALE, GlobalConfig, MarketState, MatchingType, Order, OrderType, PerpError, Position, Ratio, RiskEngine, Side, UserCollateral, match_against_book,
+    BidAsk, DISCRIMINATOR_LEN, EventQueue, GlobalConfig, MarketState, MatchResult, MatchingType, Order, OrderCancelled, OrderExpired, OrderType, PerpError, Position, PositionManager, PositionRisk, ReduceOnlyCapped, ReduceOnlyCaps, RiskEngine, SelfTradeMode, Side, UserCollateral, match_against_book,
 };
 
 #[derive(Accounts)]
@@ -25,6 +25,9 @@ pub struct Liquidation<'info> {
         bump
     )]
     pub market: Account<'info, MarketState>,
+    /// CHECK: price account at `market.oracle_pubkey`, parsed by the oracle adapter
+    #[account(address = market.oracle_pubkey @ PerpError::OracleMismatch)]
+    pub oracle: UncheckedAccount<'info>,
 
     #[account(
         mut,
@@ -42,7 +45,7 @@ pub struct Liquidation<'info> {
 
     #[account(
         mut,
-        seeds = [b"event_queue"],
+        seeds = [b"event_queue", market.symbol.as_bytes()],
         bump
     )]
     pub event_queue: AccountLoader<'info, EventQueue>,
@@ -97,7 +100,17 @@ pub struct Liquidation<'info> {
 }
 
 impl<'info> Liquidation<'info> {
-    pub fn process(&mut self) -> Result<()> {
+    /// Close the position if the user's whole account is below maintenance.
+    /// `remaining_accounts` holds the market, oracle and position of each of the user's open
+    /// positions in other markets; while any remain, the leftover equity stays as their
+    /// collateral instead of being paid out, and a shortfall stays as negative collateral
+    /// rather than drawing on the insurance fund. Otherwise everything but the margin
+    /// reserved for open orders is paid out, or the insurance fund covers the shortfall.
+    ///
+    /// An isolated position is judged and closed on its own margin alone: no remaining
+    /// accounts are needed, the rest of the account is never charged, and whatever margin
+    /// survives the penalty returns to the user's collateral.
+    pub fn process(&mut self, remaining_accounts: &[AccountInfo]) -> Result<()> {
 
         let bids = &mut self.bids;
         let asks = &mut self.ask;
@@ -114,46 +127,29 @@ impl<'info> Liquidation<'info> {
         require!(target_pos.base_position != 0, PerpError::NothingToLiquidate);
 
         // Apply funding (must happen before health check) 
-        let pos_last_cum = target_pos.last_cum_funding;
-        let delta_funding = market
-            .cum_funding
-            .checked_sub(pos_last_cum)
-            .ok_or(PerpError::MathOverflow)?;
-
-        let funding_payment = delta_funding
-            .checked_mul(target_pos.base_position as i64)
-            .and_then(|v| v.checked_div(FUNDING_SCALE as i64))
-            .ok_or(PerpError::MathOverflow)?;
-
-        target_pos.realized_pnl = target_pos
-            .realized_pnl
-            .checked_sub(funding_payment)
-            .ok_or(PerpError::MathOverflow)?;
-        liquidatee_user_collateral.collateral_amount = liquidatee_user_collateral
-            .collateral_amount
-            .checked_sub(funding_payment as i128)
-            .ok_or(PerpError::MathOverflow)?;
-
-        target_pos.last_cum_funding = market.cum_funding;
+        PositionManager::settle_funding(market, target_pos, liquidatee_user_collateral)?;
 
         //  Recompute health using updated realized_pnl means user_Colletrl 
 
-        let collateral_i128 = liquidatee_user_collateral.collateral_amount;
+        let now = Clock::get()?.unix_timestamp;
+        market.refresh_oracle(&self.oracle, now)?;
         let mark_price = market.get_mark_price()?;
-        let maintain_ratio = Ratio::from_bps(market.mm_bps);
+        let isolated = target_pos.is_isolated;
 
-        let health = RiskEngine::account_health_single(
-            collateral_i128,
-            target_pos.base_position as i128,
-            target_pos.entry_price as u128,
-            mark_price,
-            maintain_ratio,
-        )?;
+        // the equity the liquidation draws on: the position's own margin, or the whole account
+        let mut equity = if isolated { target_pos.isolated_margin } else { liquidatee_user_collateral.collateral_amount };
+        let health = if isolated {
+            RiskEngine::account_health_isolated(equity, &PositionRisk::new(market, target_pos)?)?
+        } else {
+            let mut positions = liquidatee_user_collateral.cross_position_risks(market.key(), remaining_accounts, now)?;
+            positions.push(PositionRisk::new(market, target_pos)?);
+            RiskEngine::account_health_cross(equity, &positions)?
+        };
 
         require!(health < 0, PerpError::NothingToLiquidate);
 
         //Build liquidation taker order 
-        let position_qty_abs = target_pos.base_position.abs() as u64;
+        let position_qty_abs = target_pos.base_position.unsigned_abs();
         let is_long = target_pos.base_position > 0;
         let liquidation_side = if is_long { Side::Sell } else { Side::Buy };
 
@@ -167,28 +163,54 @@ impl<'info> Liquidation<'info> {
             initial_margin: 0,
             leverage: 0,
             market: market.key(),
+            reduce_only: true,
+            // the liquidated user's own quotes are pulled rather than blocking the close
+            self_trade: SelfTradeMode::CancelMaker,
+            client_order_id: 0,
+            expires_at: 0,
+            fee_tier: 0,
         };
 
-        // Match against book / forced close remainder at mark 
-        let (_remaining_qty, fills) = match liquidation_side {
+        // Match against book within the oracle band / forced close remainder at mark.
+        // No positions are loaded here, so resting reduce-only makers are pulled, not filled.
+        let band = market.price_band()?;
+        let mut caps = ReduceOnlyCaps::default();
+        let MatchResult { remaining_qty: _remaining_qty, taker_fills: fills, expired_makers, reduce_only_pulled, outside_band, .. } = match liquidation_side {
             Side::Buy => {
                 let ask_account_info = &mut asks.to_account_info();
                 let mut ask_data = ask_account_info.try_borrow_mut_data()?;
-                let ask_bytes: &mut [u8] = &mut **ask_data;
+                let ask_bytes: &mut [u8] = &mut ask_data[DISCRIMINATOR_LEN..];
                 let ask_slab = &mut crate::Slab::from_bytes_mut(ask_bytes)?;
 
-                match_against_book(ask_slab, &taker_order, event_queue, MatchingType::Liquidation)?
+                match_against_book(ask_slab, &taker_order, event_queue, &mut caps, MatchingType::Liquidation, band)?
             }
             Side::Sell => {
                 let bid_account_info = &mut bids.to_account_info();
                 let mut bid_data = bid_account_info.try_borrow_mut_data()?;
-                let bid_bytes: &mut [u8] = &mut **bid_data;
+                let bid_bytes: &mut [u8] = &mut bid_data[DISCRIMINATOR_LEN..];
                 let bid_slab = &mut crate::Slab::from_bytes_mut(bid_bytes)?;
 
-                match_against_book(bid_slab, &taker_order, event_queue, MatchingType::Liquidation)?
+                match_against_book(bid_slab, &taker_order, event_queue, &mut caps, MatchingType::Liquidation, band)?
             }
         };
 
+        let maker_side = if is_long { Side::Buy } else { Side::Sell };
+        for maker in &expired_makers {
+            emit!(OrderExpired::removed(market.key(), maker, maker_side));
+        }
+        for maker in &outside_band {
+            emit!(OrderCancelled::removed(market.key(), maker, maker_side));
+        }
+        for maker in &reduce_only_pulled {
+            emit!(ReduceOnlyCapped {
+                market: market.key(),
+                order_id: maker.key,
+                owner: Pubkey::new_from_array(maker.owner),
+                requested_qty: maker.quantity,
+                allowed_qty: 0,
+            });
+        }
+
         let mut total_closed_notional: u128 = 0;
         let total_filled_qty: u64 = fills.iter().map(|f| f.fill_qty).sum();
         let mut exit_avg_price: u128 = 0;
@@ -209,7 +231,7 @@ impl<'info> Liquidation<'info> {
 
         if _remaining_qty != 0 {
             // force close remainder at mark price
-            let forced_notional = (mark_price as u128)
+            let forced_notional = mark_price
                 .checked_mul(_remaining_qty as u128)
                 .ok_or(PerpError::MathOverflow)?;
             total_closed_notional = total_closed_notional
@@ -231,18 +253,18 @@ impl<'info> Liquidation<'info> {
             .checked_add(realized_pnl as i64)
             .ok_or(PerpError::MathOverflow)?;
 
-        liquidatee_user_collateral.collateral_amount = liquidatee_user_collateral
-            .collateral_amount
-            .checked_add(realized_pnl as i128)
+        equity = equity
+            .checked_add(realized_pnl)
             .ok_or(PerpError::MathOverflow)?;
 
         target_pos.base_position = 0;
         target_pos.entry_price = 0;
         target_pos.last_cum_funding = market.cum_funding;
-        target_pos.updated_at = Clock::get()?.unix_timestamp;
+        target_pos.updated_at = now;
+        liquidatee_user_collateral.track_position(market.key(), 0)?;
 
         // Compute final equity and apply penalties/transfers
-        let final_equity = liquidatee_user_collateral.collateral_amount;
+        let final_equity = equity;
 
         
         let liquidation_penalty_bps = market.liq_penalty_bps as u128;
@@ -278,8 +300,7 @@ impl<'info> Liquidation<'info> {
         // Transfer to liquidator (from vault) - use PDA signer of global_config
         let signer_seeds: &[&[u8]] = &[b"global_config", &[global_config.bump]];
         
-        liquidatee_user_collateral.collateral_amount = liquidatee_user_collateral
-            .collateral_amount
+        equity = equity
             .checked_sub(capped_penalty_u128 as i128)   
             .ok_or(PerpError::MathOverflow)?;
 
@@ -317,50 +338,52 @@ impl<'info> Liquidation<'info> {
             )?;
         }
     
-        // If negative, cover shortfall from insurance fund -> vault_quote. Otherwise pay user from vault.
-        if liquidatee_user_collateral.collateral_amount < 0 {
-
-            let shortfall_i128 = liquidatee_user_collateral.collateral_amount.checked_abs().ok_or(PerpError::MathOverflow)?;
-            let shortfall_u128 = shortfall_i128 as u128;
-            let shortfall_u64 = u64::try_from(shortfall_u128).map_err(|_| PerpError::MathOverflow)?;
-
-
-            // Transfer from insurance_fund -> vault_quote to cover bad debt
-            if shortfall_u64> 0 {
-                token::transfer(
-                    CpiContext::new_with_signer(
-                        self.token_program.to_account_info(),
-                        Transfer {
-                            from: insurance_fund.to_account_info(),
-                            to: vault_quote.to_account_info(),
-                            authority: global_config.to_account_info(),
-                        },
-                        &[signer_seeds],
-                    ),
-                    shortfall_u64,
-                )?;
-            liquidatee_user_collateral.collateral_amount = 0;
-            }
+        // Cover a shortfall nothing else backs from insurance fund -> vault_quote, or pay the
+        // user out of the vault; see `RiskEngine::settle_liquidation`.
+        let settlement = RiskEngine::settle_liquidation(
+            equity,
+            isolated,
+            !liquidatee_user_collateral.open_positions.is_empty(),
+            liquidatee_user_collateral.reserved_margin,
+        )?;
+        if settlement.insurance_draw > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    Transfer {
+                        from: insurance_fund.to_account_info(),
+                        to: vault_quote.to_account_info(),
+                        authority: global_config.to_account_info(),
+                    },
+                    &[signer_seeds],
+                ),
+                settlement.insurance_draw,
+            )?;
+        }
+        if settlement.payout > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    Transfer {
+                        from: vault_quote.to_account_info(),
+                        to: liquidatee_token_account.to_account_info(),
+                        authority: global_config.to_account_info(),
+                    },
+                    &[signer_seeds],
+                ),
+                settlement.payout,
+            )?;
+        }
+        equity = settlement.remaining;
+
+        if isolated {
+            target_pos.isolated_margin = 0;
+            liquidatee_user_collateral.collateral_amount = liquidatee_user_collateral
+                .collateral_amount
+                .checked_add(equity)
+                .ok_or(PerpError::MathOverflow)?;
         } else {
-            // pay remaining equity back to user
-            let remaining_u128 = liquidatee_user_collateral.collateral_amount as u128;
-            let payout_u64 = u64::try_from(remaining_u128).map_err(|_| PerpError::MathOverflow)?;
-
-            if payout_u64 > 0 {
-                token::transfer(
-                    CpiContext::new_with_signer(
-                        self.token_program.to_account_info(),
-                        Transfer {
-                            from: vault_quote.to_account_info(),
-                            to: liquidatee_token_account.to_account_info(),
-                            authority: global_config.to_account_info(),
-                        },
-                        &[signer_seeds],
-                    ),
-                    payout_u64,
-                )?;
-            }
-         liquidatee_user_collateral.collateral_amount = 0;
+            liquidatee_user_collateral.collateral_amount = equity;
         }
         Ok(())
     }
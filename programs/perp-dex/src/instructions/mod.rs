@@ -8,6 +8,57 @@ pub  use initlaize_global_config::*;
 pub mod place_order;
 pub use place_order::*;
 
+pub mod cancel_order;
+pub use cancel_order::*;
+
+pub mod cancel_all_orders;
+pub use cancel_all_orders::*;
+
+pub mod initialize_trigger_book;
+pub use initialize_trigger_book::*;
+
+pub mod place_trigger_order;
+pub use place_trigger_order::*;
+
+pub mod cancel_trigger_order;
+pub use cancel_trigger_order::*;
+
+pub mod trigger_orders;
+pub use trigger_orders::*;
+
+pub mod prune_expired;
+pub use prune_expired::*;
+
+pub mod update_funding;
+pub use update_funding::*;
+
+pub mod update_funding_params;
+pub use update_funding_params::*;
+
+pub mod settle_funding;
+pub use settle_funding::*;
+
+pub mod sweep_fees;
+pub use sweep_fees::*;
+
+pub mod set_fee_tiers;
+pub use set_fee_tiers::*;
+
+pub mod set_referral_params;
+pub use set_referral_params::*;
+
+pub mod bind_referrer;
+pub use bind_referrer::*;
+
+pub mod claim_referral_rewards;
+pub use claim_referral_rewards::*;
+
+pub mod set_margin_mode;
+pub use set_margin_mode::*;
+
+pub mod adjust_isolated_margin;
+pub use adjust_isolated_margin::*;
+
 pub mod process_order;
 pub use process_order::*;
 
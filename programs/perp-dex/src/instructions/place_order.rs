@@ -7,7 +7,7 @@ use anchor_spl::{
 use anchor_lang::solana_program::sysvar::clock::Clock;
 
 
-use crate::{GlobalConfig, MarketState, Order,  PerpError, Position, RequestQueue, RequestType, UserCollateral, make_order_id};
+use crate::{GlobalConfig, MarketState, Order,  PerpError, Position, PositionRisk, RequestQueue, RequestType, RiskEngine, UserCollateral, UserStats, make_order_id};
 #[derive(Accounts)]
 pub struct PlaceOrder<'info>{
     #[account(mut)]
@@ -24,12 +24,20 @@ pub struct PlaceOrder<'info>{
         bump = market.bump
     )]
     pub market : Account<'info,MarketState>,
+    /// CHECK: price account at `market.oracle_pubkey`, parsed by the oracle adapter
+    #[account(address = market.oracle_pubkey @ PerpError::OracleMismatch)]
+    pub oracle : UncheckedAccount<'info>,
     #[account(
         mut,
         seeds = [b"user_colletral", user.key().as_ref()],
         bump
     )]
     pub user_colletral : Account<'info,UserCollateral>,
+    #[account(
+        seeds = [b"user_stats", user.key().as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats : Account<'info,UserStats>,
     #[account(
         init_if_needed,
         space = 8+Position::INIT_SPACE,
@@ -40,7 +48,7 @@ pub struct PlaceOrder<'info>{
     pub position_per_market: Account<'info, Position>,
     #[account(
         mut,
-        seeds = [b"request_queue"],
+        seeds = [b"request_queue", market.symbol.as_bytes()],
         bump
     )]
     pub request_queue : AccountLoader<'info,RequestQueue>,
@@ -50,21 +58,60 @@ pub struct PlaceOrder<'info>{
 }
 
 impl <'info> PlaceOrder <'info>{
+    /// `remaining_accounts` holds the market, oracle and position of each of the user's open
+    /// positions in other markets; the new margin must leave the whole account healthy.
     pub fn process(
         &mut self,
-        order :Order
+        order :Order,
+        remaining_accounts: &[AccountInfo],
     )->Result<()>{
     
     let market = &mut self.market;
+    require!(order.market == market.key(), PerpError::MarketMismatch);
+    let now = Clock::get()?.unix_timestamp;
+    // the band and margin are sized on a fresh oracle price
+    market.refresh_oracle(&self.oracle, now)?;
+    market.validate_order(&order)?;
+    if order.order_type.has_limit_price() {
+        require!(market.price_band()?.contains(order.limit_price), PerpError::PriceOutsideOracleBand);
+    }
+    require!(
+        order.expires_at == 0 || (order.order_type.rests() && order.expires_at > now),
+        PerpError::InvalidExpiry
+    );
     let user_colletral = &mut self.user_colletral;
-    let im_required = market.compute_initial_margin(order.clone())?;
+    let position: &mut Account<'info, Position> = &mut self.position_per_market;
+
+    // reduce-only orders are capped at the position they close and lock no margin;
+    // process_order caps them again against the position net of reduce-only qty matched, and
+    // a resting one is capped each time it trades
+    let (qty, im_required) = if order.reduce_only {
+        require!(!order.order_type.is_post_only(), PerpError::ReduceOnlyPostOnly);
+        let reducible = position.reducible_qty(order.side);
+        require!(reducible > 0, PerpError::ReduceOnlyWouldIncrease);
+        (order.qty.min(reducible), 0)
+    } else {
+        (order.qty, market.compute_initial_margin(order.clone())?)
+    };
 
     require!(
-        user_colletral.collateral_amount>= im_required as i128,
+        user_colletral.free_collateral()? >= im_required as i128,
         PerpError::InsufficientCollateral
     );
-    
-    let position: &mut Account<'info, Position> = &mut self.position_per_market;
+    // losses and maintenance margin elsewhere eat into what can back a new order
+    if !order.reduce_only {
+        let mut positions = user_colletral.cross_position_risks(market.key(), remaining_accounts, now)?;
+        if !position.is_isolated {
+            positions.push(PositionRisk::new(market, position)?);
+        }
+        let free_after = user_colletral.free_collateral()?
+            .checked_sub(im_required as i128)
+            .ok_or(PerpError::MathOverflow)?;
+        require!(
+            RiskEngine::account_health_cross(free_after, &positions)? >= 0,
+            PerpError::InsufficientCollateral
+        );
+    }
 
     let request_queues = &mut self.request_queue.load_mut()?;
     require!(request_queues.count<request_queues.capacity,PerpError::QueueFull);
@@ -73,33 +120,37 @@ impl <'info> PlaceOrder <'info>{
     let order_id = make_order_id(order.order_type , order.side , order.limit_price ,seq);
     request_queues.sequence = seq.add(1);
 
-    //initalise the posiotion 
-    position.owner = self.user.key();
-    position.market = order.market;
+    // lock the initial margin until the order fills or leaves the book
+    if !order.reduce_only {
+        user_colletral.reserve_margin(order.market, order_id, qty, im_required)?;
+    }
+
+    //initalise the posiotion, but never reset an open one
+    position.open_if_new(self.user.key(), order.market, now);
     position.order_id = order_id;
     position.side = order.side;
-    position.qty = order.qty;
+    position.qty = qty;
     position.order_type = order.order_type;
     position.status = crate::OrderStatus::Pending;
-    position.base_position = 0 ;
-    position.entry_price = 0;
-    position.realized_pnl = 0;
-    position.last_cum_funding = 0;
     position.initial_margin = order.initial_margin;
     position.leverage = order.leverage;
-    position.created_at =  Clock::get()?.unix_timestamp;
-    position.updated_at = Clock::get()?.unix_timestamp;
+    position.updated_at = now;
 
     let make_order = Order{
         user:self.user.key().to_bytes(),
-        order_id : order_id,
+        order_id,
         side : order.side,
-        qty : order.qty,
+        qty,
         order_type : order.order_type,
         limit_price : order.limit_price,
         initial_margin : order.initial_margin,
         leverage : order.leverage,
         market : order.market,
+        reduce_only : order.reduce_only,
+        self_trade : order.self_trade,
+        client_order_id : order.client_order_id,
+        expires_at : order.expires_at,
+        fee_tier : self.global_config.fee_tier(self.user_stats.trailing_volume(now)),
     };
     let req = RequestType::Place(make_order);
   
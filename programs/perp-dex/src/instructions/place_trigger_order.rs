@@ -0,0 +1,163 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    make_order_id, MarketState, Order, PerpError, Position, PositionRisk, RequestQueue, RiskEngine, SelfTradeMode,
+    Side, TriggerBook, TriggerKind, TriggerOrder, UserCollateral,
+};
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct TriggerOrderParams {
+    pub side: Side,
+    pub kind: TriggerKind,
+    pub qty: u64,
+    pub trigger_price: u64,
+    pub limit_price: u64,      // StopLimit only
+    pub reduce_only: bool,
+    pub expires_at: i64,       // 0 = good till cancelled
+    pub client_order_id: u64,
+}
+
+#[derive(Accounts)]
+pub struct PlaceTriggerOrder<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    /// CHECK: price account at `market.oracle_pubkey`, parsed by the oracle adapter
+    #[account(address = market.oracle_pubkey @ PerpError::OracleMismatch)]
+    pub oracle: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"trigger_book", market.symbol.as_bytes()],
+        bump = trigger_book.bump
+    )]
+    pub trigger_book: Account<'info, TriggerBook>,
+    #[account(
+        mut,
+        seeds = [b"request_queue", market.symbol.as_bytes()],
+        bump
+    )]
+    pub request_queue: AccountLoader<'info, RequestQueue>,
+    #[account(
+        mut,
+        seeds = [b"user_colletral", user.key().as_ref()],
+        bump
+    )]
+    pub user_colletral: Account<'info, UserCollateral>,
+    #[account(
+        init_if_needed,
+        space = 8 + Position::INIT_SPACE,
+        payer = user,
+        seeds = [b"position", market.symbol.as_bytes(), user.key().as_ref()],
+        bump
+    )]
+    pub position_per_market: Account<'info, Position>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> PlaceTriggerOrder<'info> {
+    /// Park a conditional order until `trigger_orders` sees the mark cross its trigger.
+    /// The order id is taken from the request queue now, and the initial margin is
+    /// reserved now, so firing the trigger needs no account of the owner.
+    /// `remaining_accounts` holds the market, oracle and position of each of the user's open
+    /// positions in other markets; the reserved margin must leave the whole account healthy.
+    pub fn process(&mut self, params: TriggerOrderParams, remaining_accounts: &[AccountInfo]) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(params.trigger_price > 0, PerpError::InvalidTriggerOrder);
+        require!(
+            params.kind != TriggerKind::StopLimit || params.limit_price > 0,
+            PerpError::InvalidTriggerOrder
+        );
+        require!(params.expires_at == 0 || params.expires_at > now, PerpError::InvalidTriggerOrder);
+
+        self.market.refresh_oracle(&self.oracle, now)?;
+        let market = &self.market;
+        require!(market.is_on_tick(params.trigger_price), PerpError::PriceNotMultipleOfTick);
+        let order_type = params.kind.order_type();
+        let mut order = Order {
+            user: self.user.key().to_bytes(),
+            order_id: 0,
+            side: params.side,
+            qty: params.qty,
+            order_type,
+            limit_price: params.limit_price,
+            initial_margin: 0,
+            leverage: 0,
+            market: market.key(),
+            reduce_only: params.reduce_only,
+            self_trade: SelfTradeMode::CancelMaker, // as the order fires, see trigger_orders
+            client_order_id: params.client_order_id,
+            expires_at: 0,
+            fee_tier: 0,
+        };
+        market.validate_order(&order)?;
+
+        order.order_id = {
+            let request_queue = &mut self.request_queue.load_mut()?;
+            let seq = request_queue.sequence;
+            request_queue.sequence = seq + 1;
+            make_order_id(order_type, params.side, params.limit_price, seq)
+        };
+        let order_id = order.order_id;
+
+        if !params.reduce_only {
+            let im_required = market.compute_initial_margin(order)?;
+            // losses and maintenance margin elsewhere eat into what can back the order
+            let user_colletral = &mut self.user_colletral;
+            let mut positions = user_colletral.cross_position_risks(market.key(), remaining_accounts, now)?;
+            if !self.position_per_market.is_isolated {
+                positions.push(PositionRisk::new(market, &self.position_per_market)?);
+            }
+            let free_after = user_colletral.free_collateral()?
+                .checked_sub(im_required as i128)
+                .ok_or(PerpError::MathOverflow)?;
+            require!(
+                RiskEngine::account_health_cross(free_after, &positions)? >= 0,
+                PerpError::InsufficientCollateral
+            );
+            user_colletral.reserve_margin(market.key(), order_id, params.qty, im_required)?;
+        }
+
+        self.position_per_market.open_if_new(self.user.key(), market.key(), now);
+
+        self.trigger_book.insert(TriggerOrder {
+            owner: self.user.key(),
+            order_id,
+            side: params.side,
+            kind: params.kind,
+            qty: params.qty,
+            trigger_price: params.trigger_price,
+            limit_price: params.limit_price,
+            reduce_only: params.reduce_only,
+            expires_at: params.expires_at,
+            created_at: now,
+            client_order_id: params.client_order_id,
+        })?;
+
+        emit!(TriggerOrderPlaced {
+            market: market.key(),
+            order_id,
+            owner: self.user.key(),
+            kind: params.kind,
+            side: params.side,
+            qty: params.qty,
+            trigger_price: params.trigger_price,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct TriggerOrderPlaced {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub owner: Pubkey,
+    pub kind: TriggerKind,
+    pub side: Side,
+    pub qty: u64,
+    pub trigger_price: u64,
+}
@@ -1,7 +1,7 @@
 use anchor_lang::prelude::*;
 use crate::{
-    EventQueue,  MarketState, PerpError,
-    Position, PositionManager, UserCollateral
+    EventKind, EventQueue, GlobalConfig, MarketState, PerpError,
+    Position, PositionManager, UserCollateral, UserStats
 };
 use anchor_spl::token::Token;
 
@@ -24,7 +24,7 @@ pub struct PositionIns<'info> {
 
     #[account(
         mut,
-        seeds = [b"event_queue"],
+        seeds = [b"event_queue", market.symbol.as_bytes()],
         bump
     )]
     pub event_queue: AccountLoader<'info, EventQueue>,
@@ -36,16 +36,44 @@ pub struct PositionIns<'info> {
     )]
     pub user_collateral: Account<'info, UserCollateral>,
 
+    #[account(
+        seeds = [b"global_config"],
+        bump = global_config.bump
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+
+    #[account(
+        mut,
+        seeds = [b"user_stats", user_key.as_ref()],
+        bump = user_stats.bump
+    )]
+    pub user_stats: Account<'info, UserStats>,
+
+    /// Stats of `user_stats.referrer`; required when the user was referred.
+    #[account(
+        mut,
+        seeds = [b"user_stats", referrer_stats.owner.as_ref()],
+        bump = referrer_stats.bump
+    )]
+    pub referrer_stats: Option<Account<'info, UserStats>>,
+
     pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token>
 }
 
 impl<'info> PositionIns<'info> {
-    /// Process fill events from the global event queue that belong to this user.
+    /// Process fill and out events from the global event queue that belong to this user.
     /// Only consumes events at the head that are for `user_key`; if the head is for another user, returns `EventNotForUser`.
     /// May process multiple consecutive events for the same user in one call.
+    /// Fills are charged at the user's fee tier and count towards their 30-day volume.
     pub fn process(&mut self, user_key: Pubkey) -> Result<()> {
         require!(user_key == self.user_position.owner, PerpError::Unauthorized);
+        let now = Clock::get()?.unix_timestamp;
+        let referred = self.user_stats.is_referred();
+        if referred {
+            let referrer_stats = self.referrer_stats.as_ref().ok_or(PerpError::ReferrerMismatch)?;
+            require_keys_eq!(referrer_stats.owner, self.user_stats.referrer, PerpError::ReferrerMismatch);
+        }
 
         let mut processed = 0;
         let max_per_call = 16u16; // cap events per instruction
@@ -66,12 +94,39 @@ impl<'info> PositionIns<'info> {
             let fill_event = queue.pop()?;
             drop(queue);
 
-            PositionManager::apply_fill(
-                &mut self.market,
-                &mut self.user_position,
-                &mut self.user_collateral,
-                fill_event,
-            )?;
+            match fill_event.kind {
+                EventKind::Fill => {
+                    // makers are charged at the tier stamped on their resting order, takers at
+                    // the tier of their volume when the fill lands
+                    let fee_rates = if fill_event.is_maker {
+                        self.global_config.fee_rates_at_tier(&self.market, fill_event.maker_fee_tier, referred)
+                    } else {
+                        let volume = self.user_stats.trailing_volume(now);
+                        self.global_config.fee_rates(&self.market, volume, referred)
+                    };
+                    let notional = u64::try_from(fill_event.notional()?)
+                        .map_err(|_| PerpError::MathOverflow)?;
+                    let is_maker = fill_event.is_maker;
+                    let fee = PositionManager::apply_fill(
+                        &mut self.market,
+                        &mut self.user_position,
+                        &mut self.user_collateral,
+                        fill_event,
+                        fee_rates,
+                    )?;
+                    self.user_stats.record_fill(is_maker, notional, now)?;
+                    if let Some(referrer_stats) = self.referrer_stats.as_mut().filter(|_| referred) {
+                        referrer_stats.credit_referral_reward(fee.referrer_reward)?;
+                    }
+                }
+                EventKind::Out => {
+                    self.user_collateral.release_margin(
+                        self.market.key(),
+                        fill_event.order_id,
+                        fill_event.fill_qty,
+                    )?;
+                }
+            }
             processed += 1;
         }
 
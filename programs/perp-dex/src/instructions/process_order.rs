@@ -4,7 +4,7 @@ use anchor_spl::{
     token::{ Token},
     associated_token::AssociatedToken,
 };
-use crate::{BidAsk, EventQueue, MAX_TO_PROCESS, MarketState, MatchingEngine,  RequestQueue, RequestType};
+use crate::{BidAsk, EventQueue, MAX_TO_PROCESS, MarketState, MatchingEngine, Order, OrderType, PerpError, Position, ReduceOnlyCaps, RequestQueue, RequestType, SelfTradeMode, push_out};
 
 #[derive(Accounts)]
 pub struct ProcessOrder<'info> {
@@ -16,6 +16,9 @@ pub struct ProcessOrder<'info> {
         bump
     )]
     pub market: Account<'info, MarketState>,
+    /// CHECK: price account at `market.oracle_pubkey`, parsed by the oracle adapter
+    #[account(address = market.oracle_pubkey @ PerpError::OracleMismatch)]
+    pub oracle: UncheckedAccount<'info>,
     #[account(
         mut,
         seeds = [b"bids", market.symbol.as_bytes()],
@@ -32,14 +35,14 @@ pub struct ProcessOrder<'info> {
 
     #[account(
         mut,
-        seeds = [b"request_queue"],
+        seeds = [b"request_queue", market.symbol.as_bytes()],
         bump
     )]
     pub request_queue: AccountLoader<'info, RequestQueue>,
 
     #[account(
         mut,
-        seeds = [b"event_queue"],
+        seeds = [b"event_queue", market.symbol.as_bytes()],
         bump
     )]
     pub event_queue: AccountLoader<'info, EventQueue>,
@@ -52,7 +55,36 @@ pub struct ProcessOrder<'info> {
 
 
 impl<'info> ProcessOrder<'info> {
-    pub fn process(&mut self) -> Result<()> {
+    /// `remaining_accounts` carries the position accounts of the owners of reduce-only orders,
+    /// queued or resting, writable so the matched qty can be counted as pending; a reduce-only
+    /// order whose position is missing is treated as having nothing to reduce.
+    pub fn process(&mut self, remaining_accounts: &'info [AccountInfo<'info>]) -> Result<()> {
+        let mut positions: Vec<Account<'info, Position>> = Vec::new();
+        for info in remaining_accounts {
+            require!(info.is_writable, PerpError::PositionNotWritable);
+            // a position passed twice would count its matched qty twice
+            if positions.iter().any(|position| position.key() == info.key()) {
+                continue;
+            }
+            let position = Account::<Position>::try_from(info)?;
+            require!(position.market == self.market.key(), PerpError::MarketMismatch);
+            positions.push(position);
+        }
+        let mut caps = ReduceOnlyCaps::from_positions(positions.iter().map(|position| &**position));
+
+        // orders match inside the band around a fresh oracle price; without one they are
+        // rejected rather than failing the crank, so the cancels queued behind them still run
+        let now = Clock::get()?.unix_timestamp;
+        if let Err(err) = self.market.refresh_oracle(&self.oracle, now) {
+            msg!("ProcessOrder: oracle not refreshed: {}", err);
+        }
+        let band = match self.market.live_price_band(now) {
+            Ok(band) => Some(band),
+            Err(err) => {
+                msg!("ProcessOrder: no price band, orders will be rejected: {}", err);
+                None
+            }
+        };
         let mut processed = 0;
 
         loop {
@@ -72,20 +104,133 @@ impl<'info> ProcessOrder<'info> {
 
 
             match req {
-                Some(RequestType::Place(order)) => {
+                Some(RequestType::Place(mut order)) => {
                     msg!("RequestQueue: enqueue order_id={}", order.order_id);
-                    MatchingEngine::process_place_order(self, order)?;
+                    require!(order.market == self.market.key(), PerpError::MarketMismatch);
+                    if order.reduce_only {
+                        order.qty = self.cap_reduce_only(&order, &caps)?;
+                    }
+                    if order.qty > 0 {
+                        match band {
+                            Some(band) => MatchingEngine::process_place_order(self, order, &mut caps, band)?,
+                            None => self.reject_without_band(&order, now)?,
+                        }
+                    }
                 }
                 Some(RequestType::Cancel(cancel)) => {
                     msg!("RequestQueue: cancel order_id={}", cancel.order_id);
                     MatchingEngine::process_cancel_order(self, cancel)?;
                 }
+                Some(RequestType::CancelByClientId(cancel)) => {
+                    msg!("RequestQueue: cancel client_order_id={}", cancel.client_order_id);
+                    MatchingEngine::process_cancel_by_client_id(self, cancel)?;
+                }
                 None => break,
             }
 
             processed += 1;
         }
 
+        for position in positions.iter_mut() {
+            let matched = caps.matched(&position.owner.to_bytes());
+            position.add_reduce_only_pending(matched);
+            position.exit(&crate::ID)?;
+        }
         Ok(()) 
     }
+
+    /// Release an order that cannot be matched for want of a fresh oracle price.
+    fn reject_without_band(&mut self, order: &Order, now: i64) -> Result<()> {
+        push_out(&mut *self.event_queue.load_mut()?, order, order.qty, now)?;
+        emit!(StaleOracleRejected {
+            market: self.market.key(),
+            order_id: order.order_id,
+            owner: Pubkey::new_from_array(order.user),
+            qty: order.qty,
+        });
+        Ok(())
+    }
+
+    /// Cap a reduce-only order at the live position net of the reduce-only qty already
+    /// matched, and release the cut. Whatever rests is capped again each time it trades.
+    fn cap_reduce_only(&mut self, order: &Order, caps: &ReduceOnlyCaps) -> Result<u64> {
+        let owner = Pubkey::new_from_array(order.user);
+        let allowed = order.qty.min(caps.available(&order.user, order.side));
+
+        if allowed < order.qty {
+            let now = Clock::get()?.unix_timestamp;
+            push_out(&mut *self.event_queue.load_mut()?, order, order.qty - allowed, now)?;
+            emit!(ReduceOnlyCapped {
+                market: self.market.key(),
+                order_id: order.order_id,
+                owner,
+                requested_qty: order.qty,
+                allowed_qty: allowed,
+            });
+        }
+        Ok(allowed)
+    }
+}
+
+#[event]
+pub struct SelfTradePrevented {
+    pub market: Pubkey,
+    pub taker_order_id: u128,
+    pub maker_order_id: u128,
+    pub owner: Pubkey,
+    pub mode: SelfTradeMode,
+    pub prevented_qty: u64,
+}
+
+#[event]
+pub struct ReduceOnlyCapped {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub owner: Pubkey,
+    pub requested_qty: u64,
+    pub allowed_qty: u64,   // 0 when the position is flat or on the order's side
+}
+
+#[event]
+pub struct OrderRemainderCancelled {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub owner: Pubkey,
+    pub order_type: OrderType,
+    pub filled_qty: u64,
+    pub cancelled_qty: u64,
+}
+
+#[event]
+pub struct StaleOracleRejected {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub owner: Pubkey,
+    pub qty: u64,
+}
+
+#[event]
+pub struct FillOrKillRejected {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub owner: Pubkey,
+    pub qty: u64,
+}
+
+#[event]
+pub struct PostOnlyRejected {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub owner: Pubkey,
+    pub limit_price: u64,
+}
+
+#[event]
+pub struct PostOnlyRepriced {
+    pub market: Pubkey,
+    pub original_order_id: u128,
+    pub order_id: u128,        // key the order rests under; use it to cancel
+    pub owner: Pubkey,
+    pub original_price: u64,
+    pub limit_price: u64,
 }
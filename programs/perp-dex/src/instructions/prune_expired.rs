@@ -0,0 +1,90 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    prune_expired_in_book_core, BidAsk, EventQueue, LeafNode, MarketState, Side, Slab, DISCRIMINATOR_LEN,
+    MAX_PRUNE_PER_CALL,
+};
+
+#[derive(Accounts)]
+pub struct PruneExpired<'info> {
+    #[account(
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    #[account(
+        mut,
+        seeds = [b"bids", market.symbol.as_bytes()],
+        bump
+    )]
+    pub bids: AccountLoader<'info, BidAsk>,
+    #[account(
+        mut,
+        seeds = [b"asks", market.symbol.as_bytes()],
+        bump
+    )]
+    pub asks: AccountLoader<'info, BidAsk>,
+    #[account(
+        mut,
+        seeds = [b"event_queue", market.symbol.as_bytes()],
+        bump
+    )]
+    pub event_queue: AccountLoader<'info, EventQueue>,
+}
+
+impl<'info> PruneExpired<'info> {
+    /// Permissionless crank: remove expired leaves from both books, up to `MAX_PRUNE_PER_CALL`
+    /// and the free space of the event queue. The position crank releases the owners'
+    /// reserved margin from the `Out` events pushed here.
+    pub fn process(&mut self) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let market_key = self.market.key();
+        let event_queue = &mut self.event_queue.load_mut()?;
+
+        let mut budget = MAX_PRUNE_PER_CALL.min((event_queue.capacity - event_queue.count) as usize);
+        let mut has_more = false;
+        for (side, book) in [(Side::Buy, &self.bids), (Side::Sell, &self.asks)] {
+            let book_info = book.to_account_info();
+            let mut book_data = book_info.try_borrow_mut_data()?;
+            let slab = Slab::from_bytes_mut(&mut book_data[DISCRIMINATOR_LEN..])?;
+
+            let (removed, more) = prune_expired_in_book_core(slab, side, event_queue, now, budget)?;
+            for leaf in &removed {
+                emit!(OrderExpired::removed(market_key, leaf, side));
+            }
+            budget -= removed.len();
+            has_more |= more;
+        }
+
+        msg!("PruneExpired: has_more={}", has_more);
+        Ok(())
+    }
+}
+
+#[event]
+pub struct OrderExpired {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub owner: Pubkey,
+    pub side: Side,
+    pub price: u64,
+    pub removed_qty: u64,
+    pub client_order_id: u64,
+    pub expires_at: i64,
+}
+
+impl OrderExpired {
+    /// Event for a resting order that left the book on expiry.
+    pub fn removed(market: Pubkey, leaf: &LeafNode, side: Side) -> Self {
+        Self {
+            market,
+            order_id: leaf.key,
+            owner: Pubkey::new_from_array(leaf.owner),
+            side,
+            price: leaf.price(),
+            removed_qty: leaf.quantity,
+            client_order_id: leaf.client_order_id(),
+            expires_at: leaf.expires_at,
+        }
+    }
+}
@@ -1,13 +1,20 @@
 use anchor_lang::prelude::*;
 
-use crate::{EventQueue, RequestQueue};
+use crate::{EventQueue, MarketState, PerpError, RequestQueue};
 
 #[derive(Accounts)]
 pub struct ResetQueue<'info> {
-    #[account(mut, seeds = [b"request_queue"], bump)]
+    pub authority: Signer<'info>,
+    #[account(mut, seeds = [b"request_queue", market.symbol.as_bytes()], bump)]
     pub request_queue: AccountLoader<'info, RequestQueue>,
-    #[account(mut, seeds = [b"event_queue"], bump)]
+    #[account(mut, seeds = [b"event_queue", market.symbol.as_bytes()], bump)]
     pub event_queue: AccountLoader<'info, EventQueue>,
+    #[account(
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump,
+        constraint = market.authority == authority.key() @ PerpError::Unauthorized
+    )]
+    pub market: Account<'info, MarketState>,
 }
 
 impl<'info> ResetQueue<'info> {
@@ -0,0 +1,47 @@
+use anchor_lang::prelude::*;
+
+use crate::{FeeTier, GlobalConfig, PerpError};
+
+#[derive(Accounts)]
+pub struct SetFeeTiers<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"global_config"],
+        bump = global_config.bump,
+        has_one = authority @ PerpError::NotAuthorized
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+}
+
+impl<'info> SetFeeTiers<'info> {
+    /// Replace the volume fee tiers of every market. An empty table charges each market's
+    /// own fees, and is refused while referral params are set. Fills already in the event
+    /// queue are charged at the new rates. Rebates must stay covered by what the protocol
+    /// keeps of a referred taker's fee.
+    pub fn process(&mut self, fee_tiers: Vec<FeeTier>) -> Result<()> {
+        GlobalConfig::validate_fee_tiers(&fee_tiers)?;
+        let config = &self.global_config;
+        require!(
+            !fee_tiers.is_empty() || (config.referrer_share_bps == 0 && config.referee_discount_bps == 0),
+            PerpError::InvalidFeeTiers
+        );
+        require!(
+            GlobalConfig::rebates_covered(&fee_tiers, config.referrer_share_bps, config.referee_discount_bps),
+            PerpError::InvalidFeeTiers
+        );
+        let previous = std::mem::replace(&mut self.global_config.fee_tiers, fee_tiers.clone());
+
+        emit!(FeeTiersUpdated {
+            previous,
+            current: fee_tiers,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct FeeTiersUpdated {
+    pub previous: Vec<FeeTier>,
+    pub current: Vec<FeeTier>,
+}
@@ -0,0 +1,67 @@
+use anchor_lang::prelude::*;
+
+use crate::{MarketState, PerpError, Position, UserCollateral};
+
+#[derive(Accounts)]
+pub struct SetMarginMode<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    #[account(
+        init_if_needed,
+        space = 8 + Position::INIT_SPACE,
+        payer = user,
+        seeds = [b"position", market.symbol.as_bytes(), user.key().as_ref()],
+        bump
+    )]
+    pub user_position: Account<'info, Position>,
+    #[account(
+        mut,
+        seeds = [b"user_colletral", user.key().as_ref()],
+        bump
+    )]
+    pub user_colletral: Account<'info, UserCollateral>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> SetMarginMode<'info> {
+    /// Switch the user's position in this market between cross and isolated margin.
+    /// Only a flat position can switch; leaving isolated mode returns its margin to the
+    /// collateral, so a deficit must be topped up first.
+    pub fn process(&mut self, isolated: bool) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let position = &mut self.user_position;
+        position.open_if_new(self.user.key(), self.market.key(), now);
+        require!(position.base_position == 0, PerpError::PositionNotFlat);
+
+        if position.is_isolated && !isolated {
+            require!(position.isolated_margin >= 0, PerpError::InsufficientCollateral);
+            let user_colletral = &mut self.user_colletral;
+            user_colletral.collateral_amount = user_colletral
+                .collateral_amount
+                .checked_add(position.isolated_margin)
+                .ok_or(PerpError::MathOverflow)?;
+            position.isolated_margin = 0;
+        }
+        position.is_isolated = isolated;
+        position.updated_at = now;
+
+        emit!(MarginModeSet {
+            market: self.market.key(),
+            owner: self.user.key(),
+            isolated,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct MarginModeSet {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub isolated: bool,
+}
@@ -0,0 +1,48 @@
+use anchor_lang::prelude::*;
+
+use crate::{GlobalConfig, PerpError};
+
+#[derive(Accounts)]
+pub struct SetReferralParams<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"global_config"],
+        bump = global_config.bump,
+        has_one = authority @ PerpError::NotAuthorized
+    )]
+    pub global_config: Box<Account<'info, GlobalConfig>>,
+}
+
+impl<'info> SetReferralParams<'info> {
+    /// Set the fractions of a referred taker's fee that go to the referrer and are waived
+    /// for the taker. Together they may not exceed the whole fee, and what the protocol keeps
+    /// of the fee at every tier must still cover the maker rebates. Referrals need a tier
+    /// table: the markets' own fees are not checked against them.
+    pub fn process(&mut self, referrer_share_bps: u16, referee_discount_bps: u16) -> Result<()> {
+        GlobalConfig::validate_referral_params(referrer_share_bps, referee_discount_bps)?;
+        let config = &mut self.global_config;
+        require!(
+            !config.fee_tiers.is_empty() || (referrer_share_bps == 0 && referee_discount_bps == 0),
+            PerpError::InvalidReferralParams
+        );
+        require!(
+            GlobalConfig::rebates_covered(&config.fee_tiers, referrer_share_bps, referee_discount_bps),
+            PerpError::InvalidReferralParams
+        );
+        config.referrer_share_bps = referrer_share_bps;
+        config.referee_discount_bps = referee_discount_bps;
+
+        emit!(ReferralParamsUpdated {
+            referrer_share_bps,
+            referee_discount_bps,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct ReferralParamsUpdated {
+    pub referrer_share_bps: u16,
+    pub referee_discount_bps: u16,
+}
@@ -1,19 +1,49 @@
 use anchor_lang::prelude::*;
 
-use crate::MarketState;
+use crate::{MarketState, PerpError, PriceAccount, ORACLE_MAGIC, ORACLE_STATUS_TRADING};
 
+/// Publishes a price to a mock oracle for local testing. Only price accounts owned by
+/// this program can be written, so a market pointed at a real feed cannot be moved.
 #[derive(Accounts)]
 pub struct SetMarkPrice<'info> {
-    #[account(mut)]
-    pub market : Account<'info,MarketState>,
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump,
+        has_one = authority @ PerpError::NotAuthorized
+    )]
+    pub market: Account<'info, MarketState>,
+    /// CHECK: mock price account created by the authority with `PriceAccount::LEN` bytes
+    #[account(
+        mut,
+        address = market.oracle_pubkey @ PerpError::OracleMismatch,
+        owner = crate::ID @ PerpError::InvalidOracleAccount
+    )]
+    pub oracle: UncheckedAccount<'info>,
 }
-impl <'info> SetMarkPrice<'info>{
-    pub fn process(
-        &mut self,
-        mark_price:u64
-    )->Result<()>{
-        let market = &mut self.market;
-        market.last_oracle_price = mark_price as i64;
+
+impl<'info> SetMarkPrice<'info> {
+    pub fn process(&mut self, price: i64, conf: u64, expo: i32) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let oracle_info = self.oracle.to_account_info();
+        {
+            let mut data = oracle_info.try_borrow_mut_data()?;
+            require!(data.len() >= PriceAccount::LEN, PerpError::InvalidOracleAccount);
+            let account = PriceAccount {
+                magic: ORACLE_MAGIC,
+                status: ORACLE_STATUS_TRADING,
+                expo,
+                _padding: 0,
+                price,
+                conf,
+                publish_time: now,
+            };
+            data[..PriceAccount::LEN].copy_from_slice(bytemuck::bytes_of(&account));
+        }
+
+        let reading = self.market.refresh_oracle(&oracle_info, now)?;
+        msg!("SetMarkPrice: oracle price={} conf={}", reading.price, reading.conf);
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,57 @@
+use anchor_lang::prelude::*;
+
+use crate::{MarketState, PerpError, Position, PositionManager, UserCollateral};
+
+#[derive(Accounts)]
+#[instruction(user_key : Pubkey)]
+pub struct SettleFunding<'info> {
+    #[account(
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    #[account(
+        mut,
+        seeds = [b"position", market.symbol.as_bytes(), user_key.as_ref()],
+        bump
+    )]
+    pub user_position: Account<'info, Position>,
+    #[account(
+        mut,
+        seeds = [b"user_colletral", user_key.as_ref()],
+        bump
+    )]
+    pub user_collateral: Account<'info, UserCollateral>,
+}
+
+impl<'info> SettleFunding<'info> {
+    /// Permissionless: apply the funding `user_key`'s position has accrued since it last
+    /// settled, so an idle position pays or receives funding without trading.
+    pub fn process(&mut self, user_key: Pubkey) -> Result<()> {
+        require!(user_key == self.user_position.owner, PerpError::Unauthorized);
+
+        let from_cum_funding = self.user_position.last_cum_funding;
+        let payment = PositionManager::settle_funding(&self.market, &mut self.user_position, &mut self.user_collateral)?;
+
+        emit!(FundingSettled {
+            market: self.market.key(),
+            owner: user_key,
+            base_position: self.user_position.base_position,
+            from_cum_funding,
+            to_cum_funding: self.market.cum_funding,
+            payment: i64::try_from(payment).map_err(|_| PerpError::MathOverflow)?,
+        });
+        Ok(())
+    }
+}
+
+/// `payment` is what the position paid; negative when it received funding.
+#[event]
+pub struct FundingSettled {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub base_position: i64,
+    pub from_cum_funding: i64,
+    pub to_cum_funding: i64,
+    pub payment: i64,
+}
@@ -0,0 +1,71 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+
+use crate::{GlobalConfig, MarketState};
+
+#[derive(Accounts)]
+pub struct SweepFees<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    #[account(
+        seeds = [b"global_config"],
+        bump = global_config.bump
+    )]
+    pub global_config: Account<'info, GlobalConfig>,
+    #[account(
+        mut,
+        address = global_config.vault_quote
+    )]
+    pub vault_quote: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        address = global_config.fee_pool
+    )]
+    pub fee_pool: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+impl<'info> SweepFees<'info> {
+    /// Permissionless: move the market's net unswept fees from `vault_quote` to the fee
+    /// pool. The destination is fixed by the global config, so anyone may call it.
+    pub fn process(&mut self) -> Result<()> {
+        let amount = self.market.fees.sweepable().min(self.vault_quote.amount);
+        if amount == 0 {
+            msg!("SweepFees: nothing to sweep");
+            return Ok(());
+        }
+        self.market.fees.record_sweep(amount)?;
+
+        let signer_seeds: &[&[u8]] = &[b"global_config", &[self.global_config.bump]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                Transfer {
+                    from: self.vault_quote.to_account_info(),
+                    to: self.fee_pool.to_account_info(),
+                    authority: self.global_config.to_account_info(),
+                },
+                &[signer_seeds],
+            ),
+            amount,
+        )?;
+
+        emit!(FeesSwept {
+            market: self.market.key(),
+            amount,
+            total_swept: self.market.fees.swept,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct FeesSwept {
+    pub market: Pubkey,
+    pub amount: u64,
+    pub total_swept: u64,
+}
@@ -0,0 +1,130 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    push_out, EventQueue, MarketState, Order, PerpError, RequestQueue, RequestType, SelfTradeMode, TriggerBook,
+    TriggerOrderRemoved, MAX_TO_PROCESS,
+};
+
+#[derive(Accounts)]
+pub struct TriggerOrders<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    /// CHECK: price account at `market.oracle_pubkey`, parsed by the oracle adapter
+    #[account(address = market.oracle_pubkey @ PerpError::OracleMismatch)]
+    pub oracle: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"trigger_book", market.symbol.as_bytes()],
+        bump = trigger_book.bump
+    )]
+    pub trigger_book: Account<'info, TriggerBook>,
+    #[account(
+        mut,
+        seeds = [b"request_queue", market.symbol.as_bytes()],
+        bump
+    )]
+    pub request_queue: AccountLoader<'info, RequestQueue>,
+    #[account(
+        mut,
+        seeds = [b"event_queue", market.symbol.as_bytes()],
+        bump
+    )]
+    pub event_queue: AccountLoader<'info, EventQueue>,
+}
+
+impl<'info> TriggerOrders<'info> {
+    /// Permissionless crank: queue every trigger the mark price has crossed as a Place
+    /// request and drop expired ones, up to `MAX_TO_PROCESS` of each per call.
+    pub fn process(&mut self) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let market_key = self.market.key();
+        self.market.refresh_oracle(&self.oracle, now)?;
+        let mark_price = u64::try_from(self.market.get_mark_price()?)
+            .map_err(|_| crate::PerpError::MathOverflow)?;
+
+        let request_queue = &mut self.request_queue.load_mut()?;
+        let event_queue = &mut self.event_queue.load_mut()?;
+        let orders = &mut self.trigger_book.orders;
+
+        let mut fired = 0;
+        let mut expired = 0;
+        let mut i = 0;
+        while i < orders.len() {
+            let trigger = &orders[i];
+            let order = Order {
+                user: trigger.owner.to_bytes(),
+                order_id: trigger.order_id,
+                side: trigger.side,
+                qty: trigger.qty,
+                order_type: trigger.kind.order_type(),
+                limit_price: trigger.limit_price,
+                initial_margin: 0,
+                leverage: 0,
+                market: market_key,
+                reduce_only: trigger.reduce_only,
+                // a fired stop pulls the owner's own quotes rather than dying on them or
+                // shrinking by their size
+                self_trade: SelfTradeMode::CancelMaker,
+                client_order_id: trigger.client_order_id,
+                expires_at: 0,
+                fee_tier: 0,
+            };
+
+            if trigger.is_expired(now) {
+                if expired >= MAX_TO_PROCESS || event_queue.count >= event_queue.capacity {
+                    i += 1;
+                    continue;
+                }
+                // release the reserved margin through the position crank
+                if !trigger.reduce_only {
+                    push_out(event_queue, &order, order.qty, now)?;
+                }
+                emit!(TriggerOrderRemoved {
+                    market: market_key,
+                    order_id: trigger.order_id,
+                    owner: trigger.owner,
+                    expired: true,
+                });
+                orders.remove(i);
+                expired += 1;
+                continue;
+            }
+
+            if trigger.kind.is_triggered(trigger.side, trigger.trigger_price, mark_price) {
+                if fired >= MAX_TO_PROCESS || request_queue.count >= request_queue.capacity {
+                    i += 1;
+                    continue;
+                }
+                emit!(TriggerOrderFired {
+                    market: market_key,
+                    order_id: trigger.order_id,
+                    owner: trigger.owner,
+                    trigger_price: trigger.trigger_price,
+                    mark_price,
+                });
+                request_queue.push(&RequestType::Place(order))?;
+                orders.remove(i);
+                fired += 1;
+                continue;
+            }
+
+            i += 1;
+        }
+
+        msg!("TriggerOrders: fired={} expired={} pending={}", fired, expired, orders.len());
+        Ok(())
+    }
+}
+
+#[event]
+pub struct TriggerOrderFired {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub owner: Pubkey,
+    pub trigger_price: u64,
+    pub mark_price: u64,
+}
@@ -0,0 +1,65 @@
+use anchor_lang::prelude::*;
+
+use crate::{accrue_funding, MarketState, PerpError};
+
+#[derive(Accounts)]
+pub struct UpdateFunding<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, MarketState>,
+    /// CHECK: price account at `market.oracle_pubkey`, parsed by the oracle adapter
+    #[account(address = market.oracle_pubkey @ PerpError::OracleMismatch)]
+    pub oracle: UncheckedAccount<'info>,
+}
+
+impl<'info> UpdateFunding<'info> {
+    /// Permissionless crank: once `funding_interval_secs` has passed, move `cum_funding`
+    /// by the clamped rate for every whole interval since the last update, from the
+    /// premium TWAP since then. Fails on a stale or wide oracle rather than charging
+    /// funding against an old price.
+    pub fn process(&mut self) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let oracle = self.market.refresh_oracle(&self.oracle, now)?;
+        let mark_price = self.market.get_mark_price()?;
+
+        let update = accrue_funding(&mut self.market, now)?;
+        msg!(
+            "UpdateFunding: rate={} intervals={} cum_funding={}",
+            update.rate,
+            update.intervals,
+            update.cum_funding
+        );
+
+        emit!(FundingUpdated {
+            market: self.market.key(),
+            rate: update.rate as i64,
+            premium: update.components.premium as i64,
+            interest: update.components.interest as i64,
+            intervals: update.intervals,
+            cum_funding: update.cum_funding,
+            mark_price: mark_price as u64,
+            oracle_price: oracle.price,
+            funding_ts: update.last_funding_ts,
+        });
+        Ok(())
+    }
+}
+
+/// Rates are per interval in `FUNDING_SCALE` units; `premium` comes from the window's
+/// TWAP, and `rate` is after the clamp, so it can differ from `premium + interest`.
+/// `mark_price` is the spot mark at the crank, for reference only.
+#[event]
+pub struct FundingUpdated {
+    pub market: Pubkey,
+    pub rate: i64,
+    pub premium: i64,
+    pub interest: i64,
+    pub intervals: i64,
+    pub cum_funding: i64,
+    pub mark_price: u64,
+    pub oracle_price: u64,
+    pub funding_ts: i64,
+}
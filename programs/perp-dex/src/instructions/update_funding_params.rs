@@ -0,0 +1,38 @@
+use anchor_lang::prelude::*;
+
+use crate::{FundingParams, MarketState, PerpError};
+
+#[derive(Accounts)]
+pub struct UpdateFundingParams<'info> {
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"market", market.symbol.as_bytes()],
+        bump = market.bump,
+        has_one = authority @ PerpError::NotAuthorized
+    )]
+    pub market: Account<'info, MarketState>,
+}
+
+impl<'info> UpdateFundingParams<'info> {
+    /// Replace the market's funding parameters. They apply from the next `update_funding`,
+    /// including to intervals that have already elapsed but not been settled.
+    pub fn process(&mut self, params: FundingParams) -> Result<()> {
+        let previous = self.market.funding_params();
+        self.market.set_funding_params(params)?;
+
+        emit!(FundingParamsUpdated {
+            market: self.market.key(),
+            previous,
+            current: params,
+        });
+        Ok(())
+    }
+}
+
+#[event]
+pub struct FundingParamsUpdated {
+    pub market: Pubkey,
+    pub previous: FundingParams,
+    pub current: FundingParams,
+}
@@ -4,7 +4,7 @@ use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken, token::{self, Mint, Token, TokenAccount, Transfer}
 };
-use crate::{GlobalConfig, MarketState, PerpError, Position, Ratio, RiskEngine, UserCollateral};
+use crate::{GlobalConfig, MarketState, PerpError, Position, PositionManager, PositionRisk, RiskEngine, UserCollateral};
 #[derive(Accounts)]
 pub struct  Withdraw<'info> {
     #[account(mut)]
@@ -43,6 +43,9 @@ pub struct  Withdraw<'info> {
         bump
     )]
     pub market : Account<'info,MarketState>,
+    /// CHECK: price account at `market.oracle_pubkey`, parsed by the oracle adapter
+    #[account(address = market.oracle_pubkey @ PerpError::OracleMismatch)]
+    pub oracle : UncheckedAccount<'info>,
 
 
     #[account(
@@ -59,47 +62,45 @@ pub struct  Withdraw<'info> {
 }
 
 impl <'info> Withdraw <'info> {
+    /// `remaining_accounts` holds the market, oracle and position of each of the user's
+    /// other open positions, so collateral backing them cannot be withdrawn.
     pub fn process(
         &mut self,
-        withdraw_amount:u64
+        withdraw_amount:u64,
+        remaining_accounts: &[AccountInfo],
     )->Result<()>{
         let user_colletral = &mut self.user_colletral;
         let vault_quote = &mut self.vault_quote;
         let user_position = &mut self.user_position_;
-        let market = &self.market;
+        let market = &mut self.market;
         let global_config = &self.global_config;
 
+        // funding accrued while the position sat idle changes both collateral and health
+        PositionManager::settle_funding(market, user_position, user_colletral)?;
+
         let available = user_colletral.collateral_amount;
         let withdraw_i128 = withdraw_amount as i128;
 
         require!(withdraw_i128 > 0 ,PerpError::InvalidAmount);
-        require!(available >= withdraw_i128,PerpError::InsufficientCollateral);
+        require!(user_colletral.free_collateral()? >= withdraw_i128,PerpError::InsufficientCollateral);
         
         //lets compute helth after withdrawls
         let new_colletral = available
             .checked_sub(withdraw_i128)
             .ok_or(PerpError::MathOverflow)?;
+        // margin reserved for open orders cannot also back the position
+        let new_free = user_colletral.free_collateral()?
+            .checked_sub(withdraw_i128)
+            .ok_or(PerpError::MathOverflow)?;
 
-        let mark_price = market.get_mark_price()?;
-        let maintain_ratio = Ratio::from_bps(market.mm_bps);
-
-
-        let unreal = RiskEngine::unrealized_pnl(
-            user_position.base_position as i128,
-            user_position.entry_price as u128,
-            mark_price
-        )?;
-
-        let mainatenance = RiskEngine::maintenance_margin(
-            user_position.base_position as i128,
-            mark_price,
-            maintain_ratio
-        )?;
+        let now = Clock::get()?.unix_timestamp;
+        market.refresh_oracle(&self.oracle, now)?;
+        let mut positions = user_colletral.cross_position_risks(market.key(), remaining_accounts, now)?;
+        if !user_position.is_isolated {
+            positions.push(PositionRisk::new(market, user_position)?);
+        }
 
-        let health_after = new_colletral
-            .checked_add(unreal)
-            .and_then(|v|v.checked_sub(mainatenance as i128))
-            .ok_or(PerpError::MathOverflow)?;
+        let health_after = RiskEngine::account_health_cross(new_free, &positions)?;
 
         require!(health_after>0 , PerpError::WithdrawWouldLiquidate);
 
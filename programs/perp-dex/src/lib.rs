@@ -1,3 +1,6 @@
+// anchor's `#[program]` expansion still calls the deprecated `AccountInfo::realloc`
+#![allow(deprecated)]
+
 pub mod constants;
 pub mod error;
 pub mod instructions;
@@ -27,9 +30,8 @@ pub mod perp_dex {
     pub fn initalise_global_config(
         ctx: Context<InitializeGlobalConfig>,
         is_paused: bool,
-        funding_interval_secs: u32,
     ) -> Result<()> {
-        ctx.accounts.process(is_paused, funding_interval_secs, &ctx.bumps)?;
+        ctx.accounts.process(is_paused, &ctx.bumps)?;
         Ok(())
     }
 
@@ -43,17 +45,101 @@ pub mod perp_dex {
     }
 
     pub fn place_order(ctx: Context<PlaceOrder>, order: Order) -> Result<()> {
-        ctx.accounts.process(order)?;
+        ctx.accounts.process(order, ctx.remaining_accounts)?;
+        Ok(())
+    }
+
+    pub fn cancel_order(ctx: Context<CancelOrderIns>, order_id: u128, side: Side) -> Result<()> {
+        ctx.accounts.process(order_id, side)?;
+        Ok(())
+    }
+
+    pub fn cancel_order_by_client_id(ctx: Context<CancelOrderIns>, client_order_id: u64, side: Side) -> Result<()> {
+        ctx.accounts.process_by_client_id(client_order_id, side)?;
+        Ok(())
+    }
+
+    pub fn cancel_all_orders(ctx: Context<CancelAllOrders>, params: CancelAllParams) -> Result<()> {
+        ctx.accounts.process(params)?;
+        Ok(())
+    }
+
+    pub fn initialize_trigger_book(ctx: Context<InitializeTriggerBook>) -> Result<()> {
+        ctx.accounts.process(&ctx.bumps)?;
+        Ok(())
+    }
+
+    pub fn place_trigger_order(ctx: Context<PlaceTriggerOrder>, params: TriggerOrderParams) -> Result<()> {
+        ctx.accounts.process(params, ctx.remaining_accounts)?;
+        Ok(())
+    }
+
+    pub fn cancel_trigger_order(ctx: Context<CancelTriggerOrder>, order_id: u128) -> Result<()> {
+        ctx.accounts.process(order_id)?;
+        Ok(())
+    }
+
+    pub fn trigger_orders(ctx: Context<TriggerOrders>) -> Result<()> {
+        ctx.accounts.process()?;
+        Ok(())
+    }
+
+    pub fn prune_expired(ctx: Context<PruneExpired>) -> Result<()> {
+        ctx.accounts.process()?;
+        Ok(())
+    }
+
+    pub fn update_funding(ctx: Context<UpdateFunding>) -> Result<()> {
+        ctx.accounts.process()?;
+        Ok(())
+    }
+
+    pub fn update_funding_params(ctx: Context<UpdateFundingParams>, params: FundingParams) -> Result<()> {
+        ctx.accounts.process(params)?;
+        Ok(())
+    }
+
+    pub fn settle_funding(ctx: Context<SettleFunding>, user_key: Pubkey) -> Result<()> {
+        ctx.accounts.process(user_key)?;
+        Ok(())
+    }
+
+    pub fn sweep_fees(ctx: Context<SweepFees>) -> Result<()> {
+        ctx.accounts.process()?;
+        Ok(())
+    }
+
+    pub fn set_fee_tiers(ctx: Context<SetFeeTiers>, fee_tiers: Vec<FeeTier>) -> Result<()> {
+        ctx.accounts.process(fee_tiers)?;
+        Ok(())
+    }
+
+    pub fn set_referral_params(
+        ctx: Context<SetReferralParams>,
+        referrer_share_bps: u16,
+        referee_discount_bps: u16,
+    ) -> Result<()> {
+        ctx.accounts.process(referrer_share_bps, referee_discount_bps)?;
+        Ok(())
+    }
+
+    pub fn bind_referrer(ctx: Context<BindReferrer>) -> Result<()> {
+        ctx.accounts.process()?;
+        Ok(())
+    }
+
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        ctx.accounts.process()?;
         Ok(())
     }
      
-    pub fn set_mark_price(ctx: Context<SetMarkPrice>, mark_price: u64) -> Result<()> {
-        ctx.accounts.process(mark_price)?;
+    pub fn set_mark_price(ctx: Context<SetMarkPrice>, price: i64, conf: u64, expo: i32) -> Result<()> {
+        ctx.accounts.process(price, conf, expo)?;
         Ok(())
     }
     
-    pub fn process_place_order(ctx: Context<ProcessOrder>) -> Result<()> {
-        ctx.accounts.process()?;
+    pub fn process_place_order<'info>(ctx: Context<'_, '_, 'info, 'info, ProcessOrder<'info>>) -> Result<()> {
+        ctx.accounts.process(ctx.remaining_accounts)?;
         Ok(())
     }
     pub fn reset_queues(ctx: Context<ResetQueue>) -> Result<()> {
@@ -71,17 +157,32 @@ pub mod perp_dex {
     }
 
     pub fn liquidate(ctx: Context<Liquidation>) -> Result<()> {
-        ctx.accounts.process()?;
+        ctx.accounts.process(ctx.remaining_accounts)?;
         Ok(())
     }
 
     pub fn deposit_colletral(ctx: Context<DepositColletral>, amount: u64) -> Result<()> {
-        ctx.accounts.process(amount)?;
+        ctx.accounts.process(amount, &ctx.bumps)?;
+        Ok(())
+    }
+
+    pub fn set_margin_mode(ctx: Context<SetMarginMode>, isolated: bool) -> Result<()> {
+        ctx.accounts.process(isolated)?;
+        Ok(())
+    }
+
+    pub fn add_isolated_margin(ctx: Context<AdjustIsolatedMargin>, amount: u64) -> Result<()> {
+        ctx.accounts.add(amount, ctx.remaining_accounts)?;
+        Ok(())
+    }
+
+    pub fn remove_isolated_margin(ctx: Context<AdjustIsolatedMargin>, amount: u64) -> Result<()> {
+        ctx.accounts.remove(amount)?;
         Ok(())
     }
 
     pub fn withdraw(ctx: Context<Withdraw>, withdraw_amount: u64) -> Result<()> {
-        ctx.accounts.process(withdraw_amount)?;
+        ctx.accounts.process(withdraw_amount, ctx.remaining_accounts)?;
         Ok(())
     }
 
@@ -1,5 +1,7 @@
 use anchor_lang::prelude::*;
 
+use crate::{MarketState, PerpError, MAX_FEE_TIERS};
+
 #[account]
 #[derive(InitSpace)]
 pub struct GlobalConfig{
@@ -7,9 +9,111 @@ pub struct GlobalConfig{
     pub vault_quote:Pubkey,
     pub insurance_fund : Pubkey,
     pub fee_pool :Pubkey,
-    pub request_queue : Pubkey,
-    pub event_queue : Pubkey,
     pub trading_paused : bool,
-    pub funding_interval_secs :u32,  //How often the funding rate updates usually every 1-8 hours
+    #[max_len(MAX_FEE_TIERS)]
+    pub fee_tiers: Vec<FeeTier>,  // by ascending 30-day volume; empty = every market's own fees
+    pub referrer_share_bps: u16,    // of a referred taker's fee, credited to the referrer
+    pub referee_discount_bps: u16,  // of a referred taker's fee, waived
     pub bump:u8
-}
\ No newline at end of file
+}
+
+/// Fees for users whose trailing 30-day volume is at least `min_volume` quote units.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct FeeTier {
+    pub min_volume: u64,
+    pub taker_fee_bps: u16,
+    pub maker_rebate_bps: u16,
+}
+
+/// Fee rates applied to one fill. The referral shares are fractions of the taker fee
+/// and are zero for users without a referrer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeRates {
+    pub taker_fee_bps: u16,
+    pub maker_rebate_bps: u16,
+    pub referrer_share_bps: u16,
+    pub referee_discount_bps: u16,
+}
+
+impl FeeRates {
+    pub fn from_market(market: &MarketState) -> Self {
+        Self {
+            taker_fee_bps: market.taker_fee_bps,
+            maker_rebate_bps: market.maker_fee_bps,
+            referrer_share_bps: 0,
+            referee_discount_bps: 0,
+        }
+    }
+}
+
+impl GlobalConfig {
+    pub fn validate_fee_tiers(tiers: &[FeeTier]) -> Result<()> {
+        require!(tiers.len() <= MAX_FEE_TIERS, PerpError::InvalidFeeTiers);
+        if let Some(first) = tiers.first() {
+            require!(first.min_volume == 0, PerpError::InvalidFeeTiers);
+        }
+        require!(
+            tiers.windows(2).all(|pair| pair[0].min_volume < pair[1].min_volume),
+            PerpError::InvalidFeeTiers
+        );
+        require!(
+            tiers
+                .iter()
+                .all(|tier| tier.maker_rebate_bps <= tier.taker_fee_bps && tier.taker_fee_bps <= 10_000),
+            PerpError::InvalidFeeTiers
+        );
+        require!(Self::rebates_covered(tiers, 0, 0), PerpError::InvalidFeeTiers);
+        Ok(())
+    }
+
+    /// Whether the taker fee at every tier, less a referred taker's `referee_discount_bps`
+    /// and the referrer's `referrer_share_bps` of it, pays for the maker rebate at any tier:
+    /// maker and taker of a fill need not share a tier.
+    pub fn rebates_covered(tiers: &[FeeTier], referrer_share_bps: u16, referee_discount_bps: u16) -> bool {
+        let max_rebate = tiers.iter().map(|tier| tier.maker_rebate_bps).max().unwrap_or(0);
+        let min_fee = tiers.iter().map(|tier| tier.taker_fee_bps).min().unwrap_or(0);
+        let kept_bps = 10_000u64.saturating_sub(referrer_share_bps as u64 + referee_discount_bps as u64);
+        max_rebate as u64 * 10_000 <= min_fee as u64 * kept_bps
+    }
+
+    pub fn validate_referral_params(referrer_share_bps: u16, referee_discount_bps: u16) -> Result<()> {
+        require!(
+            referrer_share_bps as u32 + referee_discount_bps as u32 <= 10_000,
+            PerpError::InvalidReferralParams
+        );
+        Ok(())
+    }
+
+    /// Index of the highest tier `volume` reaches; 0 without a tier table.
+    pub fn fee_tier(&self, volume: u64) -> u8 {
+        self.fee_tiers
+            .iter()
+            .rposition(|tier| volume >= tier.min_volume)
+            .unwrap_or(0) as u8
+    }
+
+    /// Rates for a user with `volume` of trailing 30-day volume; the market's own fees
+    /// until a tier table is set. `referred` users get the referral split.
+    pub fn fee_rates(&self, market: &MarketState, volume: u64, referred: bool) -> FeeRates {
+        self.fee_rates_at_tier(market, self.fee_tier(volume), referred)
+    }
+
+    /// Rates of tier `tier`, as stamped on a resting order at placement. A tier past the end
+    /// of the current table gets its top tier, so every fill is charged from one table.
+    pub fn fee_rates_at_tier(&self, market: &MarketState, tier: u8, referred: bool) -> FeeRates {
+        let tier = (tier as usize).min(self.fee_tiers.len().saturating_sub(1));
+        let mut rates = match self.fee_tiers.get(tier) {
+            Some(tier) => FeeRates {
+                taker_fee_bps: tier.taker_fee_bps,
+                maker_rebate_bps: tier.maker_rebate_bps,
+                ..FeeRates::from_market(market)
+            },
+            None => FeeRates::from_market(market),
+        };
+        if referred {
+            rates.referrer_share_bps = self.referrer_share_bps;
+            rates.referee_discount_bps = self.referee_discount_bps;
+        }
+        rates
+    }
+}
@@ -1,6 +1,10 @@
 use anchor_lang::prelude::*;
 
-use crate::{Order, PerpError};
+use crate::{
+    book_mid, compute_mark_price, premium_index, read_oracle, update_fill_ema, OraclePrice, Order, PerpError, Side,
+    MAX_FUNDING_PERIOD_SECS, MAX_FUNDING_RATE_BPS, MAX_INTEREST_RATE_BPS, MAX_PREMIUM_DAMPENER_BPS,
+    MIN_FUNDING_INTERVAL_SECS, ORACLE_MAX_STALENESS_SECS,
+};
 
 #[account]
 #[derive(InitSpace)]
@@ -11,24 +15,37 @@ pub struct MarketState{
     pub oracle_pubkey : Pubkey,
     pub last_oracle_price : i64,
     pub last_oracle_ts : i64,
+    pub mark_price : u64,      // last computed by `refresh_mark_price`; read through `get_mark_price`
+    pub book_mid_price : u64,  // 0 while either side of the book is empty
+    pub fill_price_ema : u64,  // 0 until the first fill
 
     pub bid : Pubkey,
     pub asks : Pubkey,
+    pub request_queue : Pubkey,  // per-market request queue, seeded by symbol
+    pub event_queue : Pubkey,    // per-market fill queue, seeded by symbol
   
     // risk/fees (overrides)
     pub im_bps : u16,
     pub mm_bps :u16,
 
     pub taker_fee_bps :u16,
-    pub maker_fee_bps : u16,
+    pub maker_fee_bps : u16,   // rebate paid to the maker, at most the taker fee
+    pub fees: FeeLedger,
     pub liquidator_share_bps :u16, //percentage of the liquidation penalty that goes to the liquidator
     pub liq_penalty_bps:u16,//percentage charged when a user is liquidated. Often part goes to liquidators, part to the insurance fund.
-    pub oracle_band_bps: u16,  //oracle_band_bps defines the maximum allowed difference after that trading will stop and perp price stay between these 
+    pub oracle_band_bps: u16,  //max distance from the oracle for limit prices and fills; matching stops past it. 0 = no band
 
     pub cum_funding:i64,
     pub last_funding_ts :i64,
-    pub max_funding_rate: i64,
+    pub max_funding_rate: i64,      // clamp per funding interval, bps
     pub funding_interval_secs :u32,
+    pub interest_rate_bps: u16,     // per funding period; charged even when the premium is flat
+    pub premium_dampener_bps: u16,  // premium within this of zero is ignored, only the excess is charged
+    pub funding_period_secs: u32,   // period the premium and interest rate are quoted over
+    pub premium_accumulator: i128,  // sum of premium * seconds held since `premium_window_start`
+    pub last_premium: i64,          // mark vs oracle at the last sample, FUNDING_SCALE units
+    pub premium_sample_ts: i64,
+    pub premium_window_start: i64,  // reset by every funding update
     
     pub tick_size :u16,  
     pub step_size :u8,  // the minimum quantity you can buy or sell in that market
@@ -36,14 +53,274 @@ pub struct MarketState{
     pub bump:u8
 
 }
+/// Prices the match loop may trade at, around the oracle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriceBand {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl PriceBand {
+    /// No band: every price is tradable.
+    pub const NONE: PriceBand = PriceBand { min: 0, max: u64::MAX };
+
+    #[inline]
+    pub fn contains(&self, price: u64) -> bool {
+        (self.min..=self.max).contains(&price)
+    }
+
+    /// Whether a maker at `price` is past the edge a taker on `side` trades towards: above the
+    /// band for a buy, below it for a sell.
+    #[inline]
+    pub fn is_adverse(&self, side: Side, price: u64) -> bool {
+        match side {
+            Side::Buy => price > self.max,
+            Side::Sell => price < self.min,
+        }
+    }
+}
+
+/// Fees charged and rebated on this market's fills, in quote units. The net sits in
+/// `vault_quote` until `sweep_fees` moves it to the fee pool; referral rewards stay
+/// behind for their referrers to claim.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug, PartialEq, Eq, InitSpace)]
+pub struct FeeLedger {
+    pub taker_fees: u64,        // after referee discounts
+    pub maker_rebates: u64,
+    pub referral_rewards: u64,  // credited to referrers out of `taker_fees`
+    pub swept: u64,
+}
+
+impl FeeLedger {
+    pub fn record_taker_fee(&mut self, fee: u64) -> Result<()> {
+        self.taker_fees = self.taker_fees.checked_add(fee).ok_or(PerpError::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_maker_rebate(&mut self, rebate: u64) -> Result<()> {
+        self.maker_rebates = self.maker_rebates.checked_add(rebate).ok_or(PerpError::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_referral_reward(&mut self, reward: u64) -> Result<()> {
+        self.referral_rewards = self.referral_rewards.checked_add(reward).ok_or(PerpError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Net fees not yet swept. Rebates on fills without a taker fee (liquidations) can
+    /// leave the ledger behind, in which case nothing is sweepable until fees catch up.
+    pub fn sweepable(&self) -> u64 {
+        let net = self.taker_fees as i128
+            - self.maker_rebates as i128
+            - self.referral_rewards as i128
+            - self.swept as i128;
+        net.clamp(0, u64::MAX as i128) as u64
+    }
+
+    pub fn record_sweep(&mut self, amount: u64) -> Result<()> {
+        require!(amount <= self.sweepable(), PerpError::InvalidAmount);
+        self.swept = self.swept.checked_add(amount).ok_or(PerpError::MathOverflow)?;
+        Ok(())
+    }
+}
+
+/// Funding parameters the market authority can change after launch.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FundingParams {
+    pub interest_rate_bps: u16,
+    pub premium_dampener_bps: u16,
+    pub funding_period_secs: u32,
+    pub funding_interval_secs: u32,
+    pub max_funding_rate: i64,
+}
+
+impl FundingParams {
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.funding_interval_secs >= MIN_FUNDING_INTERVAL_SECS
+                && self.funding_interval_secs <= self.funding_period_secs
+                && self.funding_period_secs <= MAX_FUNDING_PERIOD_SECS,
+            PerpError::InvalidFundingParams
+        );
+        require!(
+            self.interest_rate_bps <= MAX_INTEREST_RATE_BPS
+                && self.premium_dampener_bps <= MAX_PREMIUM_DAMPENER_BPS,
+            PerpError::InvalidFundingParams
+        );
+        require!(
+            self.max_funding_rate > 0 && self.max_funding_rate <= MAX_FUNDING_RATE_BPS,
+            PerpError::InvalidFundingParams
+        );
+        Ok(())
+    }
+}
+
 impl MarketState{
+    pub fn funding_params(&self)->FundingParams{
+        FundingParams {
+            interest_rate_bps: self.interest_rate_bps,
+            premium_dampener_bps: self.premium_dampener_bps,
+            funding_period_secs: self.funding_period_secs,
+            funding_interval_secs: self.funding_interval_secs,
+            max_funding_rate: self.max_funding_rate,
+        }
+    }
+
+    pub fn set_funding_params(&mut self,params:FundingParams)->Result<()>{
+        params.validate()?;
+        self.interest_rate_bps = params.interest_rate_bps;
+        self.premium_dampener_bps = params.premium_dampener_bps;
+        self.funding_period_secs = params.funding_period_secs;
+        self.funding_interval_secs = params.funding_interval_secs;
+        self.max_funding_rate = params.max_funding_rate;
+        Ok(())
+    }
+
+    /// Mark price against the current oracle: the book mid and fill EMA are stored,
+    /// the blend and clamp are redone on every read.
     pub fn get_mark_price(&self)->Result<u128>{
-        //For simplicity using last oracle price as mark price
+        let oracle = u64::try_from(self.oracle_price()?).map_err(|_| PerpError::MathOverflow)?;
+        let nonzero = |price: u64| (price > 0).then_some(price);
+        Ok(compute_mark_price(oracle, nonzero(self.book_mid_price), nonzero(self.fill_price_ema)) as u128)
+    }
+
+    /// Record the book's best prices and, if the last placement traded, its average fill
+    /// price, then store the resulting mark price.
+    pub fn refresh_mark_price(&mut self,best_bid:Option<u64>,best_ask:Option<u64>,fill_price:Option<u64>,now:i64)->Result<u64>{
+        self.book_mid_price = book_mid(best_bid, best_ask).unwrap_or(0);
+        if let Some(price) = fill_price {
+            self.fill_price_ema = update_fill_ema(self.fill_price_ema, price);
+        }
+        self.mark_price = self.get_mark_price()? as u64;
+        self.sample_premium(now)?;
+        Ok(self.mark_price)
+    }
+
+    /// Credit the premium held since the last sample for the time it was held, then
+    /// take a new sample from the current mark and oracle. A premium only counts from
+    /// when it is sampled, so moving the mark just before the funding crank barely
+    /// moves the window's TWAP.
+    pub fn sample_premium(&mut self,now:i64)->Result<()>{
+        if now > self.premium_sample_ts {
+            let held = (self.last_premium as i128)
+                .checked_mul((now - self.premium_sample_ts) as i128)
+                .ok_or(PerpError::MathOverflow)?;
+            self.premium_accumulator = self.premium_accumulator
+                .checked_add(held)
+                .ok_or(PerpError::MathOverflow)?;
+            self.premium_sample_ts = now;
+        }
+        let premium = premium_index(self.get_mark_price()?, self.oracle_price()?)?;
+        self.last_premium = i64::try_from(premium).map_err(|_| PerpError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Time-weighted premium from `premium_window_start` to `now`, with the last sample
+    /// held until `now`.
+    pub fn premium_twap(&self,now:i64)->Result<i128>{
+        let span = now - self.premium_window_start;
+        if span <= 0 {
+            return Ok(self.last_premium as i128);
+        }
+        let held = (self.last_premium as i128)
+            .checked_mul((now - self.premium_sample_ts).max(0) as i128)
+            .ok_or(PerpError::MathOverflow)?;
+        let total = self.premium_accumulator
+            .checked_add(held)
+            .ok_or(PerpError::MathOverflow)?;
+        Ok(total / span as i128)
+    }
+
+    /// Start a new premium window at `now`, keeping the last sample.
+    pub fn reset_premium_window(&mut self,now:i64){
+        self.premium_accumulator = 0;
+        self.premium_window_start = now;
+        self.premium_sample_ts = self.premium_sample_ts.max(now);
+    }
+
+    /// Read the oracle account and cache its price as `last_oracle_price`.
+    pub fn refresh_oracle(&mut self,oracle:&AccountInfo,now:i64)->Result<OraclePrice>{
+        let reading = read_oracle(oracle, self, now)?;
+        self.last_oracle_price = i64::try_from(reading.price).map_err(|_| PerpError::MathOverflow)?;
+        self.last_oracle_ts = reading.publish_time;
+        self.mark_price = self.get_mark_price()? as u64;
+        self.sample_premium(now)?;
+        Ok(reading)
+    }
+
+    pub fn oracle_price(&self)->Result<u128>{
         if self.last_oracle_price <=0 {
             return Err(PerpError::InvalidAmount.into());
         }
         Ok(self.last_oracle_price as u128)
     }
+
+    /// `oracle_band_bps` either side of the oracle; `PriceBand::NONE` when the band is 0.
+    pub fn price_band(&self)->Result<PriceBand>{
+        if self.oracle_band_bps == 0 {
+            return Ok(PriceBand::NONE);
+        }
+        let oracle = self.oracle_price()?;
+        let width = oracle
+            .checked_mul(self.oracle_band_bps as u128)
+            .ok_or(PerpError::MathOverflow)?
+            / 10_000;
+        Ok(PriceBand {
+            min: u64::try_from(oracle.saturating_sub(width)).map_err(|_| PerpError::MathOverflow)?,
+            max: u64::try_from(oracle + width).unwrap_or(u64::MAX),
+        })
+    }
+
+    /// `price_band` for matching at `now`. With the band on, the cached oracle price it is
+    /// built from must be no older than `ORACLE_MAX_STALENESS_SECS`.
+    pub fn live_price_band(&self,now:i64)->Result<PriceBand>{
+        if self.oracle_band_bps != 0 {
+            require!(
+                now.saturating_sub(self.last_oracle_ts) <= ORACLE_MAX_STALENESS_SECS,
+                PerpError::StaleOraclePrice
+            );
+        }
+        self.price_band()
+    }
+
+    /// Entry checks for a new order. Reduce-only orders skip the min notional so that
+    /// a position below it can still be closed.
+    pub fn validate_order(&self,order:&Order)->Result<()>{
+        require!(order.qty > 0, PerpError::OrderQtyZero);
+        require!(
+            order.qty % (self.step_size.max(1) as u64) == 0,
+            PerpError::QtyNotMultipleOfStep
+        );
+        if order.order_type.has_limit_price() {
+            require!(order.limit_price > 0, PerpError::InvalidLimitPrice);
+            require!(self.is_on_tick(order.limit_price), PerpError::PriceNotMultipleOfTick);
+        }
+        if !order.reduce_only {
+            require!(
+                self.order_notional(order)? >= self.min_order_notional as u128,
+                PerpError::OrderNotionalTooSmall
+            );
+        }
+        Ok(())
+    }
+
+    pub fn is_on_tick(&self,price:u64)->bool{
+        price % (self.tick_size.max(1) as u64) == 0
+    }
+
+    /// Notional at the order's limit price, or at the mark price for market orders.
+    pub fn order_notional(&self,order:&Order)->Result<u128>{
+        let price = if order.order_type.has_limit_price() {
+            order.limit_price as u128
+        } else {
+            self.get_mark_price()?
+        };
+        (order.qty as u128)
+            .checked_mul(price)
+            .ok_or(PerpError::MathOverflow.into())
+    }
+
+    /// Initial margin for `order`, on its notional at the mark price.
     pub fn compute_initial_margin(&self,order:Order)->Result<u128>{
         let mark_price = self.get_mark_price()?;
 
@@ -51,11 +328,6 @@ impl MarketState{
             .checked_mul(mark_price)
             .ok_or(PerpError::MathOverflow)?;
 
-        require!(
-            notional >= self.min_order_notional as u128,
-            PerpError::OrderNotionalTooSmall
-        );
-
         let im_bps = self.im_bps as u128;
 
         let im_required = notional
@@ -87,7 +359,136 @@ pub struct MarketParams {
     pub cum_funding: i64,
     pub last_funding_ts: i64,
     pub funding_interval_secs: u32,
+    pub interest_rate_bps: u16,
+    pub premium_dampener_bps: u16,
+    pub funding_period_secs: u32,
     pub tick_size: u16,
     pub step_size: u8,
     pub min_order_notional: u64,
 }
+
+#[cfg(test)]
+impl MarketState {
+    /// Market the unit tests start from; each test overrides the fields it exercises.
+    pub fn test_default() -> Self {
+        MarketState {
+            symbol: "BTC".to_string(),
+            authority: Pubkey::default(),
+            oracle_pubkey: Pubkey::default(),
+            last_oracle_price: 100_000,
+            last_oracle_ts: 0,
+            mark_price: 0,
+            book_mid_price: 0,
+            fill_price_ema: 0,
+            bid: Pubkey::default(),
+            asks: Pubkey::default(),
+            request_queue: Pubkey::default(),
+            event_queue: Pubkey::default(),
+            im_bps: 500,
+            mm_bps: 250,
+            taker_fee_bps: 5,
+            maker_fee_bps: 2,
+            fees: FeeLedger::default(),
+            liquidator_share_bps: 50,
+            liq_penalty_bps: 500,
+            oracle_band_bps: 100,
+            cum_funding: 0,
+            last_funding_ts: 0,
+            max_funding_rate: 0,
+            funding_interval_secs: 3600,
+            interest_rate_bps: 0,
+            premium_dampener_bps: 0,
+            funding_period_secs: 8 * 3600,
+            premium_accumulator: 0,
+            last_premium: 0,
+            premium_sample_ts: 0,
+            premium_window_start: 0,
+            tick_size: 1,
+            step_size: 1,
+            min_order_notional: 1000,
+            bump: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderType, SelfTradeMode, Side};
+
+    fn market() -> MarketState {
+        MarketState { last_oracle_price: 100, tick_size: 5, step_size: 10, ..MarketState::test_default() }
+    }
+
+    fn order(order_type: OrderType, qty: u64, limit_price: u64) -> Order {
+        Order {
+            user: [1; 32],
+            order_id: 0,
+            side: Side::Buy,
+            qty,
+            order_type,
+            limit_price,
+            initial_margin: 0,
+            leverage: 1,
+            market: Pubkey::default(),
+            reduce_only: false,
+            self_trade: SelfTradeMode::CancelTaker,
+            client_order_id: 0,
+            expires_at: 0,
+            fee_tier: 0,
+        }
+    }
+
+    #[test]
+    fn test_validate_order_reports_each_violation() {
+        let market = market();
+        let check = |o: Order| market.validate_order(&o);
+
+        assert!(check(order(OrderType::Limit, 20, 50)).is_ok());
+        assert_eq!(check(order(OrderType::Limit, 0, 50)).unwrap_err(), error!(PerpError::OrderQtyZero));
+        assert_eq!(check(order(OrderType::Limit, 25, 50)).unwrap_err(), error!(PerpError::QtyNotMultipleOfStep));
+        assert_eq!(check(order(OrderType::Limit, 20, 0)).unwrap_err(), error!(PerpError::InvalidLimitPrice));
+        assert_eq!(check(order(OrderType::PostOnly, 20, 52)).unwrap_err(), error!(PerpError::PriceNotMultipleOfTick));
+        // market orders carry no price to check
+        assert!(check(order(OrderType::Market, 10, 3)).is_ok());
+    }
+
+    #[test]
+    fn test_min_notional_uses_limit_price_and_skips_reduce_only() {
+        let market = market();
+        // 10 @ 95 is below the min notional, though 10 @ the mark of 100 is not
+        let low = order(OrderType::Limit, 10, 95);
+        assert_eq!(market.validate_order(&low).unwrap_err(), error!(PerpError::OrderNotionalTooSmall));
+        assert_eq!(market.order_notional(&order(OrderType::Market, 10, 0)).unwrap(), 1_000);
+
+        let mut closing = low.clone();
+        closing.reduce_only = true;
+        assert!(market.validate_order(&closing).is_ok());
+    }
+
+    #[test]
+    fn test_price_band_around_oracle() {
+        let mut market = market();
+        market.last_oracle_price = 20_000;
+        assert_eq!(market.price_band().unwrap(), PriceBand { min: 19_800, max: 20_200 });
+        assert!(market.price_band().unwrap().contains(20_200));
+        assert!(!market.price_band().unwrap().contains(20_201));
+
+        market.oracle_band_bps = 0;
+        assert_eq!(market.price_band().unwrap(), PriceBand::NONE);
+    }
+
+    #[test]
+    fn test_live_price_band_refuses_a_stale_oracle() {
+        let mut market = market();
+        market.last_oracle_price = 20_000;
+        market.last_oracle_ts = 1_000;
+        let now = 1_000 + ORACLE_MAX_STALENESS_SECS;
+        assert_eq!(market.live_price_band(now).unwrap(), market.price_band().unwrap());
+        assert_eq!(market.live_price_band(now + 1).unwrap_err(), error!(PerpError::StaleOraclePrice));
+
+        // without a band the oracle price is not used
+        market.oracle_band_bps = 0;
+        assert_eq!(market.live_price_band(now + 1).unwrap(), PriceBand::NONE);
+    }
+}
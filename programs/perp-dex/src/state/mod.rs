@@ -27,4 +27,10 @@ pub mod user_colletral;
 pub use user_colletral::*;
 
 pub mod slot;
-pub use slot::*;
\ No newline at end of file
+pub use slot::*;
+
+pub mod trigger_book;
+pub use trigger_book::*;
+
+pub mod user_stats;
+pub use user_stats::*;
\ No newline at end of file
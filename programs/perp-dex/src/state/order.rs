@@ -13,10 +13,27 @@ pub struct Order {
    pub initial_margin : u64,
    pub leverage : u8,
    pub market : Pubkey,
+   pub reduce_only : bool,  // may only shrink the position; capped at |base_position| whenever it trades
+   pub self_trade : SelfTradeMode,
+   pub client_order_id : u64,  // caller-chosen id, echoed in every event for this order
+   pub expires_at : i64,       // unix time the resting remainder leaves the book; 0 = good till cancelled
+   pub fee_tier : u8,          // owner's fee tier at placement, stamped on the resting leaf and charged on its maker fills
 }
 
 impl Order {
-    pub const SIZE: usize = 32 + 16 + 1 + 8 + 1 + 8 + 8 + 1 + 32; // = 107
+    pub const SIZE: usize = 32 + 16 + 1 + 8 + 1 + 8 + 8 + 1 + 32 + 1 + 1 + 8 + 8 + 1; // = 126
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at != 0 && now >= self.expires_at
+    }
+}
+
+/// What the match loop does when the taker meets a resting order of the same owner.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum SelfTradeMode {
+    CancelTaker,   // stop matching and drop the taker's remainder
+    CancelMaker,   // pull the resting order and keep matching
+    DecrementBoth, // shrink both by the overlapping qty without a fill
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
@@ -30,6 +47,20 @@ impl CancelOrder {
     pub const SIZE: usize = 16 + 32 + 1; // = 49
 }
 
+/// Cancel the signer's resting order carrying `client_order_id` on `side`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct CancelByClientId {
+   pub client_order_id: u64,
+   pub user: Pubkey,
+   pub side: Side,
+}
+
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum EventKind {
+    Fill,
+    Out, // order left the book (or never rested) without trading; fill_qty is the quantity removed
+}
 
 #[account]
 pub struct MatchedOrder {
@@ -40,8 +71,20 @@ pub struct MatchedOrder {
     pub fill_qty: u64,
     pub side: Side,
     pub timestamp: i64,
+    pub kind: EventKind,
+    pub client_order_id: u64,
+    pub reduce_only: bool, // fill of a reduce-only order, counted in `Position::reduce_only_pending`
+    pub liquidation: bool, // maker fill against a liquidation, whose taker pays no fee to fund a rebate
+    pub maker_fee_tier: u8, // maker events: fee tier stamped on the resting order, which the maker is charged at
 }
 
 impl MatchedOrder {
-    pub const SIZE: usize = 80; // Borsh padded size
+    pub const SIZE: usize = 1 + 16 + 32 + 8 + 8 + 1 + 8 + 1 + 8 + 1 + 1 + 1; // = 86
+
+    /// Quote value of the fill, `fill_price * fill_qty`.
+    pub fn notional(&self) -> Result<u128> {
+        Ok((self.fill_price as u128)
+            .checked_mul(self.fill_qty as u128)
+            .ok_or(crate::PerpError::MathOverflow)?)
+    }
 }
@@ -22,6 +22,9 @@ pub struct Position {
     pub entry_price : u64,
     pub realized_pnl: i64,     // realized PnL from partial closes / funding
     pub last_cum_funding: i64,
+    pub is_isolated: bool,     // margined only by `isolated_margin`, outside the cross-margin account
+    pub isolated_margin: i128, // collateral allocated to this position; absorbs its PnL, fees and funding
+    pub reduce_only_pending: u64, // reduce-only qty matched whose fill events are not applied yet
 
     pub initial_margin: u64,   // margin locked when opening
     pub leverage: u8,          // leverage used
@@ -32,10 +35,72 @@ pub struct Position {
     pub updated_at: i64,       // last update timestamp
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace,PartialEq)]
+impl Position {
+    /// Set up identity and position state the first time a market is traded; a live position is left alone.
+    pub fn open_if_new(&mut self, owner: Pubkey, market: Pubkey, now: i64) {
+        if self.owner != Pubkey::default() {
+            return;
+        }
+        self.owner = owner;
+        self.market = market;
+        self.base_position = 0;
+        self.entry_price = 0;
+        self.realized_pnl = 0;
+        self.last_cum_funding = 0;
+        self.reduce_only_pending = 0;
+        self.created_at = now;
+    }
+
+    /// Base position the cross-margin index sees; isolated positions are margined apart.
+    pub fn cross_base_position(&self) -> i64 {
+        if self.is_isolated { 0 } else { self.base_position }
+    }
+
+    /// Largest quantity an order on `side` can trade without growing or flipping the position,
+    /// once the reduce-only quantity already matched has landed.
+    pub fn reducible_qty(&self, side: Side) -> u64 {
+        let open = match side {
+            Side::Sell if self.base_position > 0 => self.base_position.unsigned_abs(),
+            Side::Buy if self.base_position < 0 => self.base_position.unsigned_abs(),
+            _ => 0,
+        };
+        open.saturating_sub(self.reduce_only_pending)
+    }
+
+    /// Count `qty` of reduce-only orders matched as pending until the position crank applies their fills.
+    pub fn add_reduce_only_pending(&mut self, qty: u64) {
+        self.reduce_only_pending = self.reduce_only_pending.saturating_add(qty);
+    }
+
+    /// Stop counting `qty` of a reduce-only order whose fill event was applied.
+    pub fn settle_reduce_only(&mut self, qty: u64) {
+        self.reduce_only_pending = self.reduce_only_pending.saturating_sub(qty);
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace,PartialEq, Debug)]
 pub enum OrderType {
     Market,
-    Limit
+    Limit,
+    ImmediateOrCancel, // takes liquidity up to the limit price, never rests
+    FillOrKill,        // fills the whole qty up to the limit price or does nothing
+    PostOnly,          // rests only; rejected if it would cross
+    PostOnlySlide,     // rests only; repriced one tick behind the opposite best if it would cross
+}
+
+impl OrderType {
+    pub fn has_limit_price(&self) -> bool {
+        !matches!(self, OrderType::Market)
+    }
+
+    pub fn is_post_only(&self) -> bool {
+        matches!(self, OrderType::PostOnly | OrderType::PostOnlySlide)
+    }
+
+    /// Whether an unfilled remainder of this type rests on the book.
+    pub fn rests(&self) -> bool {
+        matches!(self, OrderType::Limit | OrderType::PostOnly | OrderType::PostOnlySlide)
+    }
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy,Debug, PartialEq, Eq)]
@@ -1,6 +1,6 @@
 use anchor_lang::prelude::*;
 use crate::slot::{REQUEST_SLOT_LEN, RequestSlot};
-use crate::{CancelOrder, MAX_REQUESTS, Order };
+use crate::{CancelByClientId, CancelOrder, MAX_REQUESTS, Order };
 use crate::PerpError;
 
 #[account(zero_copy)]
@@ -22,10 +22,11 @@ impl RequestQueue {
 pub enum RequestType {
     Place(Order),
     Cancel(CancelOrder),
+    CancelByClientId(CancelByClientId),
 }
 
 impl RequestType {
-    pub const SIZE: usize = 1 + 107; // 108 total
+    pub const SIZE: usize = 1 + 126; // 127 total
 }
 
 
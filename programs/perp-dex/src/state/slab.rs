@@ -1,7 +1,7 @@
 use anchor_lang::prelude::msg;
 use bytemuck ::{Pod,Zeroable};
 use crate::{FREE_NODE, INNER_NODE, INVALID_INDEX, LAST_FREE_NODE, LEAF_NODE, NODE_SIZE, SLAB_HEADER_LEN};
-use crate::PerpError;
+use crate::{PerpError, Side};
 #[derive(Copy,Clone,Pod,Zeroable)]
 #[repr(C)]
 pub struct  SlabHeader {
@@ -11,6 +11,12 @@ pub struct  SlabHeader {
     pub root : u64
 }
 
+impl Default for SlabHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SlabHeader{
     pub fn new ()->Self{
         Self { 
@@ -86,17 +92,21 @@ impl InnerNode {
 
 
 
-#[derive(Clone, Copy, Zeroable, Pod, Debug)]
+#[derive(Clone, Copy, Zeroable, Pod, Debug, PartialEq, Eq)]
 #[repr(C, align(16))]
 pub struct LeafNode {
     pub tag: u32,
     pub fee_tier: u8,
-    pub reserved: [u8; 11],
+    pub client_order_id: [u8; 8],  // little-endian u64; a byte array keeps the 16-byte header packed
+    pub reduce_only: u8,           // 1 = capped at the owner's position whenever it trades
+    pub reserved: [u8; 2],
 
     pub key: u128,
     pub owner: [u8; 32],
     pub quantity: u64,
-    pub timestamp: i64,
+    pub timestamp: i64,   // when the leaf was inserted
+    pub expires_at: i64,  // 0 = good till cancelled
+    pub _padding: [u8; 8],
 }
 
 
@@ -111,27 +121,78 @@ impl LeafNode {
         Self {
             tag: LEAF_NODE,
             fee_tier,
-            reserved: [0u8; 11],
+            client_order_id: [0u8; 8],
+            reduce_only: 0,
+            reserved: [0u8; 2],
             key,
             owner,
             quantity,
             timestamp,
+            expires_at: 0,
+            _padding: [0u8; 8],
         }
     }
 
+    pub fn with_client_order_id(mut self, client_order_id: u64) -> Self {
+        self.client_order_id = client_order_id.to_le_bytes();
+        self
+    }
+
+    #[inline]
+    pub fn client_order_id(&self) -> u64 {
+        u64::from_le_bytes(self.client_order_id)
+    }
+
+    pub fn with_reduce_only(mut self, reduce_only: bool) -> Self {
+        self.reduce_only = reduce_only as u8;
+        self
+    }
+
+    #[inline]
+    pub fn is_reduce_only(&self) -> bool {
+        self.reduce_only != 0
+    }
+
+    pub fn with_expires_at(mut self, expires_at: i64) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    #[inline]
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at != 0 && now >= self.expires_at
+    }
+
+    /// The upper 64 bits of the key are always the limit price, on both sides of the book.
     #[inline]
     pub fn price(&self) -> u64 {
         (self.key >> 64) as u64
     }
 
     #[inline]
-    pub fn sequence_number(&self) -> u64 {
-        self.key as u64
+    pub fn sequence_number(&self, side: Side) -> u64 {
+        match side {
+            Side::Buy => !(self.key as u64),
+            Side::Sell => self.key as u64,
+        }
+    }
+
+    /// Price-time key. Asks store the sequence in the low bits and bids store its
+    /// complement, so `find_min` on asks and `find_max` on bids both land on the
+    /// best price and, within it, the oldest order.
+    #[inline]
+    pub fn price_key(side: Side, price: u64, sequence: u64) -> u128 {
+        let time_key = match side {
+            Side::Buy => !sequence,
+            Side::Sell => sequence,
+        };
+        ((price as u128) << 64) | (time_key as u128)
     }
 
+    /// The same key (and so the same time priority) moved to another price.
     #[inline]
-    pub fn price_key(price: u64, sequence: u64) -> u128 {
-        ((price as u128) << 64) | (sequence as u128)
+    pub fn reprice_key(key: u128, price: u64) -> u128 {
+        ((price as u128) << 64) | (key as u64 as u128)
     }
 }
 
@@ -167,6 +228,8 @@ pub union NodeUnion {
 unsafe impl Pod for NodeUnion {}
 unsafe impl Zeroable for NodeUnion {}
 
+const _: () = assert!(core::mem::size_of::<AnyNode>() == NODE_SIZE);
+
 #[derive(Copy,Clone,Pod,Zeroable)]
 #[repr(C)]
 pub struct  AnyNode{
@@ -290,6 +353,8 @@ impl Slab {
         } else {
             INVALID_INDEX as u64
         };
+        // every initialised node is already on the free list
+        slab.header.bump_index = capacity as u64;
 
         msg!(
             "SLAB INIT: DONE => capacity={} free_list_head={}",
@@ -397,105 +462,96 @@ impl Slab {
             return Err(PerpError::SlabFull);
         }
 
-        let new_leaf_index = self.allocate_node()?;
-        msg!("INSERT: New leaf node index={}", new_leaf_index);
-
-        self.nodes[new_leaf_index as usize].node.leaf = *leaf;
-
         if self.header.root == INVALID_INDEX as u64 {
+            let new_leaf_index = self.allocate_node()?;
+            self.nodes[new_leaf_index as usize].node.leaf = *leaf;
             msg!("INSERT: Tree was empty. Setting root={}", new_leaf_index);
             self.header.root = new_leaf_index as u64;
-        } else {
-            let mut parent_index = INVALID_INDEX;
-            let mut current_index = self.header.root as u32;
-
-            loop {
-                let current_node = &self.nodes[current_index as usize];
-                msg!(
-                    "INSERT: Traversing node_index={} tag={}",
-                    current_index,
-                    current_node.tag()
-                );
-
-                match current_node.tag() {
-                    LEAF_NODE => {
-                        msg!(
-                            "INSERT: Found LEAF during traversal at index={}",
-                            current_index
-                        );
-
-                        let existing_leaf = current_node.as_leaf();
-                        let existing_key = existing_leaf.key;
-                        let new_key = leaf.key;
-                        let xor = existing_key ^ new_key;
-                        let prefix_len = xor.leading_zeros();
-
-                        let inner_index = self.allocate_node()?;
-                        msg!(
-                            "INSERT: Splitting => inner_index={}, prefix_len={}, xor={}",
-                            inner_index,
-                            prefix_len,
-                            xor
-                        );
-
-                        // 1) Initialize inner in the slab
-                        self.nodes[inner_index as usize].node.inner =
-                            InnerNode::new(prefix_len as u64, existing_key);
-
-                        // 2) Mutate the stored inner node, not a local copy
-                        let inner = self.nodes[inner_index as usize].as_inner_mut();
-
-                        let bit_index = 127 - prefix_len;
-                        let bit_mask = 1u128 << bit_index;
-                        let direction_new = ((new_key & bit_mask) != 0) as usize;
-
-                        if direction_new == 0 {
-                            inner.set_left(new_leaf_index);
-                            inner.set_right(current_index);
-                            msg!("INSERT: Leaf left, existing right");
-                        } else {
-                            inner.set_left(current_index);
-                            inner.set_right(new_leaf_index);
-                            msg!("INSERT: Existing left, leaf right");
-                        }
-
-                        if parent_index == INVALID_INDEX {
-                            msg!("INSERT: Updating root to inner_index={}", inner_index);
-                            self.header.root = inner_index as u64;
-                        } else {
-                            let parent = self.nodes[parent_index as usize].as_inner_mut();
-                            if parent.left() == current_index {
-                                parent.set_left(inner_index);
-                                msg!("INSERT: Updated parent.left");
-                            } else {
-                                parent.set_right(inner_index);
-                                msg!("INSERT: Updated parent.right");
-                            }
-                        }
-                        break;
-                    }
+            self.header.leaf_count += 1;
+            return Ok(new_leaf_index);
+        }
 
-                   
-                    INNER_NODE => {
-                        parent_index = current_index;
-                        let inner = current_node.as_inner();
-                        current_index = inner.walk_down(leaf.key);
-                        msg!(
-                            "INSERT: Inner traversal => new current_index={} parent={}",
-                            current_index,
-                            parent_index
-                        );
-
-                        if current_index == INVALID_INDEX {
-                            msg!("INSERT ERROR: InvalidTree while walking down");
-                            return Err(PerpError::InvalidTree);
-                        }
-                    }
-                    _ => {
-                        msg!("INSERT ERROR: InvalidTree: Unexpected node type");
+        // 1) Find the leaf sharing the longest prefix with the new key; the first
+        //    bit where they differ is the crit bit of the new inner node.
+        let mut current_index = self.header.root as u32;
+        let closest_key = loop {
+            let current_node = &self.nodes[current_index as usize];
+            match current_node.tag() {
+                LEAF_NODE => break current_node.as_leaf().key,
+                INNER_NODE => {
+                    current_index = current_node.as_inner().walk_down(leaf.key);
+                    if current_index == INVALID_INDEX {
+                        msg!("INSERT ERROR: InvalidTree while walking down");
                         return Err(PerpError::InvalidTree);
                     }
                 }
+                _ => {
+                    msg!("INSERT ERROR: InvalidTree: Unexpected node type");
+                    return Err(PerpError::InvalidTree);
+                }
+            }
+        };
+
+        let xor = closest_key ^ leaf.key;
+        if xor == 0 {
+            msg!("INSERT ERROR: DuplicateOrderId key={}", leaf.key);
+            return Err(PerpError::DuplicateOrderId);
+        }
+        let prefix_len = xor.leading_zeros() as u64;
+
+        // 2) Walk down again and stop above the first node whose crit bit is
+        //    below the new one, so the tree stays ordered by key.
+        let mut parent_index = INVALID_INDEX;
+        let mut current_index = self.header.root as u32;
+        loop {
+            let current_node = &self.nodes[current_index as usize];
+            if current_node.tag() != INNER_NODE {
+                break;
+            }
+            let inner = current_node.as_inner();
+            if inner.prefix_len > prefix_len {
+                break;
+            }
+            parent_index = current_index;
+            current_index = inner.walk_down(leaf.key);
+        }
+
+        let new_leaf_index = self.allocate_node()?;
+        msg!("INSERT: New leaf node index={}", new_leaf_index);
+        self.nodes[new_leaf_index as usize].node.leaf = *leaf;
+
+        let inner_index = self.allocate_node()?;
+        msg!(
+            "INSERT: Splitting => inner_index={}, prefix_len={}, xor={}",
+            inner_index,
+            prefix_len,
+            xor
+        );
+        self.nodes[inner_index as usize].node.inner = InnerNode::new(prefix_len, leaf.key);
+
+        let inner = self.nodes[inner_index as usize].as_inner_mut();
+        let bit_mask = 1u128 << (127 - prefix_len);
+        if leaf.key & bit_mask == 0 {
+            inner.set_left(new_leaf_index);
+            inner.set_right(current_index);
+            msg!("INSERT: Leaf left, existing right");
+        } else {
+            inner.set_left(current_index);
+            inner.set_right(new_leaf_index);
+            msg!("INSERT: Existing left, leaf right");
+        }
+
+        if parent_index == INVALID_INDEX {
+            msg!("INSERT: Updating root to inner_index={}", inner_index);
+            self.header.root = inner_index as u64;
+        } else {
+            let parent = self.nodes[parent_index as usize].as_inner_mut();
+            if parent.left() == current_index {
+                parent.set_left(inner_index);
+                msg!("INSERT: Updated parent.left");
+            } else {
+                parent.set_right(inner_index);
+                msg!("INSERT: Updated parent.right");
             }
         }
 
@@ -564,6 +620,28 @@ impl Slab {
         Ok(removed_leaf)
     }
 
+    /// Indices of every leaf in ascending key order.
+    pub fn leaves_in_order(&self) -> Vec<u32> {
+        let mut out = Vec::with_capacity(self.header.leaf_count as usize);
+        if self.header.root == INVALID_INDEX as u64 {
+            return out;
+        }
+        let mut stack = vec![self.header.root as u32];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            match node.tag() {
+                LEAF_NODE => out.push(index),
+                INNER_NODE => {
+                    let inner = node.as_inner();
+                    stack.push(inner.right());
+                    stack.push(inner.left());
+                }
+                _ => {}
+            }
+        }
+        out
+    }
+
     pub fn find_max(&self) -> Option<u32> {
         if self.header.root == INVALID_INDEX as u64 {
             return None;
@@ -606,6 +684,14 @@ impl Slab {
         }
     }
 
+    /// Linear scan for `owner`'s leaf tagged with `client_order_id`.
+    pub fn find_by_client_order_id(&self, owner: &[u8; 32], client_order_id: u64) -> Option<u32> {
+        self.leaves_in_order().into_iter().find(|&i| {
+            let leaf = self.nodes[i as usize].as_leaf();
+            leaf.owner == *owner && leaf.client_order_id() == client_order_id
+        })
+    }
+
     pub fn find_by_key(&self, key: u128) -> Option<u32> {
         if self.header.root == INVALID_INDEX as u64 {
             return None;
@@ -645,8 +731,10 @@ impl Slab {
             return Err(PerpError::NodeIsRoot);
         }
         let mut current = self.header.root as u32;
+        // an inner node's key is one of its subtree's keys, so it routes to the node itself
         let child_key = match self.nodes[child_index as usize].tag() {
             LEAF_NODE => self.nodes[child_index as usize].as_leaf().key,
+            INNER_NODE => self.nodes[child_index as usize].as_inner().key,
             _ => return Err(PerpError::InvalidNodeType),
         };
         loop {
@@ -670,3 +758,105 @@ impl Slab {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAPACITY: usize = 64;
+
+    fn slab_buffer(capacity: usize) -> Vec<u128> {
+        vec![0u128; Slab::compute_allocation_size(capacity) / 16]
+    }
+
+    fn leaf(side: Side, price: u64, seq: u64) -> LeafNode {
+        LeafNode::new(LeafNode::price_key(side, price, seq), [7u8; 32], 1, 0, 0)
+    }
+
+    // xorshift64, good enough to shuffle keys deterministically
+    fn next(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn test_price_key_round_trips_both_sides() {
+        for side in [Side::Buy, Side::Sell] {
+            let l = leaf(side, 101_250, 42);
+            assert_eq!(l.price(), 101_250);
+            assert_eq!(l.sequence_number(side), 42);
+        }
+    }
+
+    #[test]
+    fn test_bid_key_prefers_higher_price_then_older_order() {
+        let older = LeafNode::price_key(Side::Buy, 100, 1);
+        let newer = LeafNode::price_key(Side::Buy, 100, 2);
+        let better = LeafNode::price_key(Side::Buy, 101, 3);
+        assert!(older > newer);
+        assert!(better > older);
+    }
+
+    #[test]
+    fn test_ask_key_prefers_lower_price_then_older_order() {
+        let older = LeafNode::price_key(Side::Sell, 100, 1);
+        let newer = LeafNode::price_key(Side::Sell, 100, 2);
+        let better = LeafNode::price_key(Side::Sell, 99, 3);
+        assert!(older < newer);
+        assert!(better < older);
+    }
+
+    #[test]
+    fn test_duplicate_key_is_rejected() {
+        let mut buf = slab_buffer(CAPACITY);
+        let slab = Slab::initialize(bytemuck::cast_slice_mut(&mut buf), CAPACITY).unwrap();
+        slab.insert_leaf(&leaf(Side::Sell, 100, 1)).unwrap();
+        assert!(matches!(
+            slab.insert_leaf(&leaf(Side::Sell, 100, 1)),
+            Err(PerpError::DuplicateOrderId)
+        ));
+        assert_eq!(slab.header.leaf_count, 1);
+    }
+
+    #[test]
+    fn test_random_inserts_and_removes_keep_tree_ordered() {
+        let mut rng = 0x9E37_79B9_7F4A_7C15u64;
+        for round in 0..50 {
+            let mut buf = slab_buffer(CAPACITY);
+            let slab = Slab::initialize(bytemuck::cast_slice_mut(&mut buf), CAPACITY).unwrap();
+            let side = if round % 2 == 0 { Side::Buy } else { Side::Sell };
+            let mut keys: Vec<u128> = Vec::new();
+
+            for seq in 0..200u64 {
+                let remove = !keys.is_empty() && (next(&mut rng) % 3 == 0 || keys.len() >= CAPACITY / 2);
+                if remove {
+                    let key = keys.swap_remove((next(&mut rng) as usize) % keys.len());
+                    let idx = slab.find_by_key(key).expect("key must be present");
+                    assert_eq!(slab.remove_leaf(idx).unwrap().key, key);
+                } else {
+                    let price = 90 + next(&mut rng) % 20;
+                    let l = leaf(side, price, seq);
+                    slab.insert_leaf(&l).unwrap();
+                    keys.push(l.key);
+                }
+
+                let mut sorted = keys.clone();
+                sorted.sort_unstable();
+                let in_order: Vec<u128> = slab
+                    .leaves_in_order()
+                    .iter()
+                    .map(|&i| slab.nodes[i as usize].as_leaf().key)
+                    .collect();
+                assert_eq!(in_order, sorted);
+                assert_eq!(slab.header.leaf_count as usize, keys.len());
+
+                let min = slab.find_min().map(|i| slab.nodes[i as usize].as_leaf().key);
+                let max = slab.find_max().map(|i| slab.nodes[i as usize].as_leaf().key);
+                assert_eq!(min, sorted.first().copied());
+                assert_eq!(max, sorted.last().copied());
+            }
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use anchor_lang::prelude::*;
+
+use crate::{OrderType, PerpError, Side, MAX_TRIGGERS_PER_USER, MAX_TRIGGER_ORDERS};
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum TriggerKind {
+    StopMarket,   // market order once the mark moves against the order's side past the trigger
+    StopLimit,    // same trigger, places a limit order at `limit_price`
+    TakeProfit,   // market order once the mark moves in favour of the order's side past the trigger
+}
+
+impl TriggerKind {
+    /// Stops fire when the mark reaches the trigger in the direction the order trades
+    /// (a sell stop below, a buy stop above); take-profits fire on the other side.
+    pub fn is_triggered(&self, side: Side, trigger_price: u64, mark_price: u64) -> bool {
+        let stop = match side {
+            Side::Sell => mark_price <= trigger_price,
+            Side::Buy => mark_price >= trigger_price,
+        };
+        let take_profit = match side {
+            Side::Sell => mark_price >= trigger_price,
+            Side::Buy => mark_price <= trigger_price,
+        };
+        match self {
+            TriggerKind::StopMarket | TriggerKind::StopLimit => stop,
+            TriggerKind::TakeProfit => take_profit,
+        }
+    }
+
+    pub fn order_type(&self) -> OrderType {
+        match self {
+            TriggerKind::StopLimit => OrderType::Limit,
+            TriggerKind::StopMarket | TriggerKind::TakeProfit => OrderType::Market,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, InitSpace)]
+pub struct TriggerOrder {
+    pub owner: Pubkey,
+    pub order_id: u128,        // allocated from the request queue when the trigger is placed
+    pub side: Side,
+    pub kind: TriggerKind,
+    pub qty: u64,
+    pub trigger_price: u64,
+    pub limit_price: u64,      // StopLimit only
+    pub reduce_only: bool,
+    pub expires_at: i64,       // 0 = good till cancelled
+    pub created_at: i64,
+    pub client_order_id: u64,  // carried onto the order placed when the trigger fires
+}
+
+impl TriggerOrder {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at != 0 && now >= self.expires_at
+    }
+}
+
+/// Pending conditional orders for one market, seeded by symbol.
+#[account]
+#[derive(InitSpace)]
+pub struct TriggerBook {
+    pub market: Pubkey,
+    #[max_len(MAX_TRIGGER_ORDERS)]
+    pub orders: Vec<TriggerOrder>,
+    pub bump: u8,
+}
+
+impl TriggerBook {
+    pub fn insert(&mut self, order: TriggerOrder) -> Result<()> {
+        require!(self.orders.len() < MAX_TRIGGER_ORDERS, PerpError::TriggerBookFull);
+        let per_user = self.orders.iter().filter(|o| o.owner == order.owner).count();
+        require!(per_user < MAX_TRIGGERS_PER_USER, PerpError::TriggerBookFull);
+        self.orders.push(order);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, owner: Pubkey, order_id: u128) -> Result<TriggerOrder> {
+        let pos = self
+            .orders
+            .iter()
+            .position(|o| o.owner == owner && o.order_id == order_id)
+            .ok_or(PerpError::OrderNotFound)?;
+        Ok(self.orders.remove(pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_and_take_profit_directions() {
+        // long protection: sell stop below, sell take-profit above
+        assert!(TriggerKind::StopMarket.is_triggered(Side::Sell, 90, 89));
+        assert!(!TriggerKind::StopMarket.is_triggered(Side::Sell, 90, 91));
+        assert!(TriggerKind::TakeProfit.is_triggered(Side::Sell, 110, 110));
+        assert!(!TriggerKind::TakeProfit.is_triggered(Side::Sell, 110, 109));
+
+        // short protection: buy stop above, buy take-profit below
+        assert!(TriggerKind::StopLimit.is_triggered(Side::Buy, 110, 111));
+        assert!(!TriggerKind::StopLimit.is_triggered(Side::Buy, 110, 109));
+        assert!(TriggerKind::TakeProfit.is_triggered(Side::Buy, 90, 85));
+        assert!(!TriggerKind::TakeProfit.is_triggered(Side::Buy, 90, 95));
+    }
+
+    #[test]
+    fn test_per_user_limit() {
+        let owner = Pubkey::new_unique();
+        let mut book = TriggerBook { market: Pubkey::default(), orders: Vec::new(), bump: 0 };
+        let trigger = |order_id| TriggerOrder {
+            owner,
+            order_id,
+            side: Side::Sell,
+            kind: TriggerKind::StopMarket,
+            qty: 1,
+            trigger_price: 90,
+            limit_price: 0,
+            reduce_only: true,
+            expires_at: 0,
+            created_at: 0,
+            client_order_id: 0,
+        };
+        for id in 0..MAX_TRIGGERS_PER_USER as u128 {
+            book.insert(trigger(id)).unwrap();
+        }
+        assert!(book.insert(trigger(99)).is_err());
+        book.remove(owner, 3).unwrap();
+        book.insert(trigger(99)).unwrap();
+    }
+}
@@ -1,9 +1,219 @@
 use anchor_lang::prelude:: *;
 
+use crate::{MarketState, PerpError, Position, PositionRisk, MAX_OPEN_ORDERS, MAX_OPEN_POSITIONS};
+
 #[account]
 #[derive(InitSpace)]
 pub struct UserCollateral {
     pub owner: Pubkey,
     pub collateral_amount: i128,     /// stored in quote token smallest units (u64 token amounts converted to i128 for signed math)
     pub last_updated: i64,
-}
\ No newline at end of file
+    pub reserved_margin: u128,       // initial margin locked by open orders, sum of `open_orders[..].reserved`
+    #[max_len(MAX_OPEN_ORDERS)]
+    pub open_orders: Vec<OrderReservation>,
+    #[max_len(MAX_OPEN_POSITIONS)]
+    pub open_positions: Vec<Pubkey>,  // markets with a non-zero position, all margined by this collateral
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct OrderReservation {
+    pub market: Pubkey,
+    pub order_id: u128,
+    pub remaining_qty: u64,  // quantity still able to fill
+    pub reserved: u64,       // margin still locked for that quantity
+}
+
+impl UserCollateral {
+    /// Collateral not locked by open orders.
+    pub fn free_collateral(&self) -> Result<i128> {
+        let reserved = i128::try_from(self.reserved_margin).map_err(|_| PerpError::MathOverflow)?;
+        self.collateral_amount
+            .checked_sub(reserved)
+            .ok_or_else(|| error!(PerpError::MathOverflow))
+    }
+
+    /// Lock `amount` of margin for a newly queued order.
+    pub fn reserve_margin(&mut self, market: Pubkey, order_id: u128, qty: u64, amount: u128) -> Result<()> {
+        require!(self.open_orders.len() < MAX_OPEN_ORDERS, PerpError::TooManyOpenOrders);
+        require!(self.free_collateral()? >= amount as i128, PerpError::InsufficientCollateral);
+
+        let reserved = u64::try_from(amount).map_err(|_| PerpError::MathOverflow)?;
+        self.reserved_margin = self.reserved_margin
+            .checked_add(amount)
+            .ok_or(PerpError::MathOverflow)?;
+        self.open_orders.push(OrderReservation {
+            market,
+            order_id,
+            remaining_qty: qty,
+            reserved,
+        });
+        Ok(())
+    }
+
+    /// Keep `open_positions` in step with a position in `market` now holding `base_position`.
+    pub fn track_position(&mut self, market: Pubkey, base_position: i64) -> Result<()> {
+        let index = self.open_positions.iter().position(|m| *m == market);
+        match (index, base_position != 0) {
+            (None, true) => {
+                require!(self.open_positions.len() < MAX_OPEN_POSITIONS, PerpError::TooManyOpenPositions);
+                self.open_positions.push(market);
+            }
+            (Some(i), false) => {
+                self.open_positions.remove(i);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Risk of every open position outside `skip_market`, read from `accounts`: a
+    /// market, its oracle and the position for each, in `open_positions` order. Each
+    /// market is priced off a fresh read of its oracle, never its cached price.
+    pub fn cross_position_risks(&self, skip_market: Pubkey, accounts: &[AccountInfo], now: i64) -> Result<Vec<PositionRisk>> {
+        let markets: Vec<Pubkey> = self.open_positions.iter().copied().filter(|m| *m != skip_market).collect();
+        require!(accounts.len() == 3 * markets.len(), PerpError::MarginAccountsMismatch);
+
+        markets
+            .iter()
+            .zip(accounts.chunks(3))
+            .map(|(market_key, accounts)| {
+                require_keys_eq!(accounts[0].key(), *market_key, PerpError::MarginAccountsMismatch);
+                let mut market: MarketState = load_program_account(&accounts[0])?;
+                // only this copy is refreshed; the market account is left as it was
+                market.refresh_oracle(&accounts[1], now)?;
+                let position: Position = load_program_account(&accounts[2])?;
+                require!(
+                    position.owner == self.owner && position.market == *market_key,
+                    PerpError::MarginAccountsMismatch
+                );
+                PositionRisk::new(&market, &position)
+            })
+            .collect()
+    }
+
+    /// Unlock the share of an order's reservation backing `qty`, because it filled or left the book.
+    /// Orders without a reservation (e.g. liquidation takers) release nothing.
+    /// Matches on the sequence bits of the id, which survive a post-only reprice.
+    pub fn release_margin(&mut self, market: Pubkey, order_id: u128, qty: u64) -> Result<u128> {
+        let Some(pos) = self
+            .open_orders
+            .iter()
+            .position(|o| o.market == market && o.order_id as u64 == order_id as u64)
+        else {
+            return Ok(0);
+        };
+
+        let entry = &mut self.open_orders[pos];
+        let released = if qty >= entry.remaining_qty {
+            entry.reserved
+        } else {
+            let share = (entry.reserved as u128)
+                .checked_mul(qty as u128)
+                .and_then(|v| v.checked_div(entry.remaining_qty as u128))
+                .ok_or(PerpError::MathOverflow)?;
+            u64::try_from(share).map_err(|_| PerpError::MathOverflow)?
+        };
+
+        entry.reserved -= released;
+        entry.remaining_qty = entry.remaining_qty.saturating_sub(qty);
+        if entry.remaining_qty == 0 {
+            self.open_orders.swap_remove(pos);
+        }
+
+        self.reserved_margin = self.reserved_margin
+            .checked_sub(released as u128)
+            .ok_or(PerpError::MathOverflow)?;
+        Ok(released as u128)
+    }
+}
+
+fn load_program_account<T: AccountDeserialize + Owner>(info: &AccountInfo) -> Result<T> {
+    require_keys_eq!(*info.owner, T::owner(), PerpError::MarginAccountsMismatch);
+    T::try_deserialize(&mut &info.try_borrow_data()?[..])
+}
+
+#[cfg(test)]
+impl UserCollateral {
+    /// Empty margin account the unit tests start from; each test overrides the fields it exercises.
+    pub fn test_default() -> Self {
+        UserCollateral {
+            owner: Pubkey::default(),
+            collateral_amount: 0,
+            last_updated: 0,
+            reserved_margin: 0,
+            open_orders: Vec::new(),
+            open_positions: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_collateral(amount: i128) -> UserCollateral {
+        UserCollateral { owner: Pubkey::new_unique(), collateral_amount: amount, ..UserCollateral::test_default() }
+    }
+
+    #[test]
+    fn test_reserve_reduces_free_collateral() {
+        let market = Pubkey::new_unique();
+        let mut c = make_collateral(1_000);
+
+        c.reserve_margin(market, 1, 10, 400).unwrap();
+        assert_eq!(c.free_collateral().unwrap(), 600);
+        assert!(c.reserve_margin(market, 2, 10, 601).is_err());
+        c.reserve_margin(market, 2, 10, 600).unwrap();
+        assert_eq!(c.free_collateral().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_release_is_proportional_to_filled_qty() {
+        let market = Pubkey::new_unique();
+        let mut c = make_collateral(1_000);
+        c.reserve_margin(market, 7, 10, 500).unwrap();
+
+        assert_eq!(c.release_margin(market, 7, 4).unwrap(), 200);
+        assert_eq!(c.reserved_margin, 300);
+        assert_eq!(c.open_orders[0].remaining_qty, 6);
+
+        // the last release returns whatever rounding left behind and drops the entry
+        assert_eq!(c.release_margin(market, 7, 6).unwrap(), 300);
+        assert_eq!(c.reserved_margin, 0);
+        assert!(c.open_orders.is_empty());
+    }
+
+    #[test]
+    fn test_release_unknown_order_is_noop() {
+        let market = Pubkey::new_unique();
+        let mut c = make_collateral(1_000);
+        c.reserve_margin(market, 7, 10, 500).unwrap();
+
+        assert_eq!(c.release_margin(Pubkey::new_unique(), 7, 10).unwrap(), 0);
+        assert_eq!(c.release_margin(market, 8, 10).unwrap(), 0);
+        assert_eq!(c.reserved_margin, 500);
+    }
+
+    #[test]
+    fn test_track_position_indexes_open_markets() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut c = make_collateral(1_000);
+
+        c.track_position(a, 5).unwrap();
+        c.track_position(b, -3).unwrap();
+        c.track_position(a, 8).unwrap();
+        assert_eq!(c.open_positions, vec![a, b]);
+
+        c.track_position(a, 0).unwrap();
+        c.track_position(Pubkey::new_unique(), 0).unwrap();
+        assert_eq!(c.open_positions, vec![b]);
+
+        for _ in 1..MAX_OPEN_POSITIONS {
+            c.track_position(Pubkey::new_unique(), 1).unwrap();
+        }
+        assert_eq!(
+            c.track_position(Pubkey::new_unique(), 1).unwrap_err(),
+            error!(PerpError::TooManyOpenPositions)
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use anchor_lang::prelude::*;
+
+use crate::{PerpError, SECONDS_PER_DAY, VOLUME_WINDOW_DAYS};
+
+/// Per-user trading volume across all markets, in daily buckets over the trailing
+/// `VOLUME_WINDOW_DAYS`. Bucket `day % VOLUME_WINDOW_DAYS` holds that day's volume.
+#[account]
+#[derive(InitSpace)]
+pub struct UserStats {
+    pub owner: Pubkey,
+    pub maker_volume: [u64; VOLUME_WINDOW_DAYS],
+    pub taker_volume: [u64; VOLUME_WINDOW_DAYS],
+    pub last_day: i64,  // day of the most recent fill; older buckets are cleared as days pass
+    pub referrer: Pubkey,  // bound once; default = not referred
+    pub unclaimed_referral_rewards: u64,
+    pub claimed_referral_rewards: u64,
+    pub bump: u8,
+}
+
+impl UserStats {
+    fn day(now: i64) -> i64 {
+        now.div_euclid(SECONDS_PER_DAY)
+    }
+
+    fn bucket(day: i64) -> usize {
+        day.rem_euclid(VOLUME_WINDOW_DAYS as i64) as usize
+    }
+
+    /// Clear the buckets of the days between the last fill and `day`.
+    fn roll_to(&mut self, day: i64) {
+        if day <= self.last_day {
+            return;
+        }
+        let stale = (day - self.last_day).min(VOLUME_WINDOW_DAYS as i64);
+        for offset in 0..stale {
+            let bucket = Self::bucket(day - offset);
+            self.maker_volume[bucket] = 0;
+            self.taker_volume[bucket] = 0;
+        }
+        self.last_day = day;
+    }
+
+    pub fn record_fill(&mut self, is_maker: bool, notional: u64, now: i64) -> Result<()> {
+        let day = Self::day(now);
+        self.roll_to(day);
+        // a fill stamped before the last one still counts towards the latest day
+        let bucket = Self::bucket(day.max(self.last_day));
+        let volume = if is_maker { &mut self.maker_volume } else { &mut self.taker_volume };
+        volume[bucket] = volume[bucket].checked_add(notional).ok_or(PerpError::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn is_referred(&self) -> bool {
+        self.referrer != Pubkey::default()
+    }
+
+    pub fn bind_referrer(&mut self, referrer: Pubkey) -> Result<()> {
+        require!(!self.is_referred(), PerpError::ReferrerAlreadySet);
+        require!(referrer != self.owner && referrer != Pubkey::default(), PerpError::InvalidReferrer);
+        self.referrer = referrer;
+        Ok(())
+    }
+
+    pub fn credit_referral_reward(&mut self, reward: u64) -> Result<()> {
+        self.unclaimed_referral_rewards = self
+            .unclaimed_referral_rewards
+            .checked_add(reward)
+            .ok_or(PerpError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Move every unclaimed reward to claimed and return the amount to pay out.
+    pub fn take_referral_rewards(&mut self) -> Result<u64> {
+        let amount = self.unclaimed_referral_rewards;
+        require!(amount > 0, PerpError::NothingToClaim);
+        self.claimed_referral_rewards = self
+            .claimed_referral_rewards
+            .checked_add(amount)
+            .ok_or(PerpError::MathOverflow)?;
+        self.unclaimed_referral_rewards = 0;
+        Ok(amount)
+    }
+
+    /// Maker plus taker volume over the `VOLUME_WINDOW_DAYS` ending at `now`.
+    pub fn trailing_volume(&self, now: i64) -> u64 {
+        let today = Self::day(now);
+        (0..VOLUME_WINDOW_DAYS as i64)
+            .map(|offset| self.last_day - offset)
+            .filter(|day| *day > today - VOLUME_WINDOW_DAYS as i64)
+            .map(|day| {
+                let bucket = Self::bucket(day);
+                self.maker_volume[bucket].saturating_add(self.taker_volume[bucket])
+            })
+            .fold(0u64, u64::saturating_add)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> UserStats {
+        UserStats {
+            owner: Pubkey::default(),
+            maker_volume: [0; VOLUME_WINDOW_DAYS],
+            taker_volume: [0; VOLUME_WINDOW_DAYS],
+            last_day: 0,
+            referrer: Pubkey::default(),
+            unclaimed_referral_rewards: 0,
+            claimed_referral_rewards: 0,
+            bump: 0,
+        }
+    }
+
+    #[test]
+    fn test_trailing_volume_drops_days_past_the_window() {
+        let mut stats = stats();
+        stats.record_fill(true, 100, 0).unwrap();
+        stats.record_fill(false, 50, 10 * SECONDS_PER_DAY).unwrap();
+        assert_eq!(stats.trailing_volume(10 * SECONDS_PER_DAY), 150);
+
+        // day 0 leaves the window on day 30, day 10 on day 40
+        assert_eq!(stats.trailing_volume(29 * SECONDS_PER_DAY), 150);
+        assert_eq!(stats.trailing_volume(30 * SECONDS_PER_DAY), 50);
+        assert_eq!(stats.trailing_volume(40 * SECONDS_PER_DAY), 0);
+    }
+
+    #[test]
+    fn test_record_fill_clears_reused_buckets() {
+        let mut stats = stats();
+        stats.record_fill(false, 100, 0).unwrap();
+        // same bucket 30 days later: the old volume must not survive
+        stats.record_fill(false, 7, 30 * SECONDS_PER_DAY).unwrap();
+        assert_eq!(stats.trailing_volume(30 * SECONDS_PER_DAY), 7);
+
+        // a long gap clears everything
+        stats.record_fill(true, 3, 200 * SECONDS_PER_DAY).unwrap();
+        assert_eq!(stats.trailing_volume(200 * SECONDS_PER_DAY), 3);
+        assert_eq!(stats.maker_volume.iter().chain(stats.taker_volume.iter()).sum::<u64>(), 3);
+    }
+
+    #[test]
+    fn test_referrer_binds_once_and_rewards_claim_once() {
+        let mut stats = stats();
+        stats.owner = Pubkey::new_unique();
+        assert_eq!(stats.bind_referrer(stats.owner).unwrap_err(), error!(PerpError::InvalidReferrer));
+
+        let referrer = Pubkey::new_unique();
+        stats.bind_referrer(referrer).unwrap();
+        assert_eq!(stats.bind_referrer(Pubkey::new_unique()).unwrap_err(), error!(PerpError::ReferrerAlreadySet));
+        assert_eq!(stats.referrer, referrer);
+
+        stats.credit_referral_reward(3).unwrap();
+        stats.credit_referral_reward(4).unwrap();
+        assert_eq!(stats.take_referral_rewards().unwrap(), 7);
+        assert_eq!(stats.claimed_referral_rewards, 7);
+        assert_eq!(stats.take_referral_rewards().unwrap_err(), error!(PerpError::NothingToClaim));
+    }
+
+    /// Reference: keep every fill and sum those inside the window.
+    #[test]
+    fn test_matches_fill_log_reference() {
+        let mut stats = stats();
+        let mut log: Vec<(i64, u64)> = Vec::new();
+        let mut seed = 0x1234_5678_9abc_def1u64;
+        let mut now = 0i64;
+        for _ in 0..500 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            now += (seed % (3 * SECONDS_PER_DAY as u64)) as i64;
+            let notional = seed % 1_000;
+            stats.record_fill(seed % 2 == 0, notional, now).unwrap();
+            log.push((now, notional));
+
+            let today = now.div_euclid(SECONDS_PER_DAY);
+            let expected: u64 = log
+                .iter()
+                .filter(|(ts, _)| ts.div_euclid(SECONDS_PER_DAY) > today - VOLUME_WINDOW_DAYS as i64)
+                .map(|(_, n)| n)
+                .sum();
+            assert_eq!(stats.trailing_volume(now), expected);
+        }
+    }
+}
@@ -1,14 +1,15 @@
-use crate::{OrderType, Side};
+use crate::{LeafNode, OrderType, Side};
 
+/// Builds the slab key for an order. The price bits always carry the price the
+/// order is willing to trade at; market orders never rest and take the worst
+/// price on their side.
 pub fn make_order_id(order_type: OrderType, side: Side, price: u64, sequence: u64) -> u128 {
-    match order_type {
-        OrderType::Limit => {
-            let price_key = match side {
-                Side::Buy => u64::MAX - price, // reverse for bids
-                Side::Sell => price,
-            };
-            ((price_key as u128) << 64) | (sequence as u128)
-        }
-        OrderType::Market => sequence as u128, // use sequence only for market orders
-    }
+    let price = match order_type {
+        OrderType::Market => match side {
+            Side::Buy => u64::MAX,
+            Side::Sell => 0,
+        },
+        _ => price,
+    };
+    LeafNode::price_key(side, price, sequence)
 }
@@ -8,7 +8,7 @@
  */
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
-import { PublicKey, SystemProgram } from "@solana/web3.js";
+import { Keypair, PublicKey, SystemProgram } from "@solana/web3.js";
 import {
   TOKEN_PROGRAM_ID,
   ASSOCIATED_TOKEN_PROGRAM_ID,
@@ -29,6 +29,7 @@ if (!process.env.ANCHOR_PROVIDER_URL) {
 // --- Constants (match on-chain layout) ---
 const EVENT_QUEUE_COUNT_OFFSET = 8 + 4; // discriminator + head(2) + tail(2) then count at 12
 const REQUEST_QUEUE_COUNT_OFFSET = 12;
+const PRICE_ACCOUNT_LEN = 40; // PriceAccount: Pyth-style mock oracle layout
 
 describe("PerpDex", () => {
   let provider: anchor.AnchorProvider;
@@ -58,6 +59,7 @@ describe("PerpDex", () => {
   // User
   let userUsdcAta: PublicKey;
   let userCollateralPda: PublicKey;
+  let userStatsPda: PublicKey;
 
   // Market
   const MARKET_SYMBOL = "SOL-PERP";
@@ -65,6 +67,9 @@ describe("PerpDex", () => {
   let bidsPda: PublicKey;
   let asksPda: PublicKey;
   let positionPda: PublicKey;
+  // Mock oracle: a program-owned price account the market authority publishes to
+  const oracle = Keypair.generate();
+  let marketInitialized = false;
 
   /** Send tx and log on-chain logs on success or failure. */
   async function sendAndLog(ix: () => Promise<string>): Promise<string> {
@@ -93,13 +98,30 @@ describe("PerpDex", () => {
     }
   }
 
+  /** Publish `price` (6 decimals) to the mock oracle; this also refreshes the market's cached price. */
+  async function publishOraclePrice(price: anchor.BN) {
+    await program.methods
+      .setMarkPrice(price, new anchor.BN(0), -6)
+      .accounts({ authority: authority.publicKey, market: marketPda, oracle: oracle.publicKey } as any)
+      .rpc();
+  }
+
+  // the on-chain adapter rejects prices older than a minute, so keep the mock fresh
+  beforeEach(async () => {
+    if (!marketInitialized) return;
+    const market = await program.account.marketState.fetch(marketPda);
+    await publishOraclePrice(market.lastOraclePrice);
+  });
+
   /** Accounts for placeOrder (shared). */
   function placeOrderAccounts() {
     return {
       user: authority.publicKey,
       globalConfig: globalConfigPda,
       market: marketPda,
+      oracle: oracle.publicKey,
       userColletral: userCollateralPda,
+      userStats: userStatsPda,
       positionPerMarket: positionPda,
       requestQueue: requestQueuePda,
       systemProgram: SystemProgram.programId,
@@ -113,6 +135,7 @@ describe("PerpDex", () => {
     return {
       authority: authority.publicKey,
       market: marketPda,
+      oracle: oracle.publicKey,
       bids: bidsPda,
       asks: asksPda,
       requestQueue: requestQueuePda,
@@ -129,6 +152,9 @@ describe("PerpDex", () => {
       userPosition: positionPda,
       eventQueue: eventQueuePda,
       userColletral: userCollateralPda,
+      globalConfig: globalConfigPda,
+      userStats: userStatsPda,
+      referrerStats: null, // the test wallet has no referrer
       systemProgram: SystemProgram.programId,
       tokenProgram: TOKEN_PROGRAM_ID,
     };
@@ -153,6 +179,11 @@ describe("PerpDex", () => {
       initialMargin: new BN(opts.initialMargin ?? 10),
       leverage: opts.leverage ?? 10,
       market: marketPda,
+      reduceOnly: false,
+      selfTrade: { cancelTaker: {} },
+      clientOrderId: new BN(0),
+      expiresAt: new BN(0),
+      feeTier: 0,
     };
   }
 
@@ -171,7 +202,7 @@ describe("PerpDex", () => {
     await program.methods.resetSlab().accounts({ market: marketPda, bids: bidsPda, asks: asksPda } as any).rpc();
     await program.methods
       .resetQueues()
-      .accounts({ requestQueue: requestQueuePda, eventQueue: eventQueuePda } as any)
+      .accounts({ authority: authority.publicKey, requestQueue: requestQueuePda, eventQueue: eventQueuePda, market: marketPda } as any)
       .rpc();
   }
 
@@ -220,14 +251,6 @@ describe("PerpDex", () => {
       [Buffer.from("global_config")],
       program.programId
     );
-    [requestQueuePda] = PublicKey.findProgramAddressSync(
-      [Buffer.from("request_queue")],
-      program.programId
-    );
-    [eventQueuePda] = PublicKey.findProgramAddressSync(
-      [Buffer.from("event_queue")],
-      program.programId
-    );
     [vaultQuotePda] = PublicKey.findProgramAddressSync(
       [Buffer.from("vault_quote"), globalConfigPda.toBuffer()],
       program.programId
@@ -244,6 +267,10 @@ describe("PerpDex", () => {
       [Buffer.from("user_colletral"), authority.publicKey.toBuffer()],
       program.programId
     );
+    [userStatsPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user_stats"), authority.publicKey.toBuffer()],
+      program.programId
+    );
 
     const marketSymbolBytes = Buffer.from(MARKET_SYMBOL);
     [marketPda] = PublicKey.findProgramAddressSync(
@@ -258,6 +285,14 @@ describe("PerpDex", () => {
       [Buffer.from("asks"), marketSymbolBytes],
       program.programId
     );
+    [requestQueuePda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("request_queue"), marketSymbolBytes],
+      program.programId
+    );
+    [eventQueuePda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("event_queue"), marketSymbolBytes],
+      program.programId
+    );
     [positionPda] = PublicKey.findProgramAddressSync(
       [Buffer.from("position"), marketSymbolBytes, authority.publicKey.toBuffer()],
       program.programId
@@ -281,10 +316,10 @@ describe("PerpDex", () => {
   });
 
   describe("1. Bootstrap", () => {
-    it("initializes global config and queues", async () => {
+    it("initializes global config", async () => {
       await sendAndLog(() =>
         program.methods
-          .initaliseGlobalConfig(false, 3600)
+          .initaliseGlobalConfig(false)
           .accounts({
             authority: authority.publicKey,
             globalConfig: globalConfigPda,
@@ -292,8 +327,6 @@ describe("PerpDex", () => {
             vaultQuote: vaultQuotePda,
             insuranceFund: insuranceFundPda,
             feePool: feePoolAta,
-            requestQueue: requestQueuePda,
-            eventQueue: eventQueuePda,
             systemProgram: SystemProgram.programId,
             associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
             tokenProgram: TOKEN_PROGRAM_ID,
@@ -304,7 +337,6 @@ describe("PerpDex", () => {
       const config = await program.account.globalConfig.fetch(globalConfigPda);
       assert.equal(config.authority.toBase58(), authority.publicKey.toBase58());
       assert.equal(config.tradingPaused, false);
-      assert.equal(config.fundingIntervalSecs, 3600);
       assert.equal(config.vaultQuote.toBase58(), vaultQuotePda.toBase58());
       assert.equal(config.insuranceFund.toBase58(), insuranceFundPda.toBase58());
       assert.equal(config.feePool.toBase58(), feePoolAta.toBase58());
@@ -312,33 +344,46 @@ describe("PerpDex", () => {
       const vaultAcc = await getAccount(connection, vaultQuotePda);
       assert.equal(vaultAcc.mint.toBase58(), usdcMint.toBase58());
       assert.equal(vaultAcc.owner.toBase58(), globalConfigPda.toBase58());
-
-      const rqInfo = await connection.getAccountInfo(requestQueuePda);
-      const eqInfo = await connection.getAccountInfo(eventQueuePda);
-      assert.ok(rqInfo !== null);
-      assert.ok(eqInfo !== null);
     });
 
     it("initializes market", async () => {
       const now = Math.floor(Date.now() / 1000);
+      const rent = await connection.getMinimumBalanceForRentExemption(PRICE_ACCOUNT_LEN);
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.createAccount({
+            fromPubkey: authority.publicKey,
+            newAccountPubkey: oracle.publicKey,
+            lamports: rent,
+            space: PRICE_ACCOUNT_LEN,
+            programId: program.programId,
+          })
+        ),
+        [oracle]
+      );
       const params = {
-        oraclePubkey: authority.publicKey,
+        oraclePubkey: oracle.publicKey,
         lastOraclePrice: new anchor.BN(100_000_000),
         lastOracleTs: new anchor.BN(now),
         imBps: 1000,
         mmBps: 500,
-        oracleBandBps: 100,
+        // the tests quote far from the oracle price, so run without a band
+        oracleBandBps: 0,
         takerFeeBps: 10,
         makerRebateBps: 5,
         liqPenaltyBps: 500,
         liquidatorShareBps: 500,
-        maxFundingRate: new anchor.BN(1_000_000),
+        maxFundingRate: new anchor.BN(100),
         cumFunding: new anchor.BN(0),
         lastFundingTs: new anchor.BN(now),
         fundingIntervalSecs: 3600,
+        interestRateBps: 3,
+        premiumDampenerBps: 5,
+        fundingPeriodSecs: 8 * 3600,
         tickSize: 1,
         stepSize: 1,
-        minOrderNotional: new anchor.BN(10_000),
+        // limit orders in these tests sit far below the mark; min notional is checked at the limit price
+        minOrderNotional: new anchor.BN(1),
       };
 
       await sendAndLog(() =>
@@ -349,6 +394,8 @@ describe("PerpDex", () => {
             market: marketPda,
             bids: bidsPda,
             asks: asksPda,
+            requestQueue: requestQueuePda,
+            eventQueue: eventQueuePda,
             systemProgram: SystemProgram.programId,
             associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
             tokenProgram: TOKEN_PROGRAM_ID,
@@ -361,14 +408,21 @@ describe("PerpDex", () => {
       assert.equal(market.authority.toBase58(), authority.publicKey.toBase58());
       assert.equal(market.bid.toBase58(), bidsPda.toBase58());
       assert.equal(market.asks.toBase58(), asksPda.toBase58());
+      assert.equal(market.requestQueue.toBase58(), requestQueuePda.toBase58());
+      assert.equal(market.eventQueue.toBase58(), eventQueuePda.toBase58());
       assert.equal(market.imBps, 1000);
       assert.equal(market.mmBps, 500);
+      assert.equal(market.fundingPeriodSecs, 8 * 3600);
+      assert.equal(market.maxFundingRate.toNumber(), 100);
+
+      await publishOraclePrice(new anchor.BN(100_000_000));
+      marketInitialized = true;
     });
 
     it("fails to initialize global config twice", async () => {
       try {
         await program.methods
-          .initaliseGlobalConfig(false, 3600)
+          .initaliseGlobalConfig(false)
           .accounts({
             authority: authority.publicKey,
             globalConfig: globalConfigPda,
@@ -376,8 +430,6 @@ describe("PerpDex", () => {
             vaultQuote: vaultQuotePda,
             insuranceFund: insuranceFundPda,
             feePool: feePoolAta,
-            requestQueue: requestQueuePda,
-            eventQueue: eventQueuePda,
             systemProgram: SystemProgram.programId,
             associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
             tokenProgram: TOKEN_PROGRAM_ID,
@@ -388,6 +440,43 @@ describe("PerpDex", () => {
         // expected
       }
     });
+
+    it("updates funding params and rejects out-of-bounds values", async () => {
+      const funding = {
+        interestRateBps: 3,
+        premiumDampenerBps: 5,
+        fundingPeriodSecs: 8 * 3600,
+        fundingIntervalSecs: 1800,
+        maxFundingRate: new anchor.BN(100),
+      };
+      await sendAndLog(() =>
+        program.methods
+          .updateFundingParams(funding)
+          .accounts({ authority: authority.publicKey, market: marketPda } as any)
+          .rpc()
+      );
+      let market = await program.account.marketState.fetch(marketPda);
+      assert.equal(market.fundingIntervalSecs, 1800);
+
+      try {
+        await program.methods
+          .updateFundingParams({ ...funding, fundingIntervalSecs: 9 * 3600 })
+          .accounts({ authority: authority.publicKey, market: marketPda } as any)
+          .rpc();
+        assert.fail("expected an interval longer than the period to be rejected");
+      } catch (e: any) {
+        expect(e.message || e.toString()).to.match(/InvalidFundingParams/);
+      }
+
+      await sendAndLog(() =>
+        program.methods
+          .updateFundingParams({ ...funding, fundingIntervalSecs: 3600 })
+          .accounts({ authority: authority.publicKey, market: marketPda } as any)
+          .rpc()
+      );
+      market = await program.account.marketState.fetch(marketPda);
+      assert.equal(market.fundingIntervalSecs, 3600);
+    });
   });
 
   describe("2. Collateral", () => {
@@ -407,6 +496,7 @@ describe("PerpDex", () => {
             globalConfig: globalConfigPda,
             vaultQuote: vaultQuotePda,
             userColletral: userCollateralPda,
+            userStats: userStatsPda,
             systemProgram: SystemProgram.programId,
             associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
             tokenProgram: TOKEN_PROGRAM_ID,
@@ -424,6 +514,9 @@ describe("PerpDex", () => {
       const userColl = await program.account.userCollateral.fetch(userCollateralPda);
       assert.equal(userColl.owner.toBase58(), authority.publicKey.toBase58());
       assert.equal(userColl.collateralAmount.toString(), depositAmount.toString());
+
+      const stats = await program.account.userStats.fetch(userStatsPda);
+      assert.equal(stats.owner.toBase58(), authority.publicKey.toBase58());
     });
     it("rejects deposit of zero", async () => {
       try {
@@ -434,6 +527,7 @@ describe("PerpDex", () => {
           globalConfig: globalConfigPda,
           vaultQuote: vaultQuotePda,
           userColletral: userCollateralPda,
+          userStats: userStatsPda,
           systemProgram: SystemProgram.programId,
           associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
           tokenProgram: TOKEN_PROGRAM_ID,
@@ -452,6 +546,7 @@ describe("PerpDex", () => {
         globalConfig: globalConfigPda,
         vaultQuote: vaultQuotePda,
         userColletral: userCollateralPda,
+        userStats: userStatsPda,
         systemProgram: SystemProgram.programId,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         tokenProgram: TOKEN_PROGRAM_ID,
@@ -462,9 +557,9 @@ describe("PerpDex", () => {
   });
 
   describe("2b. Market & reset", () => {
-    it("set_mark_price updates market last oracle price", async () => {
+    it("set_mark_price publishes to the mock oracle and refreshes the market price", async () => {
       const newPrice = new anchor.BN(105_000_000);
-      await program.methods.setMarkPrice(newPrice).accounts({ market: marketPda } as any).rpc();
+      await publishOraclePrice(newPrice);
       const market = await program.account.marketState.fetch(marketPda);
       expect(market.lastOraclePrice.toString()).to.equal(newPrice.toString());
     });
@@ -472,12 +567,26 @@ describe("PerpDex", () => {
     it("reset_queues clears request and event queue counts", async () => {
       await program.methods
         .resetQueues()
-        .accounts({ requestQueue: requestQueuePda, eventQueue: eventQueuePda } as any)
+        .accounts({ authority: authority.publicKey, requestQueue: requestQueuePda, eventQueue: eventQueuePda, market: marketPda } as any)
         .rpc();
       expect(await getRequestQueueCount()).to.equal(0);
       expect(await getEventQueueCount()).to.equal(0);
     });
 
+    it("reset_queues rejects anyone but the market authority", async () => {
+      const stranger = Keypair.generate();
+      try {
+        await program.methods
+          .resetQueues()
+          .accounts({ authority: stranger.publicKey, requestQueue: requestQueuePda, eventQueue: eventQueuePda, market: marketPda } as any)
+          .signers([stranger])
+          .rpc();
+        assert.fail("expected Unauthorized");
+      } catch (e: any) {
+        expect(e.message || e.toString()).to.match(/Unauthorized/);
+      }
+    });
+
     it("reset_slab allows placing and cranking again", async () => {
       await resetOrderBookAndQueues();
       const order = buildOrder({ orderId: 99, side: "buy", qty: 2, limitPrice: 100 });
@@ -547,7 +656,7 @@ describe("PerpDex", () => {
       await sendAndLog(() =>
         program.methods
           .resetQueues()
-          .accounts({ requestQueue: requestQueuePda, eventQueue: eventQueuePda } as any)
+          .accounts({ authority: authority.publicKey, requestQueue: requestQueuePda, eventQueue: eventQueuePda, market: marketPda } as any)
           .rpc()
       );
 
@@ -574,7 +683,7 @@ describe("PerpDex", () => {
       await sendAndLog(() =>
         program.methods
           .resetQueues()
-          .accounts({ requestQueue: requestQueuePda, eventQueue: eventQueuePda } as any)
+          .accounts({ authority: authority.publicKey, requestQueue: requestQueuePda, eventQueue: eventQueuePda, market: marketPda } as any)
           .rpc()
       );
 
@@ -646,6 +755,9 @@ describe("PerpDex", () => {
       const position = await program.account.position.fetch(positionPda);
       expect(position.basePosition.toNumber()).to.equal(4);
       expect(position.entryPrice.toNumber()).to.equal(100);
+      // the margin account indexes the market so withdraw and liquidate see the position
+      const collateral = await program.account.userCollateral.fetch(userCollateralPda);
+      expect(collateral.openPositions.map((m: PublicKey) => m.toBase58())).to.include(marketPda.toBase58());
     });
   });
 
@@ -657,7 +769,7 @@ describe("PerpDex", () => {
       await sendAndLog(() =>
         program.methods
           .resetQueues()
-          .accounts({ requestQueue: requestQueuePda, eventQueue: eventQueuePda } as any)
+          .accounts({ authority: authority.publicKey, requestQueue: requestQueuePda, eventQueue: eventQueuePda, market: marketPda } as any)
           .rpc()
       );
 
@@ -750,6 +862,197 @@ describe("PerpDex", () => {
       expect(position.basePosition.toNumber()).to.equal(10);
       expect(position.entryPrice.toNumber()).to.be.greaterThan(0);
     });
+
+    it("settle_funding catches the position up to cum_funding", async () => {
+      await program.methods
+        .settleFunding(authority.publicKey)
+        .accounts({
+          market: marketPda,
+          userPosition: positionPda,
+          userCollateral: userCollateralPda,
+        } as any)
+        .rpc();
+      const market = await program.account.marketState.fetch(marketPda);
+      const position = await program.account.position.fetch(positionPda);
+      expect(position.lastCumFunding.toString()).to.equal(market.cumFunding.toString());
+
+      // no funding has accrued since, so settling for the same user again changes nothing
+      const before = await program.account.userCollateral.fetch(userCollateralPda);
+      await program.methods
+        .settleFunding(authority.publicKey)
+        .accounts({
+          market: marketPda,
+          userPosition: positionPda,
+          userCollateral: userCollateralPda,
+        } as any)
+        .rpc();
+      const after = await program.account.userCollateral.fetch(userCollateralPda);
+      expect(after.collateralAmount.toString()).to.equal(before.collateralAmount.toString());
+    });
+
+    it("sweep_fees moves the net fees of the fills above to the fee pool", async () => {
+      const market = await program.account.marketState.fetch(marketPda);
+      const net =
+        BigInt(market.fees.takerFees.toString()) -
+        BigInt(market.fees.makerRebates.toString()) -
+        BigInt(market.fees.swept.toString());
+      expect(net > BigInt(0)).to.equal(true);
+
+      const poolBefore = await getAccount(connection, feePoolAta);
+      await sendAndLog(() =>
+        program.methods
+          .sweepFees()
+          .accounts({
+            market: marketPda,
+            globalConfig: globalConfigPda,
+            vaultQuote: vaultQuotePda,
+            feePool: feePoolAta,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          } as any)
+          .rpc()
+      );
+      const poolAfter = await getAccount(connection, feePoolAta);
+      expect((BigInt(poolAfter.amount.toString()) - BigInt(poolBefore.amount.toString())).toString()).to.equal(
+        net.toString()
+      );
+      const swept = await program.account.marketState.fetch(marketPda);
+      expect(swept.fees.swept.toString()).to.equal(
+        (BigInt(market.fees.swept.toString()) + net).toString()
+      );
+    });
+
+    it("fills count towards the user's 30-day volume", async () => {
+      const stats = await program.account.userStats.fetch(userStatsPda);
+      const volume = [...stats.makerVolume, ...stats.takerVolume].reduce(
+        (sum: bigint, day: anchor.BN) => sum + BigInt(day.toString()),
+        BigInt(0)
+      );
+      expect(volume > BigInt(0)).to.equal(true);
+    });
+
+    it("set_fee_tiers replaces the tier table and rejects unordered tiers", async () => {
+      const tiers = [
+        { minVolume: new BN(0), takerFeeBps: 10, makerRebateBps: 2 },
+        { minVolume: new BN(1_000_000_000), takerFeeBps: 6, makerRebateBps: 3 },
+      ];
+      await program.methods
+        .setFeeTiers(tiers)
+        .accounts({ authority: authority.publicKey, globalConfig: globalConfigPda } as any)
+        .rpc();
+      const config = await program.account.globalConfig.fetch(globalConfigPda);
+      expect(config.feeTiers.map((t: any) => t.takerFeeBps)).to.deep.equal([10, 6]);
+
+      try {
+        await program.methods
+          .setFeeTiers([...tiers].reverse())
+          .accounts({ authority: authority.publicKey, globalConfig: globalConfigPda } as any)
+          .rpc();
+        assert.fail("expected InvalidFeeTiers");
+      } catch (e: any) {
+        expect(e.message || e.toString()).to.match(/InvalidFeeTiers/);
+      }
+
+      // back to each market's own fees for the tests below
+      await program.methods
+        .setFeeTiers([])
+        .accounts({ authority: authority.publicKey, globalConfig: globalConfigPda } as any)
+        .rpc();
+    });
+
+    it("isolated margin can only be moved on isolated positions", async () => {
+      const position = await program.account.position.fetch(positionPda);
+      expect(position.isIsolated).to.equal(false);
+      try {
+        await program.methods
+          .addIsolatedMargin(new BN(1_000_000))
+          .accounts({
+            user: authority.publicKey,
+            market: marketPda,
+            oracle: oracle.publicKey,
+            userPosition: positionPda,
+            userColletral: userCollateralPda,
+          } as any)
+          .rpc();
+        assert.fail("expected NotIsolated");
+      } catch (e: any) {
+        expect(e.message || e.toString()).to.match(/NotIsolated/);
+      }
+    });
+
+    it("set_referral_params rejects splits above the whole fee", async () => {
+      await program.methods
+        .setReferralParams(2_000, 1_000)
+        .accounts({ authority: authority.publicKey, globalConfig: globalConfigPda } as any)
+        .rpc();
+      const config = await program.account.globalConfig.fetch(globalConfigPda);
+      expect(config.referrerShareBps).to.equal(2_000);
+      expect(config.refereeDiscountBps).to.equal(1_000);
+
+      try {
+        await program.methods
+          .setReferralParams(9_000, 1_001)
+          .accounts({ authority: authority.publicKey, globalConfig: globalConfigPda } as any)
+          .rpc();
+        assert.fail("expected InvalidReferralParams");
+      } catch (e: any) {
+        expect(e.message || e.toString()).to.match(/InvalidReferralParams/);
+      }
+    });
+
+    it("set_referral_params keeps the discounted taker fee above every rebate", async () => {
+      await program.methods
+        .setFeeTiers([
+          { minVolume: new BN(0), takerFeeBps: 10, makerRebateBps: 2 },
+          { minVolume: new BN(1_000_000_000), takerFeeBps: 6, makerRebateBps: 5 },
+        ])
+        .accounts({ authority: authority.publicKey, globalConfig: globalConfigPda } as any)
+        .rpc();
+
+      // 6 bps less 20% leaves 4.8, below the 5 bps rebate
+      try {
+        await program.methods
+          .setReferralParams(0, 2_000)
+          .accounts({ authority: authority.publicKey, globalConfig: globalConfigPda } as any)
+          .rpc();
+        assert.fail("expected InvalidReferralParams");
+      } catch (e: any) {
+        expect(e.message || e.toString()).to.match(/InvalidReferralParams/);
+      }
+
+      await program.methods
+        .setFeeTiers([])
+        .accounts({ authority: authority.publicKey, globalConfig: globalConfigPda } as any)
+        .rpc();
+    });
+
+    it("a user cannot refer themselves or claim without rewards", async () => {
+      try {
+        await program.methods
+          .bindReferrer()
+          .accounts({ user: authority.publicKey, userStats: userStatsPda, referrerStats: userStatsPda } as any)
+          .rpc();
+        assert.fail("expected InvalidReferrer");
+      } catch (e: any) {
+        expect(e.message || e.toString()).to.match(/InvalidReferrer/);
+      }
+
+      try {
+        await program.methods
+          .claimReferralRewards()
+          .accounts({
+            user: authority.publicKey,
+            userStats: userStatsPda,
+            globalConfig: globalConfigPda,
+            vaultQuote: vaultQuotePda,
+            userAta: userUsdcAta,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          } as any)
+          .rpc();
+        assert.fail("expected NothingToClaim");
+      } catch (e: any) {
+        expect(e.message || e.toString()).to.match(/NothingToClaim/);
+      }
+    });
   });
 
   describe("5. Withdraw", () => {
@@ -768,6 +1071,7 @@ describe("PerpDex", () => {
           vaultQuote: vaultQuotePda,
           userAta: userUsdcAta,
           market: marketPda,
+          oracle: oracle.publicKey,
           userPosition: positionPda,
           associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
           systemProgram: SystemProgram.programId,
@@ -794,6 +1098,7 @@ describe("PerpDex", () => {
             vaultQuote: vaultQuotePda,
             userAta: userUsdcAta,
             market: marketPda,
+            oracle: oracle.publicKey,
             userPosition: positionPda,
             associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
             systemProgram: SystemProgram.programId,
@@ -818,6 +1123,7 @@ describe("PerpDex", () => {
             vaultQuote: vaultQuotePda,
             userAta: userUsdcAta,
             market: marketPda,
+            oracle: oracle.publicKey,
             userPosition: positionPda,
             associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
             systemProgram: SystemProgram.programId,